@@ -0,0 +1,47 @@
+//! Renew names with an update provider of this program, it is used in name configs by
+//! `update_provider_type = { type = "Custom", name = "print", options = { prefix = "set" } }`.
+//!
+//! cargo run --example custom_provider -- /etc/dns-renew/dns-renew.toml
+
+use std::net::IpAddr;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use dns_renew::{
+    init_config, registry, renew_all,
+    update::{RenewAction, RenewOutcome, UpdateProvider},
+    Args,
+};
+
+struct PrintUpdateProvider {
+    prefix: String,
+}
+
+#[async_trait]
+impl UpdateProvider for PrintUpdateProvider {
+    async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+        println!("{} {} {}", self.prefix, name, ip);
+        Ok(RenewOutcome::new(RenewAction::Update, None))
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    registry::register_update_provider("print", |options| {
+        Ok(Box::new(PrintUpdateProvider {
+            prefix: options["prefix"].as_str().unwrap_or("update").to_string(),
+        }))
+    });
+
+    let args = Args {
+        config: std::env::args()
+            .nth(1)
+            .unwrap_or_else(|| "/etc/dns-renew/dns-renew.toml".to_string())
+            .into(),
+        ..Default::default()
+    };
+    let config = init_config(&args)?;
+    let failures = renew_all(&args, &config).await?;
+    println!("{} name(s) failed", failures);
+    Ok(())
+}
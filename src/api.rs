@@ -0,0 +1,246 @@
+use std::{collections::BTreeMap, net::IpAddr, thread, time::Duration};
+
+use anyhow::{anyhow, bail, Result};
+use data_encoding::BASE64;
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tokio::runtime::Handle;
+
+use crate::{
+    config::{ApiConf, Config, NameConf, NameProvidersConf, RecordKind, UpdateCredential},
+    find_name_conf, init_query_provider, ip, renew_found,
+    update::{RecordDiff, RenewAction},
+    Args, FoundNameConf, RenewRequest,
+};
+
+/// A cycle of the daemon holds the lock of the state dir, an update waits for it.
+const WAIT_LOCK: Duration = Duration::from_secs(60);
+
+#[derive(Serialize)]
+struct RecordsResponse<'a> {
+    name: &'a str,
+    family: &'a str,
+    ips: Vec<IpAddr>,
+}
+
+#[derive(Serialize)]
+struct UpdateResponse<'a> {
+    name: &'a str,
+    /// `v4`, `v6` or `cname`.
+    family: &'a str,
+    /// It is `None` for a CNAME.
+    ip: Option<IpAddr>,
+    action: RenewAction,
+    diff: Option<RecordDiff>,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// An error with the http status code of it.
+struct ApiError(u16, anyhow::Error);
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(e: E) -> Self {
+        Self(500, e.into())
+    }
+}
+
+fn api_error(status: u16, message: String) -> ApiError {
+    ApiError(status, anyhow!(message))
+}
+
+/// Serve the http api in a background thread, it is a small ddns gateway backed by the providers
+/// of names. An update is renewed like in a cycle of the daemon, with its `args`.
+pub fn serve(args: Args, config: Config) -> Result<()> {
+    let Some(api_conf) = config.api() else {
+        return Ok(());
+    };
+    let expected_auth = expected_authorization(&config, api_conf)?;
+    let server = Server::http(api_conf.listen())
+        .map_err(|e| anyhow!("failed to listen on {}: {}", api_conf.listen(), e))?;
+    tracing::info!("api is served on {}", api_conf.listen());
+    // Requests are served one by one in the thread, providers are run on the shared runtime.
+    let runtime = Handle::current();
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let span = tracing::info_span!(
+                "api",
+                method = %request.method(),
+                url = request.url()
+            );
+            let _enter = span.enter();
+
+            let result = if is_authorized(&request, &expected_auth) {
+                runtime.block_on(handle(&args, &config, &request))
+            } else {
+                Err(api_error(401, "unauthorized".to_string()))
+            };
+            let response = match result {
+                Ok(body) => Response::from_data(body),
+                Err(ApiError(status, e)) => {
+                    tracing::warn!("api request failed: {:?}", e);
+                    let body = serde_json::to_vec(&ErrorResponse {
+                        error: format!("{:#}", e),
+                    })
+                    .unwrap_or_default();
+                    Response::from_data(body).with_status_code(status)
+                }
+            }
+            .with_header(
+                "Content-Type: application/json"
+                    .parse::<Header>()
+                    .expect("it is a valid header"),
+            );
+            if let Err(e) = request.respond(response) {
+                tracing::warn!("failed to respond: {:?}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// The expected value of the `Authorization` header, built from the credential of the api.
+fn expected_authorization(config: &Config, api_conf: &ApiConf) -> Result<String> {
+    let credential = config
+        .update_credentials()
+        .get(api_conf.credential())
+        .ok_or_else(|| anyhow!("Credential not found: {}", api_conf.credential()))?;
+    Ok(match credential {
+        UpdateCredential::HttpBearerToken { token } => format!("Bearer {}", token),
+        UpdateCredential::HttpBasicAuth(credential) => format!(
+            "Basic {}",
+            BASE64.encode(
+                format!(
+                    "{}:{}",
+                    credential.username(),
+                    credential.password().as_deref().unwrap_or_default()
+                )
+                .as_bytes()
+            )
+        ),
+        _ => bail!("Only HttpBasicAuth or HttpBearerToken credential is supported by api."),
+    })
+}
+
+fn is_authorized(request: &Request, expected: &str) -> bool {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .map(|h| h.value.as_str().as_bytes())
+        .is_some_and(|actual| {
+            actual.len() == expected.len() && openssl::memcmp::eq(actual, expected.as_bytes())
+        })
+}
+
+async fn handle(args: &Args, config: &Config, request: &Request) -> Result<Vec<u8>, ApiError> {
+    let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+    let params = form_urlencoded::parse(query.as_bytes()).collect::<BTreeMap<_, _>>();
+    let is_v6 = match params.get("family").map(|f| f.as_ref()).unwrap_or("v4") {
+        "v4" => false,
+        "v6" => true,
+        family => return Err(api_error(400, format!("invalid family: {}", family))),
+    };
+    let family = if is_v6 { "v6" } else { "v4" };
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+    match (request.method(), segments.as_slice()) {
+        (Method::Get, ["ip"]) => {
+            let ip_provider_type = config
+                .api()
+                .as_ref()
+                .and_then(|c| c.ip_provider_type().as_ref())
+                .ok_or_else(|| api_error(404, "no ip provider of api".to_string()))?;
+            let ip = ip::init_ip_provider(ip_provider_type, config)?
+                .query(is_v6)
+                .await?;
+            Ok(ip.to_string().into_bytes())
+        }
+        (Method::Get, ["records", name]) => {
+            let name_conf = find(config, name)?.name_conf;
+            let name_providers_conf = providers_conf(&name_conf, is_v6)?;
+            let query_provider = init_query_provider(
+                &name_conf,
+                name_providers_conf,
+                config,
+                &mut BTreeMap::new(),
+            )
+            .await?;
+            Ok(serde_json::to_vec(&RecordsResponse {
+                name,
+                family,
+                ips: query_provider.query(name, is_v6).await?,
+            })?)
+        }
+        (Method::Post, ["update", name]) => {
+            let found = find(config, name)?;
+            let name_providers_conf = providers_conf(&found.name_conf, is_v6)?;
+            // The ip is detected by the ip provider of the name, if it is not given.
+            let ip = match params.get("ip") {
+                Some(ip) => {
+                    let ip = ip
+                        .parse::<IpAddr>()
+                        .map_err(|e| api_error(400, format!("invalid ip {}: {}", ip, e)))?;
+                    if found.name_conf.record() == RecordKind::Cname {
+                        return Err(api_error(400, format!("{} is a CNAME", name)));
+                    }
+                    let ip = ip::check_family(
+                        ip,
+                        is_v6,
+                        name_providers_conf.unwrap_ipv4_in_ipv6(),
+                        name_providers_conf.nat64_prefix().as_ref(),
+                    )
+                    .map_err(|e| ApiError(400, e))?;
+                    Some(ip)
+                }
+                None => None,
+            };
+            // It is checked even if it is not due, and updated only if the record doesn't point
+            // to the ip.
+            let mut args = args.clone();
+            args.check_all = true;
+            args.request = Some(RenewRequest { is_v6, ip });
+            args.wait_lock = args.wait_lock.or(Some(WAIT_LOCK));
+            let report = renew_found(&args, config, &found)
+                .await?
+                .into_iter()
+                .find(|r| r.family == family || r.family == "cname")
+                .ok_or_else(|| anyhow!("{} of {} is not renewed", family, name))?;
+            tracing::info!(
+                "{} of {} is requested by api: {:?}",
+                report.action,
+                name,
+                report.ip
+            );
+            Ok(serde_json::to_vec(&UpdateResponse {
+                name,
+                family: report.family,
+                ip: report.ip,
+                action: report.action,
+                diff: report.diff,
+            })?)
+        }
+        _ => Err(api_error(404, "not found".to_string())),
+    }
+}
+
+/// Find the name config of a name, or one of its aliases.
+fn find(config: &Config, name: &str) -> Result<FoundNameConf, ApiError> {
+    find_name_conf(config, name)?
+        .ok_or_else(|| api_error(404, format!("name {} is not configured", name)))
+}
+
+fn providers_conf(name_conf: &NameConf, is_v6: bool) -> Result<&NameProvidersConf, ApiError> {
+    let (conf, other) = if is_v6 {
+        (name_conf.v6(), name_conf.v4())
+    } else {
+        (name_conf.v4(), name_conf.v6())
+    };
+    conf.as_ref()
+        .or_else(|| other.as_ref().filter(|_| name_conf.shared()))
+        .filter(|c| c.enabled())
+        .ok_or_else(|| api_error(404, "the family of the name is not enabled".to_string()))
+}
@@ -0,0 +1,81 @@
+//! The audit file of `audit_file`, a json line is appended for each change of a record sent to an
+//! update provider, whatever the log level is. Dry runs are never recorded.
+
+use std::{fs::OpenOptions, io::Write, time::SystemTime};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{
+    config::{Config, UpdateProviderType},
+    update::RenewAction,
+};
+
+/// A change of the records of a name in one family.
+#[derive(Serialize)]
+pub(crate) struct Change<'a> {
+    pub(crate) name: &'a str,
+    /// `v4`, `v6` or `cname`.
+    pub(crate) family: &'a str,
+    /// Ips or the CNAME target before the change, as seen by the query provider.
+    pub(crate) old: Vec<String>,
+    /// `None` if the records are deleted.
+    pub(crate) new: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Outcome {
+    Done(RenewAction),
+    Failed(&'static str),
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    time: String,
+    #[serde(flatten)]
+    change: &'a Change<'a>,
+    provider: String,
+    outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Record the result of an update provider, a failure of writing is only logged, so it won't
+/// fail the renew.
+pub(crate) fn record(
+    config: &Config,
+    change: &Change,
+    provider: &UpdateProviderType,
+    result: Result<RenewAction, &anyhow::Error>,
+) {
+    let Some(path) = config.audit_file() else {
+        return;
+    };
+    let (outcome, error) = match result {
+        Ok(action) => (Outcome::Done(action), None),
+        Err(e) => (Outcome::Failed("failed"), Some(format!("{:#}", e))),
+    };
+    let line = Line {
+        time: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        change,
+        provider: provider.describe(),
+        outcome,
+        error,
+    };
+    // The file is opened for each line, so it can be moved away, e.g. by logrotate.
+    let result = serde_json::to_vec(&line)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut line| {
+            line.push(b'\n');
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(&line))
+                .with_context(|| format!("failed to write to audit file {:?}", path))
+        });
+    if let Err(e) = result {
+        tracing::error!("failed to audit the change of {}: {:?}", change.name, e);
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    fmt::{Display, Formatter},
+    path::PathBuf,
+    process,
+};
+
+use anyhow::{Context, Result};
+use clap::{
+    error::ErrorKind, parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser,
+    Subcommand,
+};
+use tracing::Instrument;
+
+use crate::{
+    daemon, events,
+    healthcheck::{self, Ping},
+    http, init_config, init_log, metrics, prune, renew_all, status, suggest, systemd, tui,
+    validate, Args, NamesFailed, Tenant,
+};
+
+/// Exit codes, so cron or systemd can tell a partially failed run from a broken setup.
+const EXIT_ERROR: i32 = 1;
+const EXIT_CONFIG_ERROR: i32 = 2;
+const EXIT_NAMES_FAILED: i32 = 3;
+
+/// Marks an error of loading the config.
+#[derive(Debug)]
+struct ConfigError;
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid config")
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Renew dns records of names with the ip of this host.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// The path of config file.
+    #[arg(
+        short,
+        long,
+        global = true,
+        value_name = "PATH",
+        default_value = "/etc/dns-renew/dns-renew.toml"
+    )]
+    config: PathBuf,
+
+    /// Options of `run`, which is the default without a subcommand.
+    #[command(flatten)]
+    run: Args,
+
+    /// The same as `daemon`, it is kept for compatibility.
+    #[arg(long, hide = true, default_missing_value = "true")]
+    daemon: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Cli {
+    /// Arguments of renewing, and the command if it is not `run` or `daemon`.
+    fn resolve(self, matches: &ArgMatches) -> (Args, Option<Command>) {
+        // Options of `run` before a subcommand would be ignored.
+        if self.command.is_some()
+            && matches.ids().any(|id| {
+                id != "config"
+                    && matches.value_source(id.as_str()) == Some(ValueSource::CommandLine)
+            })
+        {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "options of renewing should be after `run` or `daemon`",
+                )
+                .exit();
+        }
+        let (mut args, command) = match self.command {
+            Some(Command::Run(args)) => (args, None),
+            Some(Command::Daemon(mut args)) => {
+                args.daemon = true;
+                (args, None)
+            }
+            command => {
+                let mut args = self.run;
+                args.daemon = self.daemon;
+                (args, command)
+            }
+        };
+        if args.daemon && (args.fail_fast || args.force) {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--fail-fast and --force can't be used in daemon mode",
+                )
+                .exit();
+        }
+        args.config = self.config;
+        (args, command)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Renew names which are due, and exit. It is the default without a subcommand.
+    Run(Args),
+    /// Keep running, and renew names whenever they are due.
+    Daemon(Args),
+    /// Print statuses of names, from the control socket of the daemon or state files.
+    Status {
+        /// Print a json array instead of lines.
+        #[arg(long, default_missing_value = "true")]
+        json: bool,
+    },
+    /// Manage states of names.
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+    /// Show a live dashboard of names, from the control socket of the daemon or state files.
+    Tui,
+    /// Generate a service and a timer of systemd from the config, as an alternative of the
+    /// daemon mode.
+    GenerateSystemd {
+        /// Write units into this directory, e.g. `/etc/systemd/system`, instead of stdout.
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+    },
+    /// Probe public ip services and resolvers from this host, and print the recommended
+    /// providers. The config file is optional.
+    Suggest {
+        /// Probe ipv6 instead of ipv4.
+        #[arg(long, default_missing_value = "true")]
+        v6: bool,
+    },
+    /// Check the config and all name configs without contacting any service, and print all
+    /// problems.
+    Validate,
+}
+
+#[derive(Subcommand, Debug)]
+enum StateCommand {
+    /// Remove states of names whose config no longer exists, and print them.
+    Prune {
+        /// Delete records of the names by providers kept in their states, it requires
+        /// `delete_on_disable` of the names.
+        #[arg(long, default_missing_value = "true")]
+        delete_records: bool,
+        /// Only print states which would be removed.
+        #[arg(long, default_missing_value = "true")]
+        dry_run: bool,
+    },
+}
+
+async fn run(args: Args, command: Option<Command>) -> Result<()> {
+    if let Some(Command::Suggest { v6 }) = command {
+        // It is for setting up, a config may not exist yet.
+        return suggest::run(&init_config(&args).unwrap_or_default(), v6).await;
+    }
+    let config = init_config(&args).context(ConfigError)?;
+
+    match &command {
+        Some(Command::Status { json }) => return status::print(&config, *json),
+        // Logs would mess up the terminal.
+        Some(Command::Tui) => return tui::run(&config),
+        Some(Command::GenerateSystemd { output_dir }) => {
+            let (tenants, tenant_errors) = Tenant::init_all(&args, config);
+            if let Some(e) = tenant_errors.into_iter().next() {
+                return Err(e.context(ConfigError));
+            }
+            return systemd::generate(&args, &tenants, output_dir.as_deref());
+        }
+        Some(Command::Validate) => {
+            return validate::run(&args, &config).await.context(ConfigError);
+        }
+        Some(
+            Command::Run(_) | Command::Daemon(_) | Command::State { .. } | Command::Suggest { .. },
+        )
+        | None => {}
+    }
+
+    init_log(&args, &config).context(ConfigError)?;
+    http::init(&config).context(ConfigError)?;
+    events::init(args.events_fd, args.events_file.as_deref())?;
+
+    let (tenants, tenant_errors) = Tenant::init_all(&args, config);
+    if let Some(Command::State {
+        command: StateCommand::Prune {
+            delete_records,
+            dry_run,
+        },
+    }) = command
+    {
+        if let Some(e) = tenant_errors.into_iter().next() {
+            return Err(e.context(ConfigError));
+        }
+        let args = Args { dry_run, ..args };
+        return prune::run(&args, &tenants, delete_records).await;
+    }
+    if args.daemon {
+        daemon::run(&args, tenants).await
+    } else {
+        // Options of the run itself are read from the main config.
+        let main_config = &tenants[0].config;
+        if !args.dry_run {
+            healthcheck::ping(main_config, Ping::Start, String::new()).await;
+        }
+        let mut failures = 0;
+        // A failed tenant doesn't stop the others, the first error is returned at last. A tenant
+        // skipped for its invalid config fails the run as a config error.
+        let mut error = tenant_errors
+            .into_iter()
+            .next()
+            .map(|e| e.context(ConfigError));
+        for tenant in &tenants {
+            let span = tenant.span();
+            let result =
+                metrics::with_tenant(tenant.name.clone(), renew_all(&args, &tenant.config))
+                    .instrument(span.clone())
+                    .await;
+            let _enter = span.enter();
+            match result {
+                Ok(tenant_failures) => failures += tenant_failures,
+                Err(e) => {
+                    tracing::error!("failed to renew names: {:?}", e);
+                    error.get_or_insert(e);
+                }
+            }
+            if args.fail_fast && (failures > 0 || error.is_some()) {
+                break;
+            }
+        }
+        let mut statuses = vec![];
+        for tenant in &tenants {
+            match status::from_states(&tenant.config, tenant.name.as_deref()) {
+                Ok(tenant_statuses) => statuses.extend(tenant_statuses),
+                Err(e) => {
+                    tracing::error!("failed to read statuses: {:?}", e);
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        if !args.dry_run {
+            let result = match &error {
+                Some(e) => Err(e),
+                None => Ok(statuses.as_slice()),
+            };
+            healthcheck::finish(main_config, result).await;
+        }
+        if let Some(path) = main_config.metrics().textfile() {
+            metrics::write_textfile(path, &statuses)?;
+        }
+        if let Some(e) = error {
+            return Err(e);
+        }
+        if failures > 0 {
+            return Err(NamesFailed(failures).into());
+        }
+        Ok(())
+    }
+}
+
+/// Parse arguments and run the command, the process exits with an exit code if it fails.
+pub async fn main() {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let (args, command) = cli.resolve(&matches);
+    if let Err(e) = run(args, command).await {
+        eprintln!("run command failed: {e:#}");
+        let code = if e.downcast_ref::<NamesFailed>().is_some() {
+            EXIT_NAMES_FAILED
+        } else if e.downcast_ref::<ConfigError>().is_some() {
+            EXIT_CONFIG_ERROR
+        } else {
+            EXIT_ERROR
+        };
+        process::exit(code);
+    }
+}
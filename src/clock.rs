@@ -0,0 +1,70 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// A step of the wall clock larger than this is treated as a clock jump, e.g. NTP stepping the
+/// clock of a router without RTC after boot.
+const JUMP_THRESHOLD: Duration = Duration::from_secs(60);
+
+/// The epoch of the wall clock, it is increased whenever a clock jump is detected. A `next` of a
+/// `NameState` which is scheduled in an older epoch can't be trusted.
+static EPOCH: AtomicU64 = AtomicU64::new(0);
+
+pub fn epoch() -> u64 {
+    EPOCH.load(Ordering::SeqCst)
+}
+
+/// Keep the epoch monotonic across restarts, by catching up with the one in a state file.
+pub fn observe(epoch: u64) {
+    EPOCH.fetch_max(epoch, Ordering::SeqCst);
+}
+
+/// Compare the elapsed wall time with the elapsed monotonic time to detect clock jumps.
+pub struct JumpDetector {
+    instant: Instant,
+    wall: SystemTime,
+}
+
+impl JumpDetector {
+    pub fn new() -> Self {
+        Self {
+            instant: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+
+    /// Return the new epoch if the wall clock has jumped since the last check.
+    pub fn check(&mut self) -> Option<u64> {
+        let instant = Instant::now();
+        let wall = SystemTime::now();
+        let monotonic_elapsed = instant.duration_since(self.instant);
+        let (wall_elapsed, backward) = match wall.duration_since(self.wall) {
+            Ok(d) => (d, false),
+            Err(e) => (e.duration(), true),
+        };
+        self.instant = instant;
+        self.wall = wall;
+
+        let drift = if backward {
+            wall_elapsed + monotonic_elapsed
+        } else {
+            wall_elapsed.abs_diff(monotonic_elapsed)
+        };
+        if drift <= JUMP_THRESHOLD {
+            return None;
+        }
+        let epoch = EPOCH.fetch_add(1, Ordering::SeqCst) + 1;
+        tracing::warn!(
+            "wall clock jumped {} by {:?}, move to epoch {}",
+            if backward || wall_elapsed < monotonic_elapsed {
+                "backward"
+            } else {
+                "forward"
+            },
+            drift,
+            epoch
+        );
+        Some(epoch)
+    }
+}
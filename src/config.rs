@@ -1,9 +1,22 @@
-use std::{collections::HashMap, net::IpAddr, path::PathBuf, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
 
-use getset::{CopyGetters, Getters};
+use anyhow::{bail, Context, Result};
+use figment::{
+    providers::{Format, Toml},
+    Figment,
+};
+use getset::{CopyGetters, Getters, MutGetters, Setters};
+use hickory_proto::rr::dnssec::rdata::tsig::TsigAlgorithm;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Getters)]
+use crate::clock;
+
+#[derive(Default, Deserialize, CopyGetters, Getters)]
 pub struct Config {
     #[getset(get = "pub")]
     name_conf_dir: PathBuf,
@@ -11,18 +24,348 @@ pub struct Config {
     #[getset(get = "pub")]
     name_state_dir: PathBuf,
 
+    /// An age key file, generated by `age-keygen`. State files are encrypted with it, if it is
+    /// set.
+    #[getset(get = "pub")]
+    state_key_file: Option<PathBuf>,
+
+    /// Where states are kept, a file for each name in `name_state_dir` by default.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    state_backend: StateBackend,
+
+    /// The database of the sqlite state backend, default to `states.sqlite` in `name_state_dir`.
+    #[getset(get = "pub")]
+    state_db: Option<PathBuf>,
+
+    /// The document of the single state backend, default to `states.toml` in `name_state_dir`,
+    /// it is json if its extension is `json`.
+    #[getset(get = "pub")]
+    state_file: Option<PathBuf>,
+
     #[getset(get = "pub")]
     log_timestamp: Option<bool>,
 
+    /// Default to `text`, it can be overridden by `--log-format`.
+    #[getset(get_copy = "pub")]
+    log_format: Option<LogFormat>,
+
+    /// Write logs to a rotated file too, e.g. on routers without journald.
     #[getset(get = "pub")]
+    log_file: Option<LogFileConf>,
+
+    #[getset(get = "pub")]
+    #[serde(default)]
     update_credentials: HashMap<String, UpdateCredential>,
+
+    /// A toml file of extra credentials, each table in it is a credential. They will override
+    /// the ones with the same name in `update_credentials`.
+    #[getset(get = "pub")]
+    update_credentials_file: Option<PathBuf>,
+
+    /// A json line is appended to it for each change sent to an update provider, with its
+    /// outcome, for finding out when a record was changed.
+    #[getset(get = "pub")]
+    audit_file: Option<PathBuf>,
+
+    /// Where executables of `Plugin` providers are, default to `/usr/lib/dns-renew/plugins`.
+    #[getset(get = "pub")]
+    plugin_dir: Option<PathBuf>,
+
+    /// The max number of http requests to the same host in flight at the same time.
+    #[getset(get_copy = "pub")]
+    max_connections_per_host: Option<usize>,
+
+    /// The max number of names renewed at the same time.
+    #[getset(get_copy = "pub")]
+    max_concurrency: Option<usize>,
+
+    /// Experimental, discover names to renew from a signed txt record.
+    #[getset(get = "pub")]
+    discovery: Option<DiscoveryConf>,
+
+    /// Independent config roots, e.g. one for each customer, keyed by the tenant name. Each of
+    /// them is a config file with its own name config dir, state dir and credentials.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    tenants: BTreeMap<String, PathBuf>,
+
+    /// A unix socket where the daemon serves statuses of names, e.g. for `dns-renew tui`.
+    #[getset(get = "pub")]
+    control_socket: Option<PathBuf>,
+
+    /// How often the daemon checks if any name is due to renew.
+    #[getset(get_copy = "pub")]
+    #[serde(default, with = "humantime_serde")]
+    daemon_interval: Option<Duration>,
+    /// Check all names right after a global address or the default route of the host is changed,
+    /// in daemon mode. Default to true, it is only supported on linux.
+    #[getset(get_copy = "pub")]
+    watch_network: Option<bool>,
+
+    #[getset(get = "pub")]
+    #[serde(default)]
+    notification: NotificationConf,
+
+    /// Defaults of all http requests, e.g. of providers, notifiers and healthchecks.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    http: HttpConf,
+
+    /// An http api served in daemon mode, it turns dns-renew into a small ddns gateway.
+    #[getset(get = "pub")]
+    api: Option<ApiConf>,
+
+    /// Prometheus metrics of renews.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    metrics: MetricsConf,
+
+    /// A dead man's switch pinged for each run, e.g. of healthchecks.io.
+    #[getset(get = "pub")]
+    healthcheck: Option<HealthcheckConf>,
+}
+
+impl Config {
+    /// Load credentials from `update_credentials_file`, and merge them into `update_credentials`.
+    pub fn load_update_credentials_file(&mut self) -> Result<()> {
+        if let Some(path) = &self.update_credentials_file {
+            let credentials = Figment::new()
+                .merge(Toml::file(path))
+                .extract::<HashMap<String, UpdateCredential>>()
+                .with_context(|| format!("failed to read from credentials file: {:?}", path))?;
+            self.update_credentials.extend(credentials);
+        }
+        Ok(())
+    }
+
+    pub fn set_update_credentials(&mut self, credentials: HashMap<String, UpdateCredential>) {
+        self.update_credentials = credentials;
+    }
+}
+
+#[derive(Deserialize, CopyGetters, Getters)]
+pub struct DiscoveryConf {
+    /// The name of the txt record, e.g. `_dnsrenew.config.example.com`.
+    #[getset(get = "pub")]
+    name: String,
+    #[getset(get = "pub")]
+    name_server_host: String,
+    #[getset(get = "pub")]
+    name_server_port: Option<u16>,
+    #[getset(get_copy = "pub")]
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+    /// The base64 encoded ed25519 public key to verify the signature of the list.
+    #[getset(get = "pub")]
+    public_key: String,
+    /// The name config file used to renew discovered names, its `name` is replaced. It should be
+    /// placed outside of `name_conf_dir`.
+    #[getset(get = "pub")]
+    template: PathBuf,
+}
+
+#[derive(Deserialize, Getters)]
+pub struct ApiConf {
+    /// e.g. `127.0.0.1:8053`.
+    #[getset(get = "pub")]
+    listen: SocketAddr,
+    /// A HttpBearerToken or HttpBasicAuth credential which requests should carry.
+    #[getset(get = "pub")]
+    credential: String,
+    /// The ip provider of `GET /ip`.
+    #[getset(get = "pub")]
+    ip_provider_type: Option<IpProviderType>,
+}
+
+/// A command run around an update, it gets `DNS_RENEW_NAME`, `DNS_RENEW_FAMILY`,
+/// `DNS_RENEW_OLD_IP` (ips separated by `,`) and `DNS_RENEW_NEW_IP` in env.
+#[derive(Clone, Deserialize, CopyGetters, Getters)]
+pub struct HookConf {
+    /// The program and its args.
+    #[getset(get = "pub")]
+    command: Vec<String>,
+    /// A failure of the hook fails the renew of the family, instead of being only logged.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    fatal: bool,
+    /// Default to 1m.
+    #[getset(get_copy = "pub")]
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+}
+
+#[derive(Deserialize, CopyGetters, Getters)]
+pub struct LogFileConf {
+    /// e.g. `/var/log/dns-renew.log`, rotated files are beside it.
+    #[getset(get = "pub")]
+    path: PathBuf,
+    /// Rotate the file when it would grow over this size, in bytes. Rotated files are
+    /// `<path>.1`, `<path>.2`, ... It can't be used with `rotation`.
+    #[getset(get_copy = "pub")]
+    max_size: Option<u64>,
+    /// Rotate the file by time, default to `daily` if `max_size` is not set. Rotated files have
+    /// the date in their names, e.g. `dns-renew.2024-06-01.log`.
+    #[getset(get_copy = "pub")]
+    rotation: Option<LogRotation>,
+    /// How many files are kept, including the current one, default to 5.
+    #[getset(get_copy = "pub")]
+    max_files: Option<usize>,
+    /// Keep writing logs to stdout as well, default to true.
+    #[getset(get_copy = "pub")]
+    stdout: Option<bool>,
+}
+
+#[derive(Default, Deserialize, CopyGetters, Getters)]
+pub struct HttpConf {
+    /// The timeout of a whole request, default to 30s. Some providers have their own `timeout`,
+    /// which overrides it.
+    #[getset(get_copy = "pub")]
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+    /// Default to `dns-renew/<version>`.
+    #[getset(get = "pub")]
+    user_agent: Option<String>,
+    /// e.g. `http://proxy.lan:3128` or `socks5h://127.0.0.1:9050`, proxies of environment
+    /// variables, e.g. `HTTPS_PROXY`, are used if it is not set. It is overridden by `proxy` of a
+    /// provider.
+    #[getset(get = "pub")]
+    proxy: Option<String>,
+    /// Extra root certificates in a PEM file, e.g. of a private CA, which are trusted besides the
+    /// ones of the system.
+    #[getset(get = "pub")]
+    ca_file: Option<PathBuf>,
+    /// How many times a request is sent again after a connection error, a timeout, or a status
+    /// of 429, 502, 503 or 504, default to 0.
+    #[getset(get_copy = "pub")]
+    retries: Option<u32>,
+    /// The wait before sending a request again, default to 1s.
+    #[getset(get_copy = "pub")]
+    #[serde(default, with = "humantime_serde")]
+    retry_interval: Option<Duration>,
+}
+
+/// Settings of the http client of a provider, which override the ones of `[http]`.
+#[derive(Clone, Debug, Default, Deserialize, Getters, Setters)]
+pub struct HttpClientConf {
+    /// A proxy of the provider, e.g. `http://proxy.lan:3128`, or an empty string to send requests
+    /// directly.
+    #[getset(get = "pub", set = "pub")]
+    proxy: Option<String>,
+    /// Extra root certificates of the provider in a PEM file, e.g. of a self-hosted endpoint,
+    /// which are trusted besides the one of `[http]`.
+    #[getset(get = "pub")]
+    ca_file: Option<PathBuf>,
+}
+
+#[derive(Default, Deserialize, Getters)]
+pub struct MetricsConf {
+    /// Serve metrics on `http://<listen>/metrics` in daemon mode, e.g. `127.0.0.1:9153`.
+    #[getset(get = "pub")]
+    listen: Option<SocketAddr>,
+    /// Write metrics to this file after each run, e.g. for the textfile collector of
+    /// node_exporter.
+    #[getset(get = "pub")]
+    textfile: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Getters)]
+pub struct HealthcheckConf {
+    /// e.g. `https://hc-ping.com/<uuid>`, it is pinged when a run succeeds, `/start` is appended
+    /// when a run starts, and `/fail` when it fails or any name is failing.
+    #[getset(get = "pub")]
+    url: String,
+}
+
+#[derive(Default, Deserialize, Getters)]
+pub struct NotificationConf {
+    /// Routes of names, the first one whose tags are all in the tags of a name is used. Names
+    /// matching no route are only logged.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    routes: Vec<RouteConf>,
+    /// Notifiers referenced by routes, keyed by name.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    notifiers: HashMap<String, NotifierConf>,
+}
+
+#[derive(Deserialize, CopyGetters, Getters)]
+pub struct RouteConf {
+    /// An empty list matches all names.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    tags: Vec<String>,
+    /// The notifier to send events to, events are only logged if it is not set.
+    #[getset(get = "pub")]
+    notifier: Option<String>,
+    /// The priority of messages, its meaning depends on the notifier, e.g. 1-5 in ntfy.
+    #[getset(get_copy = "pub")]
+    priority: Option<u8>,
+    /// Kinds of events sent to the notifier, all of them if it is not set.
+    #[getset(get = "pub")]
+    events: Option<Vec<NotifyEventKind>>,
+    /// Labels attached to logs of events and series of metrics of the matched names, e.g.
+    /// `{ tier = "prod" }`, they can't be `tenant`, `name`, `family` or `provider`.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum NotifyEventKind {
+    /// A record is changed.
+    Changed,
+    Failed,
+    /// A name is renewed after consecutive failures.
+    Recovered,
+    /// A record is changed by someone else.
+    ExternalChange,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum NotifierConf {
+    /// Publish to a topic of ntfy, e.g. `https://ntfy.sh/my-topic`.
+    Ntfy {
+        url: String,
+        credential: Option<String>,
+    },
+    /// Push a message to a self-hosted gotify server, e.g. `https://gotify.example.com`, the token
+    /// of the application is an ApiKey credential.
+    Gotify { url: String, credential: String },
+    /// Post to an incoming webhook of slack, e.g. `https://hooks.slack.com/services/...`.
+    Slack { url: String },
+    /// Post to a webhook of a discord channel, e.g. `https://discord.com/api/webhooks/...`.
+    Discord { url: String },
+    /// Send a message to a chat by a telegram bot, the token of the bot is an ApiKey credential.
+    Telegram {
+        credential: String,
+        /// e.g. `"123456789"`, or `"@my_channel"`.
+        chat_id: String,
+        /// Default to `https://api.telegram.org`.
+        api_url: Option<String>,
+    },
 }
 
 #[derive(Clone, Deserialize)]
 #[serde(tag = "type")]
 pub enum UpdateCredential {
     HttpBasicAuth(HttpBasicAuthCredential),
-    HttpBearerToken { token: String },
+    HttpBearerToken {
+        token: String,
+    },
+    Inwx(InwxCredential),
+    ApiKey {
+        key: String,
+    },
+    TransIp(TransIpCredential),
+    Tsig(TsigCredential),
+    /// The legacy global api key of cloudflare, with the email of the account.
+    CloudflareGlobalKey {
+        email: String,
+        key: String,
+    },
 }
 
 #[derive(Clone, Deserialize, Getters)]
@@ -33,13 +376,80 @@ pub struct HttpBasicAuthCredential {
     password: Option<String>,
 }
 
-#[derive(Deserialize, CopyGetters, Getters)]
-pub struct NameConf {
+#[derive(Clone, Deserialize, Getters)]
+pub struct InwxCredential {
+    #[getset(get = "pub")]
+    username: String,
+    #[getset(get = "pub")]
+    password: String,
+    /// The base32 secret of the 2fa, it is required if 2fa of the account is enabled.
+    #[getset(get = "pub")]
+    totp_secret: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Getters)]
+pub struct TransIpCredential {
+    #[getset(get = "pub")]
+    login: String,
+    /// The pem file of the private key generated in the control panel of transip.
+    #[getset(get = "pub")]
+    private_key_file: PathBuf,
+}
+
+/// A request of `GenericRest`. Variables like `{name}` and `{ip}` can be used in `url` and any
+/// string of `body`.
+#[derive(Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct RestRequestConf {
+    url: String,
+    method: Option<String>,
+    body: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Deserialize, Getters)]
+pub struct TsigCredential {
+    #[getset(get = "pub")]
+    key_name: String,
+    /// One of `hmac-sha256`, `hmac-sha384` and `hmac-sha512`.
+    algorithm: String,
+    /// The base64 encoded secret, e.g. the one generated by `tsig-keygen`.
     #[getset(get = "pub")]
+    secret: String,
+}
+
+impl TsigCredential {
+    pub fn algorithm(&self) -> Result<TsigAlgorithm> {
+        Ok(match self.algorithm.to_lowercase().as_str() {
+            "hmac-sha256" => TsigAlgorithm::HmacSha256,
+            "hmac-sha384" => TsigAlgorithm::HmacSha384,
+            "hmac-sha512" => TsigAlgorithm::HmacSha512,
+            _ => bail!("Unsupport tsig algorithm: {}", self.algorithm),
+        })
+    }
+}
+
+#[derive(Deserialize, CopyGetters, Getters, Setters)]
+pub struct NameConf {
+    #[getset(get = "pub", set = "pub")]
     name: String,
+    /// Other names renewed with the same providers, e.g. `vpn.example.com` or
+    /// `*.lab.example.com`. Each of them has its own state.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    aliases: Vec<String>,
     #[getset(get = "pub")]
     #[serde(with = "humantime_serde")]
     renew_interval: Duration,
+    /// The interval of the next attempt after a failure, it is doubled for each consecutive
+    /// failure, up to `renew_interval`. It is retried after `renew_interval` if it is not set.
+    #[getset(get_copy = "pub")]
+    #[serde(default, with = "humantime_serde")]
+    retry_interval: Option<Duration>,
+    /// Check a family every `min(renew_interval, ttl)` with the ttl of its records from the query
+    /// provider, so a record with a low ttl is checked more often than a static one.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    schedule_by_ttl: bool,
     /// use config of v4/v6, if v6/v4 is not set.
     #[getset(get_copy = "pub")]
     shared: bool,
@@ -47,27 +457,165 @@ pub struct NameConf {
     v4: Option<NameProvidersConf>,
     #[getset(get = "pub")]
     v6: Option<NameProvidersConf>,
+    /// Records which are created when a record of this name is created for the first time.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    companion_records: Vec<CompanionRecordConf>,
+    /// What to do if the rrset is changed by someone else since the last renew.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    on_external_change: ExternalChangePolicy,
+    /// Tags used to route notifications and label metrics, e.g. `critical`.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Delete the A/AAAA records, when a family is disabled, or the config is removed.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    delete_on_disable: bool,
+    /// A command run before the record of a family is updated.
+    #[getset(get = "pub")]
+    pre_update: Option<HookConf>,
+    /// A command run after the record of a family is changed.
+    #[getset(get = "pub")]
+    post_update: Option<HookConf>,
+    /// With `CNAME`, the name points to `cname_target` instead of the detected ip.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    record: RecordKind,
+    /// The ttl of records in seconds, it is used if the update provider doesn't set one, and by
+    /// `{ttl}` in templates, which is 300 by default.
+    #[getset(get_copy = "pub")]
+    ttl: Option<u32>,
+    /// The zone of the name, e.g. `example.co.uk`, it is guessed by the last two labels of the
+    /// name if it is not set. It is used by `{zone}` and `{subdomain}` in templates.
+    #[getset(get = "pub")]
+    zone: Option<String>,
+    #[getset(get = "pub")]
+    cname_target: Option<String>,
+    /// The engine of templates in update providers of this name.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    template_engine: TemplateEngine,
+    /// How a change of the ip is detected.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    detection: ChangeDetection,
+}
+
+#[derive(Deserialize, Getters)]
+pub struct CompanionRecordConf {
+    /// A template of the name by `template_engine`, `{name}` is the name of the created record.
+    /// Default to the name.
+    #[getset(get = "pub")]
+    name: Option<String>,
+    #[getset(get = "pub")]
+    record_type: String,
+    /// A template of the content, variables of the created record can be used, e.g. `{name}` and
+    /// `{ip}`.
+    #[getset(get = "pub")]
+    content: String,
+    #[getset(get = "pub")]
+    ttl: Option<u32>,
+    /// Structured data of the record, e.g. `{ flags = 0, tag = "issue", value = "letsencrypt.org" }`
+    /// of a CAA record in cloudflare.
+    #[getset(get = "pub")]
+    data: Option<serde_json::Value>,
 }
 
 #[derive(Deserialize, CopyGetters, Getters)]
 #[serde(tag = "type")]
 pub struct NameProvidersConf {
     #[getset(get = "pub")]
-    update_provider_type: UpdateProviderType,
+    update_provider_type: UpdateProviderTypes,
     #[getset(get = "pub")]
     query_provider_type: QueryProviderType,
     #[getset(get = "pub")]
     ip_provider_type: IpProviderType,
     #[getset(get_copy = "pub")]
     enabled: bool,
+    /// Unwrap an ipv4 address which is mapped or synthesized by NAT64 in an ipv6 address, if it
+    /// is returned by the ip provider in a v4 query.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    unwrap_ipv4_in_ipv6: bool,
+    /// The network-specific /96 prefix of NAT64, the well-known `64:ff9b::/96` is always checked.
+    #[getset(get = "pub")]
+    nat64_prefix: Option<Ipv6Addr>,
+    /// Reject an ip from the ip provider if it isn't reachable from the internet, e.g. private,
+    /// link-local, CGNAT, ULA or documentation ranges, instead of publishing it.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    reject_non_global: bool,
+    /// Prefixes accepted even if `reject_non_global` is set, e.g. `10.0.0.0/8` for a lan zone.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    allowed_prefixes: Vec<String>,
+    /// Only update the record after a new ip is observed in this many consecutive renews, to
+    /// avoid flapping by a transient ip during reconnects.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    confirmations: u32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum LocalResolver {
+    /// Records are written as `address=/<name>/<ip>`.
+    Dnsmasq,
+    /// Records are written as `local-data: "<name>. <ttl> IN <type> <ip>"`.
+    Unbound,
+}
+
+/// One update provider, or a chain of them, e.g. `[[v4.update_provider_type]]`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum UpdateProviderTypes {
+    One(Box<UpdateProviderType>),
+    Chain(Vec<UpdateProviderType>),
+}
+
+impl UpdateProviderTypes {
+    pub fn as_slice(&self) -> &[UpdateProviderType] {
+        match self {
+            UpdateProviderTypes::One(t) => std::slice::from_ref(t.as_ref()),
+            UpdateProviderTypes::Chain(ts) => ts,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 pub enum UpdateProviderType {
+    /// A provider registered by a program embedding the renew engine, see `registry`.
+    Custom {
+        name: String,
+        /// Passed to the factory of the provider.
+        #[serde(default)]
+        options: serde_json::Value,
+    },
+    /// An executable in `plugin_dir`, it gets a json request from stdin, and writes a json
+    /// response to stdout.
+    Plugin {
+        plugin: String,
+        /// Passed to the plugin in every request.
+        #[serde(default)]
+        options: serde_json::Value,
+        /// Default to 30s.
+        #[serde(default, with = "humantime_serde")]
+        timeout: Option<Duration>,
+    },
     HttpGet {
         credential: Option<String>,
         url_template: String,
+        /// The update fails if the response body doesn't match it.
+        success_body_regex: Option<String>,
+        /// The update fails if the response body matches it, e.g. `^(badauth|nohost)`.
+        failure_body_regex: Option<String>,
+        /// A rhai script which shapes the request and checks the response, e.g. to sign the
+        /// request.
+        script: Option<PathBuf>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
     },
     HttpPlainBody {
         credential: Option<String>,
@@ -75,17 +623,196 @@ pub enum UpdateProviderType {
         method: String,
         content_type: String,
         body_template: String,
+        success_body_regex: Option<String>,
+        failure_body_regex: Option<String>,
+        /// See `script` of `HttpGet`.
+        script: Option<PathBuf>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
+    },
+    /// Send a json body, and check the response, since many endpoints return 200 with an error.
+    HttpJsonBody {
+        credential: Option<String>,
+        url: String,
+        /// Default to `POST`.
+        method: Option<String>,
+        /// Variables like `{name}` and `{ip}` can be used in any string of it.
+        body: serde_json::Value,
+        /// A json pointer of the response, e.g. `/status`. The update fails if it doesn't exist.
+        success_pointer: Option<String>,
+        /// The expected value at `success_pointer`, any value is accepted if it is not set.
+        success_value: Option<serde_json::Value>,
+        /// See `script` of `HttpGet`.
+        script: Option<PathBuf>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
+    },
+    /// Query the record, then update it if found, or create it.
+    GenericRest {
+        credential: Option<String>,
+        /// Default to `GET`.
+        query: RestRequestConf,
+        /// Default to `POST`.
+        create: RestRequestConf,
+        /// Default to `PUT`, `{id}` of the found record can be used.
+        update: RestRequestConf,
+        /// A json pointer of records in the query response, e.g. `/result`. Default to the whole
+        /// response. It can be an array or a single record.
+        records_pointer: Option<String>,
+        /// A json pointer of the id in a record, e.g. `/id`.
+        id_pointer: String,
+        /// A json pointer of the content in a record, e.g. `/content`. Only records with an ip of
+        /// the same family are matched.
+        content_pointer: String,
+        /// It is run for each of the query, create and update requests, see `script` of
+        /// `HttpGet`.
+        script: Option<PathBuf>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
     },
     Cloudflare {
         credential: String,
-        zone_id: String,
+        /// Either `zone_id` or `zone` should be set.
+        zone_id: Option<String>,
+        /// The name of the zone, e.g. `example.com`. Its id is resolved once, and cached in the
+        /// state file.
+        zone: Option<String>,
         proxied: Option<bool>,
         ttl: Option<u32>,
         comment: Option<String>,
+        /// For a name with multiple records, e.g. round-robin, only the record with this comment
+        /// is updated, it is created if not found. It overrides `comment`.
+        managed_comment: Option<String>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
+    },
+    Inwx {
+        credential: String,
+        /// The domain which the name belongs to.
+        domain: String,
+        ttl: Option<u32>,
+        /// Default to the production api, the one of OTE can be used to test.
+        api_url: Option<String>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
+    },
+    /// The dynamic dns of dns.he.net, the credential is the key of the record.
+    HurricaneElectric {
+        credential: String,
+        url: Option<String>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
+    },
+    TransIp {
+        credential: String,
+        /// The domain which the name belongs to.
+        domain: String,
+        ttl: Option<u32>,
+        api_url: Option<String>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
+    },
+    /// The http api of PowerDNS Authoritative Server, authenticated by an ApiKey credential.
+    PowerDns {
+        credential: String,
+        /// The base url of the api, e.g. `http://127.0.0.1:8081`.
+        api_url: String,
+        /// Default to `localhost`.
+        server_id: Option<String>,
+        zone: String,
+        ttl: Option<u32>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
+    },
+    /// Rewrite a config snippet of a local resolver, and reload it, so the ip is also published
+    /// internally.
+    LocalZone {
+        resolver: LocalResolver,
+        /// A file which only contains records managed by dns-renew, e.g. one included by
+        /// `conf-dir` of dnsmasq or `include` of unbound.
+        file: PathBuf,
+        /// Only used by unbound.
+        ttl: Option<u32>,
+        /// e.g. `["unbound-control", "reload"]`, nothing is run if it is empty.
+        #[serde(default)]
+        reload_command: Vec<String>,
+    },
+    /// Rewrite A/AAAA records of the name in a zone file of BIND style, increase the serial of
+    /// its SOA record, and reload the server.
+    ZoneFile {
+        file: PathBuf,
+        /// The origin of the zone, e.g. `example.com`.
+        zone: String,
+        ttl: Option<u32>,
+        /// e.g. `["rndc", "reload", "example.com"]`, nothing is run if it is empty.
+        #[serde(default)]
+        reload_command: Vec<String>,
+    },
+    /// Run a program to update the record. Variables of templates are passed by env, e.g.
+    /// `DNS_RENEW_NAME` and `DNS_RENEW_IP`, and can be used in args, e.g. `{name}` and `{ip}`.
+    /// A non-zero exit code is a failure, except
+    /// `unchanged_exit_code`.
+    Exec {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+        /// The exit code meaning the record is not changed, default to 100.
+        unchanged_exit_code: Option<i32>,
+    },
+    /// Static dns entries of a MikroTik router, through the rest api of RouterOS v7.
+    Mikrotik {
+        /// A HttpBasicAuth credential of a router user.
+        credential: String,
+        /// e.g. `https://192.168.88.1`.
+        url: String,
+        /// In the format of RouterOS, e.g. `5m`.
+        ttl: Option<String>,
+        /// Accept a self-signed certificate of the router.
+        accept_invalid_certs: Option<bool>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
+    },
+    /// Dynamic update of RFC 2136 to an authoritative server, signed by tsig if a credential is
+    /// set.
+    Rfc2136 {
+        credential: Option<String>,
+        name_server_host: String,
+        name_server_port: Option<u16>,
+        #[serde(default, with = "humantime_serde")]
+        timeout: Option<Duration>,
+        use_tcp: Option<bool>,
+        zone: String,
+        ttl: Option<u32>,
     },
 }
 
-#[derive(Deserialize)]
+impl UpdateProviderType {
+    /// The type of the provider, with the name of a custom provider or a plugin, e.g.
+    /// `Plugin(netcup)`.
+    pub fn describe(&self) -> String {
+        let kind = match self {
+            Self::Custom { name, .. } => return format!("Custom({})", name),
+            Self::Plugin { plugin, .. } => return format!("Plugin({})", plugin),
+            Self::HttpGet { .. } => "HttpGet",
+            Self::HttpPlainBody { .. } => "HttpPlainBody",
+            Self::HttpJsonBody { .. } => "HttpJsonBody",
+            Self::GenericRest { .. } => "GenericRest",
+            Self::Cloudflare { .. } => "Cloudflare",
+            Self::Inwx { .. } => "Inwx",
+            Self::HurricaneElectric { .. } => "HurricaneElectric",
+            Self::TransIp { .. } => "TransIp",
+            Self::PowerDns { .. } => "PowerDns",
+            Self::LocalZone { .. } => "LocalZone",
+            Self::ZoneFile { .. } => "ZoneFile",
+            Self::Exec { .. } => "Exec",
+            Self::Mikrotik { .. } => "Mikrotik",
+            Self::Rfc2136 { .. } => "Rfc2136",
+        };
+        kind.to_string()
+    }
+}
+
+#[derive(Debug, Deserialize)]
 #[serde(tag = "type")]
 pub enum QueryProviderType {
     /// For domains with waf enabled, there is no need to query its ips.
@@ -94,12 +821,104 @@ pub enum QueryProviderType {
     DohGoogle(DohGoogleQueryParams),
     DohIetf(DohIetfQueryParams),
     Dot(DotQueryParams),
+    /// Query an authoritative server of the zone, found by NS records, so a stale answer cached by
+    /// recursive resolvers doesn't hide the real record.
+    Authoritative(AuthoritativeQueryParams),
+    /// Ask the first update provider of the name for the content of the record, instead of a dns
+    /// lookup, so it isn't fooled by propagation delay right after an update.
+    Provider,
+    /// A provider registered by a program embedding the renew engine, see `registry`.
+    Custom {
+        name: String,
+        /// Passed to the factory of the provider.
+        #[serde(default)]
+        options: serde_json::Value,
+    },
+    /// An executable in `plugin_dir`, it gets a json request from stdin, and writes a json
+    /// response to stdout.
+    Plugin {
+        plugin: String,
+        /// Passed to the plugin in every request.
+        #[serde(default)]
+        options: serde_json::Value,
+        /// Default to 30s.
+        #[serde(default, with = "humantime_serde")]
+        timeout: Option<Duration>,
+    },
 }
 
-#[derive(Deserialize, CopyGetters, Getters)]
+impl QueryProviderType {
+    pub fn retry(&self) -> Option<&QueryRetryConf> {
+        match self {
+            QueryProviderType::Dummy
+            | QueryProviderType::Provider
+            | QueryProviderType::Custom { .. }
+            | QueryProviderType::Plugin { .. } => None,
+            QueryProviderType::Dns(params) => Some(params.retry()),
+            QueryProviderType::DohGoogle(params) => Some(params.retry()),
+            QueryProviderType::DohIetf(params) => Some(params.retry()),
+            QueryProviderType::Dot(params) => Some(params.retry()),
+            QueryProviderType::Authoritative(params) => Some(params.retry()),
+        }
+    }
+}
+
+/// EDNS0 of queries, it is only used if one of the options is set.
+#[derive(Debug, Clone, Default, Deserialize, Getters)]
+pub struct EdnsConf {
+    /// The advertised udp payload size, default to 1232.
+    #[getset(get = "pub")]
+    udp_payload_size: Option<u16>,
+    /// The EDNS client subnet, e.g. `203.0.113.0/24`.
+    #[getset(get = "pub")]
+    client_subnet: Option<String>,
+}
+
+/// The local source of queries, e.g. to send them out of a specific WAN link of a multi-homed
+/// host.
+#[derive(Debug, Clone, Default, Deserialize, Getters)]
+pub struct BindConf {
+    #[getset(get = "pub")]
+    bind_address: Option<IpAddr>,
+    /// An address of the interface in the family of the query is used, only on linux.
+    #[getset(get = "pub")]
+    bind_interface: Option<String>,
+}
+
+/// Retries of a failed query, e.g. a udp packet is lost.
+#[derive(Debug, Default, Deserialize, CopyGetters)]
+pub struct QueryRetryConf {
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    retries: u32,
+    /// The wait before the first retry, it is doubled for every retry, default to 1s.
+    #[getset(get_copy = "pub")]
+    #[serde(default, with = "humantime_serde")]
+    retry_backoff: Option<Duration>,
+}
+
+/// One name server, or a list of them tried in order, e.g. `name_server_host = ["223.5.5.5", "1.1.1.1"]`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum NameServerHosts {
+    One(String),
+    List(Vec<String>),
+}
+
+impl NameServerHosts {
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            NameServerHosts::One(host) => std::slice::from_ref(host),
+            NameServerHosts::List(hosts) => hosts,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, CopyGetters, Getters)]
 pub struct DnsQueryParams {
+    /// A host, or a list of hosts tried in order.
     #[getset(get = "pub")]
-    name_server_host: String,
+    name_server_host: NameServerHosts,
     #[getset(get = "pub")]
     name_server_port: Option<u16>,
     #[getset(get_copy = "pub")]
@@ -107,9 +926,21 @@ pub struct DnsQueryParams {
     timeout: Option<Duration>,
     #[getset(get_copy = "pub")]
     use_tcp: Option<bool>,
+    /// A socks5 proxy, e.g. `127.0.0.1:9050`, only over tcp.
+    #[getset(get = "pub")]
+    socks5_proxy: Option<String>,
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    edns: EdnsConf,
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    bind: BindConf,
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    retry: QueryRetryConf,
 }
 
-#[derive(Deserialize, CopyGetters, Getters)]
+#[derive(Debug, Deserialize, CopyGetters, Getters)]
 pub struct DohGoogleQueryParams {
     #[getset(get = "pub")]
     url: String,
@@ -118,31 +949,110 @@ pub struct DohGoogleQueryParams {
     #[getset(get_copy = "pub")]
     #[serde(default, with = "humantime_serde")]
     timeout: Option<Duration>,
+    /// A socks5 proxy, e.g. `127.0.0.1:9050`.
+    #[getset(get = "pub")]
+    socks5_proxy: Option<String>,
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    retry: QueryRetryConf,
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    http_client: HttpClientConf,
 }
 
-#[derive(Deserialize, CopyGetters, Getters)]
+#[derive(Debug, Deserialize, CopyGetters, Getters)]
 pub struct DohIetfQueryParams {
     #[getset(get = "pub")]
     url: String,
+    /// Send queries by GET with a base64url-encoded `dns` parameter, instead of POST, which is
+    /// more cache-friendly, and required by some endpoints.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    use_get: bool,
+    /// Send queries over HTTP/3, it can't be used with `socks5_proxy`.
+    #[getset(get_copy = "pub")]
+    #[serde(default)]
+    use_http3: bool,
     #[getset(get_copy = "pub")]
     #[serde(default, with = "humantime_serde")]
     timeout: Option<Duration>,
+    /// A socks5 proxy, e.g. `127.0.0.1:9050`.
+    #[getset(get = "pub")]
+    socks5_proxy: Option<String>,
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    retry: QueryRetryConf,
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    http_client: HttpClientConf,
 }
 
-#[derive(Deserialize, CopyGetters, Getters)]
-pub struct DotQueryParams {
+#[derive(Debug, Deserialize, CopyGetters, Getters)]
+pub struct AuthoritativeQueryParams {
+    /// The recursive resolver to look up NS records of the zone.
     #[getset(get = "pub")]
     name_server_host: String,
     #[getset(get = "pub")]
     name_server_port: Option<u16>,
+    /// The zone of names, it is found by the closest parent with NS records if it is not set.
+    #[getset(get = "pub")]
+    zone: Option<String>,
     #[getset(get_copy = "pub")]
     #[serde(default, with = "humantime_serde")]
     timeout: Option<Duration>,
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    bind: BindConf,
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    retry: QueryRetryConf,
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Deserialize, CopyGetters, Getters)]
+pub struct DotQueryParams {
+    /// A host, or a list of hosts tried in order.
+    #[getset(get = "pub")]
+    name_server_host: NameServerHosts,
+    #[getset(get = "pub")]
+    name_server_port: Option<u16>,
+    #[getset(get_copy = "pub")]
+    #[serde(default, with = "humantime_serde")]
+    timeout: Option<Duration>,
+    /// A socks5 proxy, e.g. `127.0.0.1:9050`.
+    #[getset(get = "pub")]
+    socks5_proxy: Option<String>,
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    edns: EdnsConf,
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    bind: BindConf,
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    retry: QueryRetryConf,
+}
+
+#[derive(Debug, Deserialize)]
 #[serde(tag = "type")]
 pub enum IpProviderType {
+    /// A provider registered by a program embedding the renew engine, see `registry`.
+    Custom {
+        name: String,
+        /// Passed to the factory of the provider.
+        #[serde(default)]
+        options: serde_json::Value,
+    },
+    /// An executable in `plugin_dir`, it gets a json request from stdin, and writes a json
+    /// response to stdout.
+    Plugin {
+        plugin: String,
+        /// Passed to the plugin in every request.
+        #[serde(default)]
+        options: serde_json::Value,
+        /// Default to 30s.
+        #[serde(default, with = "humantime_serde")]
+        timeout: Option<Duration>,
+    },
     Static {
         ip: IpAddr,
     },
@@ -150,6 +1060,28 @@ pub enum IpProviderType {
         url: String,
         #[serde(default, with = "humantime_serde")]
         timeout: Option<Duration>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
+    },
+    /// An ip in a json response, e.g. of `https://api.ipify.org?format=json`.
+    HttpJson {
+        url: String,
+        /// The json pointer of the ip, e.g. `/ip`.
+        pointer: String,
+        #[serde(default, with = "humantime_serde")]
+        timeout: Option<Duration>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
+    },
+    /// An ip in a response of html or plain text, e.g. a status page of a router.
+    HttpRegex {
+        url: String,
+        /// The ip is the first capture group, or the whole match if there is no group.
+        regex: String,
+        #[serde(default, with = "humantime_serde")]
+        timeout: Option<Duration>,
+        #[serde(flatten)]
+        http_client: HttpClientConf,
     },
     SslipIo {
         name_server_host: String,
@@ -158,14 +1090,301 @@ pub enum IpProviderType {
         #[serde(default, with = "humantime_serde")]
         timeout: Option<Duration>,
     },
+    /// The A/AAAA record of `myip.opendns.com` by the resolver of OpenDNS, which is the address
+    /// of the client.
+    OpenDns {
+        /// Default to `resolver1.opendns.com`.
+        name_server_host: Option<String>,
+        name_server_port: Option<u16>,
+        /// Default to `myip.opendns.com`.
+        name: Option<String>,
+        #[serde(default, with = "humantime_serde")]
+        timeout: Option<Duration>,
+    },
+    /// The TXT record of `whoami.cloudflare` in class CH, by the resolver of Cloudflare.
+    CloudflareWhoami {
+        /// Default to `1.1.1.1` in v4, and `2606:4700:4700::1111` in v6.
+        name_server_host: Option<String>,
+        name_server_port: Option<u16>,
+        #[serde(default, with = "humantime_serde")]
+        timeout: Option<Duration>,
+    },
+    /// The mapped address of a STUN binding request, RFC 5389.
+    Stun {
+        /// The host and port of the server, the port defaults to 3478, e.g. `stun.l.google.com:19302`.
+        server: String,
+        #[serde(default, with = "humantime_serde")]
+        timeout: Option<Duration>,
+    },
+    /// Addresses of a local interface, read by netlink, only supported on linux.
+    Interface {
+        /// The name of the interface, e.g. `eth0`, addresses of all interfaces if it is not set.
+        interface: Option<String>,
+        /// Only addresses in this prefix, e.g. `2001:db8:1234::/48`.
+        prefix: Option<String>,
+        /// Never use temporary (privacy) addresses, instead of only preferring others.
+        #[serde(default)]
+        exclude_temporary: bool,
+        /// Use deprecated addresses, whose preferred lifetime is over.
+        #[serde(default)]
+        include_deprecated: bool,
+    },
+    /// Try providers in order, the first ip returned is used.
+    Fallback {
+        providers: Vec<IpProviderType>,
+        /// Query all providers, and log a warning if they disagree, the first ip is still used.
+        #[serde(default)]
+        check_disagreement: bool,
+    },
+    /// An address in a lease file of a DHCP client, or of a ppp link, without any network call.
+    LeaseFile {
+        format: LeaseFileFormat,
+        /// The interface of leases, it is required by `Networkd`, and defaults to `ppp0` for `Ppp`.
+        interface: Option<String>,
+        /// The path of the file, the default one of the format is used if it is not set.
+        path: Option<String>,
+    },
+    /// The ipv6 address of another host in the same network, whose prefix is replaced by the one
+    /// of the ip from `provider`, for devices which can't renew their records.
+    Derived {
+        provider: Box<IpProviderType>,
+        /// A full address of the host, only bits after the prefix are used, e.g. `::1234:5678`.
+        address: Ipv6Addr,
+        #[serde(default = "default_prefix_length")]
+        prefix_length: u8,
+    },
+    /// Query providers in order, until an ip is returned by `min_agreement` of them, a failed
+    /// provider is skipped.
+    Multi {
+        providers: Vec<IpProviderType>,
+        #[serde(default = "default_min_agreement")]
+        min_agreement: usize,
+    },
+}
+
+fn default_prefix_length() -> u8 {
+    64
+}
+
+fn default_min_agreement() -> usize {
+    1
 }
 
-#[derive(Deserialize, Serialize, CopyGetters, Getters)]
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum LeaseFileFormat {
+    /// Leases of ISC dhclient, `/var/lib/dhcp/dhclient.leases` or `dhclient6.leases` in v6.
+    Dhclient,
+    /// Leases of systemd-networkd, `/run/systemd/netif/leases/<ifindex>`, v4 only.
+    Networkd,
+    /// The pid file of pppd, `/var/run/<interface>.pid`, the address of the interface is used.
+    Ppp,
+}
+
+#[derive(Clone, Deserialize, Serialize, CopyGetters, Getters, MutGetters, Setters)]
 pub struct NameState {
     #[getset(get = "pub")]
     name: String,
+    /// When the name is renewed next, it is the earliest `next` of its families.
+    #[getset(get_copy = "pub", set = "pub")]
+    next: u64,
+    /// The unix timestamp of the last successful renew.
+    #[getset(get_copy = "pub", set = "pub")]
+    #[serde(default)]
+    last_renew: Option<u64>,
+    /// The ips known to be in records of renewed families, they are from `applied_ips`.
+    #[getset(get = "pub", set = "pub")]
+    #[serde(default)]
+    ips: Vec<IpAddr>,
+    /// The error of the last renew, it is cleared after a successful renew.
+    #[getset(get = "pub", set = "pub")]
+    #[serde(default)]
+    last_error: Option<String>,
+    /// The number of consecutive failed renews, it is reset after a successful renew.
+    #[getset(get_copy = "pub", set = "pub")]
+    #[serde(default)]
+    failures: u32,
+    /// The clock epoch which `next` is scheduled in, see `clock::epoch`.
+    #[getset(get_copy = "pub", set = "pub")]
+    #[serde(default)]
+    epoch: u64,
+    /// Checksums of rrsets seen in the last renew, keyed by `v4` or `v6`.
+    #[getset(get = "pub", get_mut = "pub")]
+    #[serde(default)]
+    rrset_checksums: BTreeMap<String, RrsetChecksums>,
+    /// A new ip waiting for `confirmations`, keyed by `v4` or `v6`.
+    #[getset(get = "pub", get_mut = "pub")]
+    #[serde(default)]
+    pending_ips: BTreeMap<String, PendingIp>,
+    /// The ip known to be in the record, keyed by `v4` or `v6`, it is compared with the detected
+    /// ip with `detection = "State"`.
+    #[getset(get = "pub", get_mut = "pub")]
+    #[serde(default)]
+    applied_ips: BTreeMap<String, IpAddr>,
+    /// States of families renewed independently, keyed by `v4` or `v6`.
+    #[getset(get = "pub", get_mut = "pub")]
+    #[serde(default)]
+    families: BTreeMap<String, FamilyState>,
+    /// Zone ids resolved by update providers, keyed by the zone name.
+    #[getset(get = "pub", get_mut = "pub")]
+    #[serde(default)]
+    zone_ids: BTreeMap<String, String>,
+    /// The source of the name config with `delete_on_disable`, records can still be deleted by it
+    /// after the config is removed.
+    #[getset(get = "pub", set = "pub")]
+    #[serde(default)]
+    conf: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateBackend {
+    /// A toml file for each name in `name_state_dir`.
+    #[default]
+    Files,
+    /// All names in one sqlite database, each save is a transaction, so a state is never
+    /// half-written.
+    Sqlite,
+    /// All names in one toml or json document, which is replaced atomically for each save.
+    Single,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// A json object for each line, with fields of spans, e.g. `name` of the renewed name, so logs
+    /// can be ingested without parsing text.
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum TemplateEngine {
+    /// Variables are substituted by `{name}`.
+    #[default]
+    Strfmt,
+    /// Templates of minijinja, with conditionals and filters, e.g.
+    /// `{% if is_v6 %}{{ name|lower }}{% endif %}`.
+    Jinja,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum RecordKind {
+    /// A and AAAA records of the detected ips.
+    #[default]
+    #[serde(rename = "A")]
+    Address,
+    #[serde(rename = "CNAME")]
+    Cname,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum ChangeDetection {
+    /// Compare the detected ip with records returned by the query provider.
+    #[default]
+    Query,
+    /// Compare the detected ip with the last applied one in the state file, the query provider is
+    /// not used, so a change made by someone else is not detected.
+    State,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum ExternalChangePolicy {
+    /// Update the record to the detected ip anyway.
+    #[default]
+    Reconcile,
+    /// Leave the record, until it is changed again.
+    Respect,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize, Getters, Setters)]
+pub struct RrsetChecksums {
+    /// Checksums of rrsets written or seen by dns-renew.
+    #[getset(get = "pub", set = "pub")]
+    known: Vec<String>,
+    /// The checksum of an rrset changed by someone else, which is respected.
+    #[getset(get = "pub", set = "pub")]
+    respected: Option<String>,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, CopyGetters, Setters)]
+pub struct PendingIp {
     #[getset(get_copy = "pub")]
+    ip: IpAddr,
+    /// How many consecutive renews it is observed in.
+    #[getset(get_copy = "pub", set = "pub")]
+    count: u32,
+}
+
+/// The state of a family of a name, a family is only renewed when its own `next` is due, so a
+/// failed family doesn't hold back the other one.
+#[derive(Clone, Default, Deserialize, Serialize, CopyGetters, Getters, Setters)]
+pub struct FamilyState {
+    #[getset(get_copy = "pub", set = "pub")]
     next: u64,
+    #[getset(get_copy = "pub", set = "pub")]
+    #[serde(default)]
+    last_renew: Option<u64>,
+    /// The error of the last renew of the family, it is cleared after a successful renew.
+    #[getset(get = "pub", set = "pub")]
+    #[serde(default)]
+    last_error: Option<String>,
+    /// The number of consecutive failed renews of the family.
+    #[getset(get_copy = "pub", set = "pub")]
+    #[serde(default)]
+    failures: u32,
+    /// The unix timestamp of the last change of the record.
+    #[getset(get_copy = "pub", set = "pub")]
+    #[serde(default)]
+    last_change: Option<u64>,
+    /// The unix timestamp when the record is created for the first time, rather than updated.
+    #[getset(get_copy = "pub", set = "pub")]
+    #[serde(default)]
+    created_at: Option<u64>,
+    /// Ips pushed to the record, the latest is the last one, it is bounded by `HISTORY_SIZE`.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    history: Vec<AppliedIp>,
+}
+
+/// An ip pushed to the record of a family.
+#[derive(Clone, Copy, Deserialize, Serialize, CopyGetters)]
+pub struct AppliedIp {
+    #[getset(get_copy = "pub")]
+    ip: IpAddr,
+    /// The unix timestamp when it is pushed.
+    #[getset(get_copy = "pub")]
+    at: u64,
+}
+
+impl FamilyState {
+    /// The max number of ips kept in `history`.
+    pub const HISTORY_SIZE: usize = 16;
+
+    /// Record a change of the record, the oldest ip in the history is dropped if it is full.
+    pub fn record_change(&mut self, ip: IpAddr, at: u64) {
+        self.last_change = Some(at);
+        self.history.push(AppliedIp { ip, at });
+        if self.history.len() > Self::HISTORY_SIZE {
+            self.history
+                .drain(..self.history.len() - Self::HISTORY_SIZE);
+        }
+    }
+}
+
+impl PendingIp {
+    pub fn new(ip: IpAddr) -> Self {
+        Self { ip, count: 1 }
+    }
 }
 
 impl NameState {
@@ -173,6 +1392,46 @@ impl NameState {
         Self {
             name: name.to_string(),
             next,
+            epoch: clock::epoch(),
+            rrset_checksums: BTreeMap::new(),
+            pending_ips: BTreeMap::new(),
+            applied_ips: BTreeMap::new(),
+            families: BTreeMap::new(),
+            zone_ids: BTreeMap::new(),
+            last_renew: None,
+            ips: vec![],
+            last_error: None,
+            failures: 0,
+            conf: None,
+        }
+    }
+
+    /// Take the state of a family, which is put back by `restore_family` if renewing the family
+    /// fails, so a failed renew doesn't advance it.
+    pub fn snapshot_family(&self, family: &str) -> FamilySnapshot {
+        FamilySnapshot {
+            rrset_checksums: self.rrset_checksums.get(family).cloned(),
+            pending_ip: self.pending_ips.get(family).copied(),
+            applied_ip: self.applied_ips.get(family).copied(),
         }
     }
+
+    pub fn restore_family(&mut self, family: &str, snapshot: FamilySnapshot) {
+        fn restore<T>(map: &mut BTreeMap<String, T>, family: &str, value: Option<T>) {
+            match value {
+                Some(value) => map.insert(family.to_string(), value),
+                None => map.remove(family),
+            };
+        }
+        restore(&mut self.rrset_checksums, family, snapshot.rrset_checksums);
+        restore(&mut self.pending_ips, family, snapshot.pending_ip);
+        restore(&mut self.applied_ips, family, snapshot.applied_ip);
+    }
+}
+
+/// The state of a family in a `NameState`, see `NameState::snapshot_family`.
+pub struct FamilySnapshot {
+    rrset_checksums: Option<RrsetChecksums>,
+    pending_ip: Option<PendingIp>,
+    applied_ip: Option<IpAddr>,
 }
@@ -0,0 +1,373 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{bail, Result};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+use tracing::Instrument;
+
+use crate::{
+    api,
+    clock::JumpDetector,
+    config::Config,
+    healthcheck::{self, Ping},
+    http, init_config, metrics, query, renew_all, sd_notify, shortest_renew_interval,
+    status::{self, SharedStatuses},
+    validate, Args, Tenant,
+};
+
+const DEFAULT_DAEMON_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for more changes of name configs, an editor may write a file several times.
+const NAME_CONF_SETTLE: Duration = Duration::from_secs(1);
+
+/// How long to wait for more changes of the network, e.g. an address and a default route are
+/// added one by one in a reconnect.
+const NETWORK_SETTLE: Duration = Duration::from_secs(2);
+
+/// What wakes the daemon up from sleeping between checks.
+enum Wake {
+    Tick,
+    HangUp,
+    /// Paths of name configs which are changed.
+    NameConfChanged(Vec<PathBuf>),
+    NetworkChanged,
+}
+
+/// Watch the files which credentials are read from, a change in them is detected by mtime.
+struct CredentialWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl CredentialWatcher {
+    fn new(config_path: &Path, config: &Config) -> Self {
+        let mut paths = vec![config_path.to_path_buf()];
+        if let Some(path) = config.update_credentials_file() {
+            paths.push(path.clone());
+        }
+        Self {
+            files: paths
+                .into_iter()
+                .map(|p| {
+                    let mtime = Self::mtime(&p);
+                    (p, mtime)
+                })
+                .collect(),
+        }
+    }
+
+    fn mtime(path: &Path) -> Option<SystemTime> {
+        path.metadata().and_then(|m| m.modified()).ok()
+    }
+
+    fn is_changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, last_mtime) in self.files.iter_mut() {
+            let mtime = Self::mtime(path);
+            if mtime != *last_mtime {
+                tracing::debug!("{:?} is changed", path);
+                *last_mtime = mtime;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Re-read credentials of a tenant if any of the files is changed. The old credentials are
+    /// kept, if the new ones can't be read.
+    fn reload_if_changed(&mut self, args: &Args, tenant: &mut Tenant) {
+        if !self.is_changed() {
+            return;
+        }
+        match tenant.reload_config(args) {
+            Ok(new_config) => {
+                if new_config.update_credentials_file() != tenant.config.update_credentials_file() {
+                    *self = Self::new(&tenant.config_path, &new_config);
+                }
+                tenant
+                    .config
+                    .set_update_credentials(new_config.update_credentials().clone());
+                tracing::info!("update credentials are reloaded");
+            }
+            Err(e) => tracing::error!("failed to reload update credentials: {:?}", e),
+        }
+    }
+}
+
+/// Watch name_conf_dir of all tenants, a path of a created, changed or removed name config is sent
+/// to `changes`. Names are only checked every `daemon_interval`, if it can't be watched.
+fn watch_name_conf_dirs(
+    tenants: &[Tenant],
+    changes: UnboundedSender<PathBuf>,
+) -> Option<RecommendedWatcher> {
+    let watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event)
+                if matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                ) =>
+            {
+                for path in event.paths {
+                    if path.extension().is_some_and(|ext| ext == "toml") {
+                        let _ = changes.send(path);
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("failed to watch name configs: {:?}", e),
+        });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::warn!("failed to watch name configs: {:?}", e);
+            return None;
+        }
+    };
+    for tenant in tenants {
+        let dir = tenant.config.name_conf_dir();
+        if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            tracing::warn!("failed to watch {:?}: {:?}", dir, e);
+        }
+    }
+    Some(watcher)
+}
+
+/// Wait for more changes to settle, then drain them, so they cause only one check.
+async fn settle_changes<T: PartialEq>(
+    changes: &mut UnboundedReceiver<T>,
+    first: T,
+    settle: Duration,
+) -> Vec<T> {
+    tokio::time::sleep(settle).await;
+    let mut all = vec![first];
+    while let Ok(change) = changes.try_recv() {
+        if !all.contains(&change) {
+            all.push(change);
+        }
+    }
+    all
+}
+
+#[cfg(target_os = "linux")]
+fn watch_network(changes: UnboundedSender<String>) {
+    if let Err(e) = crate::netwatch::watch(changes) {
+        tracing::warn!("failed to watch the network: {:?}", e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_network(_changes: UnboundedSender<String>) {
+    tracing::warn!("watching the network is only supported on linux");
+}
+
+/// The shortest renew interval of all names, it is only computed again after a reload or a change
+/// of name configs, since all name configs are read for it.
+fn shortest_interval(tenants: &[Tenant]) -> Option<Duration> {
+    shortest_renew_interval(tenants)
+        .inspect_err(|e| tracing::debug!("failed to get the shortest renew interval: {:?}", e))
+        .ok()
+        .flatten()
+}
+
+/// A cycle overruns if it takes longer than the shortest renew interval, names with that interval
+/// can't be renewed in time.
+fn check_overrun(shortest: Option<Duration>, elapsed: Duration) {
+    let Some(shortest) = shortest else {
+        return;
+    };
+    if elapsed > shortest {
+        let overruns = metrics::inc_cycle_overruns();
+        tracing::warn!(
+            overruns,
+            "renew cycle takes {:?}, longer than the shortest renew interval {:?}",
+            elapsed,
+            shortest
+        );
+    }
+}
+
+/// Reload the main config and configs of tenants, they are validated first, and the old ones are
+/// kept if there is any problem. Other options of the daemon than `daemon_interval`, e.g.
+/// `control_socket`, are only changed by a restart.
+async fn reload(
+    args: &Args,
+    tenants: &mut Vec<Tenant>,
+    watchers: &mut Vec<CredentialWatcher>,
+) -> Result<()> {
+    let config = init_config(args)?;
+    let problems = validate::check(&args.config, &config).await;
+    if !problems.is_empty() {
+        bail!(
+            "{} problem(s) found: {}",
+            problems.len(),
+            problems.join("; ")
+        );
+    }
+    // Clients built afterwards use the new defaults of `[http]`.
+    http::init(&config)?;
+    // Configs of tenants are validated above, none of them is skipped.
+    (*tenants, _) = Tenant::init_all(args, config);
+    *watchers = tenants
+        .iter()
+        .map(|t| CredentialWatcher::new(&t.config_path, &t.config))
+        .collect();
+    Ok(())
+}
+
+fn daemon_interval(config: &Config) -> Duration {
+    config.daemon_interval().unwrap_or(DEFAULT_DAEMON_INTERVAL)
+}
+
+/// Sleep, and keep feeding the watchdog of systemd meanwhile, if it is enabled.
+async fn sleep(duration: Duration, watchdog: Option<Duration>) {
+    let Some(watchdog) = watchdog else {
+        return tokio::time::sleep(duration).await;
+    };
+    let deadline = tokio::time::Instant::now() + duration;
+    loop {
+        let now = tokio::time::Instant::now();
+        if now >= deadline {
+            return;
+        }
+        sd_notify::notify("WATCHDOG=1");
+        tokio::time::sleep_until(deadline.min(now + watchdog)).await;
+    }
+}
+
+pub async fn run(args: &Args, mut tenants: Vec<Tenant>) -> Result<()> {
+    // Options of the daemon itself are read from the main config.
+    let main_config = &tenants[0].config;
+    let mut interval = daemon_interval(main_config);
+    let statuses = SharedStatuses::default();
+    if let Some(path) = main_config.control_socket() {
+        status::serve(path, statuses.clone())?;
+    }
+    if let Some(listen) = main_config.metrics().listen() {
+        metrics::serve(*listen, statuses.clone())?;
+    }
+    if main_config.api().is_some() {
+        // The api has its own copy of the main config.
+        api::serve(args.clone(), init_config(args)?)?;
+    }
+    let mut watchers = tenants
+        .iter()
+        .map(|t| CredentialWatcher::new(&t.config_path, &t.config))
+        .collect::<Vec<_>>();
+    tracing::info!("running in daemon mode, check interval: {:?}", interval);
+    query::enable_answer_cache();
+    // Checks are scheduled by the monotonic clock, the wall clock is only used for detecting jumps.
+    let mut jump_detector = JumpDetector::new();
+    let mut shortest = shortest_interval(&tenants);
+    // A cycle longer than `WatchdogSec=` of the service is taken as hung, it is restarted.
+    let watchdog = sd_notify::watchdog_interval();
+    let mut hangup = signal(SignalKind::hangup())?;
+    let (changes_sender, mut changes) = mpsc::unbounded_channel();
+    let mut _name_conf_watcher = watch_name_conf_dirs(&tenants, changes_sender.clone());
+    let (network_sender, mut network_changes) = mpsc::unbounded_channel();
+    if main_config.watch_network().unwrap_or(true) {
+        watch_network(network_sender);
+    }
+    // Names which aren't due are also checked after the network is changed.
+    let mut cycle_args = args.clone();
+    sd_notify::notify("READY=1");
+    loop {
+        let started = Instant::now();
+        jump_detector.check();
+        if !args.dry_run {
+            healthcheck::ping(&tenants[0].config, Ping::Start, String::new()).await;
+        }
+        let mut new_statuses = vec![];
+        let mut error = None;
+        for (tenant, watcher) in tenants.iter_mut().zip(watchers.iter_mut()) {
+            let span = tenant.span();
+            span.in_scope(|| watcher.reload_if_changed(args, tenant));
+            let result =
+                metrics::with_tenant(tenant.name.clone(), renew_all(&cycle_args, &tenant.config))
+                    .instrument(span.clone())
+                    .await;
+            let _enter = span.enter();
+            if let Err(e) = result {
+                tracing::error!("failed to renew names: {:?}", e);
+                error.get_or_insert(e);
+            }
+            match status::from_states(&tenant.config, tenant.name.as_deref()) {
+                Ok(tenant_statuses) => new_statuses.extend(tenant_statuses),
+                Err(e) => tracing::warn!("failed to refresh statuses: {:?}", e),
+            }
+        }
+        sd_notify::notify(&format!("STATUS={}", status::summary(&new_statuses)));
+        if !args.dry_run {
+            let result = match &error {
+                Some(e) => Err(e),
+                None => Ok(new_statuses.as_slice()),
+            };
+            healthcheck::finish(&tenants[0].config, result).await;
+        }
+        if let Some(path) = tenants[0].config.metrics().textfile() {
+            if let Err(e) = metrics::write_textfile(path, &new_statuses) {
+                tracing::warn!("failed to write metrics: {:?}", e);
+            }
+        }
+        *statuses.lock().expect("statuses are poisoned") = new_statuses;
+
+        let elapsed = started.elapsed();
+        check_overrun(shortest, elapsed);
+        // Checks missed during a long cycle are skipped instead of being run back to back, wait
+        // for the next tick.
+        let interval_nanos = interval.as_nanos().max(1);
+        if elapsed > interval {
+            tracing::debug!(
+                "{} check(s) are skipped",
+                elapsed.as_nanos() / interval_nanos
+            );
+        }
+        let into_tick = Duration::from_nanos((elapsed.as_nanos() % interval_nanos) as u64);
+        let wake = tokio::select! {
+            _ = sleep(interval.saturating_sub(into_tick), watchdog) => Wake::Tick,
+            _ = hangup.recv() => Wake::HangUp,
+            Some(path) = changes.recv() => {
+                let paths = settle_changes(&mut changes, path, NAME_CONF_SETTLE).await;
+                tracing::info!("name configs are changed, check names now: {:?}", paths);
+                Wake::NameConfChanged(paths)
+            }
+            Some(change) = network_changes.recv() => {
+                let changes = settle_changes(&mut network_changes, change, NETWORK_SETTLE).await;
+                tracing::info!("network is changed, check all names now: {}", changes.join(", "));
+                Wake::NetworkChanged
+            }
+        };
+        cycle_args.check_all = matches!(wake, Wake::NetworkChanged);
+        cycle_args.changed_confs.clear();
+        // Names are checked right after a reload or a change of name configs.
+        match wake {
+            Wake::Tick | Wake::NetworkChanged => {}
+            Wake::NameConfChanged(paths) => {
+                shortest = shortest_interval(&tenants);
+                cycle_args.changed_confs = paths;
+            }
+            Wake::HangUp => {
+                tracing::info!("reloading the config on SIGHUP");
+                sd_notify::notify("RELOADING=1");
+                match reload(args, &mut tenants, &mut watchers).await {
+                    Ok(()) => {
+                        interval = daemon_interval(&tenants[0].config);
+                        // name_conf_dir may be changed.
+                        _name_conf_watcher = watch_name_conf_dirs(&tenants, changes_sender.clone());
+                        shortest = shortest_interval(&tenants);
+                        tracing::info!("the config is reloaded");
+                    }
+                    Err(e) => {
+                        tracing::error!("failed to reload the config, the old one is kept: {:?}", e)
+                    }
+                }
+                sd_notify::notify("READY=1");
+            }
+        }
+    }
+}
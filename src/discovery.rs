@@ -0,0 +1,95 @@
+use anyhow::{anyhow, bail, Context, Result};
+use data_encoding::BASE64;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use hickory_proto::rr::{RData, RecordType};
+
+use crate::{config::DiscoveryConf, dns::DnsClient, DEFAULT_TIMEOUT};
+
+/// The version tag of the txt record.
+const VERSION: &str = "dnsrenew1";
+
+/// Query the txt record of discovery, and return the names in it after the signature is
+/// verified. The record is like:
+///
+/// `v=dnsrenew1 names=a.example.com,b.example.com sig=<base64 ed25519 signature of names>`
+///
+/// Long records can be split into several strings, they are concatenated before parsing.
+#[tracing::instrument(skip(conf), fields(name = conf.name()), err)]
+pub async fn discover(conf: &DiscoveryConf) -> Result<Vec<String>> {
+    let public_key: [u8; 32] = BASE64
+        .decode(conf.public_key().as_bytes())
+        .with_context(|| "invalid public key of discovery")?
+        .try_into()
+        .map_err(|_| anyhow!("public key of discovery should be 32 bytes"))?;
+    let public_key = VerifyingKey::from_bytes(&public_key)?;
+
+    let client = DnsClient::new(
+        conf.name_server_host(),
+        *conf.name_server_port(),
+        conf.timeout().unwrap_or(DEFAULT_TIMEOUT),
+        true,
+        false,
+    )?;
+    let dns_response = client.query(conf.name(), RecordType::TXT, None).await?;
+
+    let mut last_err = None;
+    for record in dns_response.answers() {
+        if let Some(RData::TXT(txt)) = record.data() {
+            let mut data = vec![];
+            for d in txt.txt_data() {
+                data.extend(d);
+            }
+            match String::from_utf8(data)
+                .map_err(From::from)
+                .and_then(|s| parse(&s, &public_key))
+            {
+                Ok(names) => {
+                    tracing::debug!("discovered names: {:?}", names);
+                    return Ok(names);
+                }
+                Err(e) => {
+                    tracing::warn!("invalid txt data of {}: {:?}", conf.name(), e);
+                    last_err = Some(e);
+                }
+            }
+        }
+    }
+    match last_err {
+        Some(e) => Err(e),
+        None => bail!("no txt record of {} found", conf.name()),
+    }
+}
+
+fn parse(txt: &str, public_key: &VerifyingKey) -> Result<Vec<String>> {
+    let mut version = None;
+    let mut names = None;
+    let mut sig = None;
+    for field in txt.trim_matches('"').split_whitespace() {
+        match field.split_once('=') {
+            Some(("v", v)) => version = Some(v),
+            Some(("names", v)) => names = Some(v),
+            Some(("sig", v)) => sig = Some(v),
+            _ => tracing::debug!("skip unknown field: {}", field),
+        }
+    }
+    if version != Some(VERSION) {
+        bail!("unsupported version: {:?}", version);
+    }
+    let names = names.ok_or_else(|| anyhow!("no names in txt record"))?;
+    let sig = sig.ok_or_else(|| anyhow!("no signature in txt record"))?;
+    let sig = Signature::from_slice(
+        &BASE64
+            .decode(sig.as_bytes())
+            .with_context(|| "invalid signature encoding")?,
+    )?;
+    public_key
+        .verify(names.as_bytes(), &sig)
+        .with_context(|| "failed to verify the signature of names")?;
+
+    Ok(names
+        .split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(ToString::to_string)
+        .collect())
+}
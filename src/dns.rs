@@ -1,16 +1,18 @@
 use std::{
-    cell::LazyCell,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     str::FromStr,
     time::Duration,
 };
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use hickory_proto::{
     iocompat::AsyncIoTokioAsStd,
     native_tls::TlsClientStreamBuilder,
-    op::{Message, Query},
-    rr::{DNSClass, Name, RecordType},
+    op::{Edns, Message, Query},
+    rr::{
+        rdata::opt::{ClientSubnet, EdnsOption},
+        DNSClass, Name, RecordType,
+    },
     tcp::TcpClientStream,
     udp::UdpClientStream,
     xfer::{
@@ -20,16 +22,11 @@ use hickory_proto::{
     Time, TokioTime,
 };
 use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::{TcpStream, UdpSocket},
-    runtime::Runtime,
 };
-
-thread_local! {
-    static RT: LazyCell<Runtime> = LazyCell::new(|| tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .expect("can't build tokio runtime"));
-}
+use tokio_native_tls::TlsConnector;
+use tokio_socks::tcp::Socks5Stream;
 
 async fn query_via_udp(
     addr: SocketAddr,
@@ -81,12 +78,70 @@ async fn query_via_tls(
     Ok(DnsResponse::from_message(response_data.to_message()?)?)
 }
 
+/// Exchange a message over a stream, with the 2-byte length prefix of dns over tcp.
+async fn exchange_over_stream<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    request: &DnsRequest,
+) -> Result<Message> {
+    let message = request.to_vec()?;
+    stream
+        .write_all(&(message.len() as u16).to_be_bytes())
+        .await?;
+    stream.write_all(&message).await?;
+    stream.flush().await?;
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).await?;
+    let mut response = vec![0u8; usize::from(u16::from_be_bytes(len))];
+    stream.read_exact(&mut response).await?;
+    Ok(Message::from_vec(&response)?)
+}
+
+/// Query over tcp, or tls if `is_tls`, through a socks5 proxy.
+async fn query_via_socks5(
+    proxy: &str,
+    addr: SocketAddr,
+    host: &str,
+    is_tls: bool,
+    timeout: Duration,
+    request: DnsRequest,
+) -> Result<DnsResponse> {
+    let message = tokio::time::timeout(timeout, async {
+        let stream = Socks5Stream::connect(proxy, addr)
+            .await
+            .with_context(|| format!("failed to connect to {} through {}", addr, proxy))?;
+        if is_tls {
+            let connector = TlsConnector::from(native_tls::TlsConnector::new()?);
+            let stream = connector.connect(host, stream).await?;
+            exchange_over_stream(stream, &request).await
+        } else {
+            exchange_over_stream(stream, &request).await
+        }
+    })
+    .await
+    .context("timeout")??;
+    Ok(DnsResponse::from_message(message)?)
+}
+
+/// The address of a socks5 proxy, in `host:port`, the scheme of `socks5://` or `socks5h://` is
+/// ignored.
+pub(crate) fn socks5_proxy_addr(proxy: &str) -> &str {
+    proxy
+        .strip_prefix("socks5h://")
+        .or_else(|| proxy.strip_prefix("socks5://"))
+        .unwrap_or(proxy)
+        .trim_end_matches('/')
+}
+
 pub struct DnsClient {
     host: String,
     port: Option<u16>,
     timeout: Duration,
     is_udp: bool,
     is_tls: bool,
+    socks5_proxy: Option<String>,
+    edns: Option<Edns>,
+    bind_address: Option<IpAddr>,
+    bind_interface: Option<String>,
 }
 
 impl DnsClient {
@@ -106,82 +161,198 @@ impl DnsClient {
             timeout,
             is_udp,
             is_tls,
+            socks5_proxy: None,
+            edns: None,
+            bind_address: None,
+            bind_interface: None,
         })
     }
 
+    /// Add an EDNS0 OPT record to queries, with the udp payload size and the client subnet, e.g.
+    /// `203.0.113.0/24`. Without them, EDNS0 is not used.
+    pub fn with_edns(
+        mut self,
+        udp_payload_size: Option<u16>,
+        client_subnet: Option<&str>,
+    ) -> Result<Self> {
+        if udp_payload_size.is_none() && client_subnet.is_none() {
+            return Ok(self);
+        }
+        let mut edns = Edns::new();
+        // The default of RFC 6891 is 512, 1232 is recommended by the dns flag day 2020.
+        edns.set_max_payload(udp_payload_size.unwrap_or(1232));
+        if let Some(client_subnet) = client_subnet {
+            let client_subnet = ClientSubnet::from_str(client_subnet)
+                .map_err(|e| anyhow!("invalid client subnet {}: {}", client_subnet, e))?;
+            edns.options_mut().insert(EdnsOption::Subnet(client_subnet));
+        }
+        self.edns = Some(edns);
+        Ok(self)
+    }
+
+    /// Send queries through a socks5 proxy, only over tcp or tls.
+    pub fn with_socks5_proxy(mut self, proxy: Option<&str>) -> Result<Self> {
+        if proxy.is_some() && self.is_udp {
+            bail!("no support of udp through a socks5 proxy");
+        }
+        self.socks5_proxy = proxy.map(|p| socks5_proxy_addr(p).to_string());
+        Ok(self)
+    }
+
+    /// Send queries from a local address, or an address of the interface in the family of the
+    /// query, it is set after the socks5 proxy.
+    pub fn with_bind(
+        mut self,
+        bind_address: Option<IpAddr>,
+        bind_interface: Option<&str>,
+    ) -> Result<Self> {
+        if bind_address.is_none() && bind_interface.is_none() {
+            return Ok(self);
+        }
+        if bind_address.is_some() && bind_interface.is_some() {
+            bail!("only one of bind address and bind interface can be set");
+        }
+        if self.socks5_proxy.is_some() {
+            bail!("no support of binding a local address with a socks5 proxy");
+        }
+        self.bind_address = bind_address;
+        self.bind_interface = bind_interface.map(ToString::to_string);
+        Ok(self)
+    }
+
+    /// The local address to bind, an interface without an address of the family is an error.
+    async fn local_ip(&self, is_via_v6: Option<bool>) -> Result<Option<IpAddr>> {
+        if let Some(address) = self.bind_address {
+            return Ok(Some(address));
+        }
+        let Some(interface) = &self.bind_interface else {
+            return Ok(None);
+        };
+        match is_via_v6 {
+            Some(is_v6) => crate::ip::interface_address(interface, is_v6)
+                .await
+                .map(Some),
+            None => match crate::ip::interface_address(interface, false).await {
+                Ok(address) => Ok(Some(address)),
+                Err(_) => crate::ip::interface_address(interface, true)
+                    .await
+                    .map(Some),
+            },
+        }
+    }
+
+    fn query_message(name: &str, record_type: RecordType, class: DNSClass) -> Result<Message> {
+        let mut message = Message::new();
+        let mut query = Query::query(Name::from_str(name)?, record_type);
+        query.set_query_class(class);
+        message.set_recursion_desired(true).add_query(query);
+        Ok(message)
+    }
+
     async fn do_query(
         &self,
         name: &str,
         record_type: RecordType,
+        class: DNSClass,
         is_via_v6: Option<bool>,
-        bind_addr: Option<SocketAddr>,
     ) -> Result<DnsResponse> {
+        let mut message = Self::query_message(name, record_type, class)?;
+        if let Some(edns) = &self.edns {
+            message.set_edns(edns.clone());
+        }
+        self.do_send(message, is_via_v6)
+            .await
+            .with_context(|| format!("failed to resolve name[{}] in type[{}]", name, record_type))
+    }
+
+    async fn do_send(&self, message: Message, is_via_v6: Option<bool>) -> Result<DnsResponse> {
+        let local_ip = self.local_ip(is_via_v6).await?;
+        // The name server is reached in the family of the local address.
+        let is_via_v6 = local_ip.map(|ip| ip.is_ipv6()).or(is_via_v6);
         let port = self.port.unwrap_or(if self.is_tls { 853 } else { 53 });
-        let addrs = (self.host.as_str(), port)
-            .to_socket_addrs()?
+        let addrs = tokio::net::lookup_host((self.host.as_str(), port))
+            .await?
             .filter(|addr| match is_via_v6 {
                 Some(true) => addr.is_ipv6(),
                 Some(false) => addr.is_ipv4(),
                 None => true,
             });
-        let bind_addr = bind_addr.or_else(|| match is_via_v6 {
-            Some(true) => Some(SocketAddr::from((IpAddr::from(Ipv6Addr::UNSPECIFIED), 0))),
-            Some(false) => Some(SocketAddr::from((IpAddr::from(Ipv4Addr::UNSPECIFIED), 0))),
-            None => None,
-        });
+        let bind_addr = local_ip
+            .map(|ip| SocketAddr::from((ip, 0)))
+            .or_else(|| match is_via_v6 {
+                Some(true) => Some(SocketAddr::from((IpAddr::from(Ipv6Addr::UNSPECIFIED), 0))),
+                Some(false) => Some(SocketAddr::from((IpAddr::from(Ipv4Addr::UNSPECIFIED), 0))),
+                None => None,
+            });
 
-        let mut message = Message::new();
-        let mut query = Query::query(Name::from_str(name)?, record_type);
-        query.set_query_class(DNSClass::IN);
-        message.set_recursion_desired(true).add_query(query);
         let request = DnsRequest::from(message);
 
         let mut has_tried = false;
         for addr in addrs {
             has_tried = true;
-            let response = if self.is_tls {
+            let response = if let Some(proxy) = &self.socks5_proxy {
+                query_via_socks5(
+                    proxy,
+                    addr,
+                    &self.host,
+                    self.is_tls,
+                    self.timeout,
+                    request.clone(),
+                )
+                .await
+            } else if self.is_tls {
                 query_via_tls(addr, &self.host, self.timeout, bind_addr, request.clone()).await
             } else if self.is_udp {
-                query_via_udp(addr, self.timeout, bind_addr, request.clone()).await
+                match query_via_udp(addr, self.timeout, bind_addr, request.clone()).await {
+                    Ok(response) if response.truncated() => {
+                        tracing::debug!(
+                            "response from addr[{}] is truncated, retry over tcp",
+                            addr
+                        );
+                        query_via_tcp(addr, self.timeout, bind_addr, request.clone()).await
+                    }
+                    response => response,
+                }
             } else {
                 query_via_tcp(addr, self.timeout, bind_addr, request.clone()).await
             };
             match response {
                 Ok(response) => return Ok(response),
                 Err(e) => {
-                    tracing::debug!(
-                        "failed to resolve name[{}] in type[{}] with addr[{}]: {}, try next",
-                        name,
-                        record_type,
-                        addr,
-                        e,
-                    )
+                    tracing::debug!("failed to send message to addr[{}]: {}, try next", addr, e)
                 }
             }
         }
 
         if has_tried {
-            bail!("failed to resolve name[{}]", name)
+            bail!("failed to send message to {}", self.host)
         }
         Ok(DnsResponse::from_message(Message::new())?)
     }
 
-    pub fn query(
+    pub async fn query(
         &self,
         name: &str,
         record_type: RecordType,
         is_via_v6: Option<bool>,
     ) -> Result<DnsResponse> {
-        RT.with(|rt| rt.block_on(self.do_query(name, record_type, is_via_v6, None)))
+        self.do_query(name, record_type, DNSClass::IN, is_via_v6)
+            .await
     }
 
-    pub fn _query_with_bind_addr(
+    /// Query in a class other than IN, e.g. CH for `whoami.cloudflare`.
+    pub async fn query_in_class(
         &self,
         name: &str,
         record_type: RecordType,
+        class: DNSClass,
         is_via_v6: Option<bool>,
-        bind_addr: SocketAddr,
     ) -> Result<DnsResponse> {
-        RT.with(|rt| rt.block_on(self.do_query(name, record_type, is_via_v6, Some(bind_addr))))
+        self.do_query(name, record_type, class, is_via_v6).await
+    }
+
+    /// Send a message which is built by the caller, e.g. an update message.
+    pub async fn send(&self, message: Message, is_via_v6: Option<bool>) -> Result<DnsResponse> {
+        self.do_send(message, is_via_v6).await
     }
 }
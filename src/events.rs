@@ -0,0 +1,115 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    net::IpAddr,
+    os::fd::FromRawFd,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{
+    now,
+    update::{RecordDiff, RenewAction},
+};
+
+/// Where events are written to, one json object per line.
+static SINK: OnceLock<Mutex<File>> = OnceLock::new();
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A renew of a name in one ip family is started.
+    Started,
+    /// The ip is detected and compared with the current ips of the name.
+    Decided,
+    /// The record is changed by the update provider.
+    Updated,
+    Failed,
+}
+
+/// An event of renewing a name, fields not known at the time of the event are omitted.
+#[derive(Serialize)]
+pub struct Event<'a> {
+    pub event: EventKind,
+    pub name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_ips: Option<&'a [IpAddr]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<RenewAction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<&'a RecordDiff>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl<'a> Event<'a> {
+    pub fn new(event: EventKind, name: &'a str, is_v6: Option<bool>) -> Self {
+        Self {
+            event,
+            name,
+            family: is_v6.map(|is_v6| if is_v6 { "v6" } else { "v4" }),
+            ip: None,
+            current_ips: None,
+            action: None,
+            diff: None,
+            error: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    ts: u64,
+    dry_run: bool,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// Write events to an inherited file descriptor or a file, which is appended.
+pub fn init(fd: Option<i32>, path: Option<&Path>) -> Result<()> {
+    let file = match (fd, path) {
+        (Some(fd), _) => {
+            // SAFETY: the fd is passed by the parent process for this purpose, and nothing else
+            // in this process owns it.
+            unsafe { File::from_raw_fd(fd) }
+        }
+        (None, Some(path)) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open events file: {:?}", path))?,
+        (None, None) => return Ok(()),
+    };
+    let _ = SINK.set(Mutex::new(file));
+    Ok(())
+}
+
+/// Emit an event, a failure of writing is only logged.
+pub fn emit(event: &Event, dry_run: bool) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+    let line = Line {
+        ts: now().unwrap_or_default(),
+        dry_run,
+        event,
+    };
+    let result = serde_json::to_vec(&line)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut line| {
+            line.push(b'\n');
+            let mut sink = sink.lock().expect("event sink is poisoned");
+            sink.write_all(&line)?;
+            Ok(sink.flush()?)
+        });
+    if let Err(e) = result {
+        tracing::warn!("failed to emit event: {:?}", e);
+    }
+}
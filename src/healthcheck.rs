@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::{
+    config::Config,
+    http,
+    status::{self, NameStatus},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy)]
+pub enum Ping {
+    Start,
+    Success,
+    Fail,
+}
+
+/// Ping the dead man's switch of the config, if any, a failed ping is only logged.
+pub async fn ping(config: &Config, ping: Ping, body: String) {
+    let Some(healthcheck) = config.healthcheck() else {
+        return;
+    };
+    let url = format!(
+        "{}{}",
+        healthcheck.url().trim_end_matches('/'),
+        match ping {
+            Ping::Start => "/start",
+            Ping::Success => "",
+            Ping::Fail => "/fail",
+        }
+    );
+    if let Err(e) = send(&url, body).await {
+        tracing::warn!("failed to ping healthcheck: {:?}", e);
+    }
+}
+
+async fn send(url: &str, body: String) -> Result<()> {
+    let req_builder = http::builder()
+        .timeout(TIMEOUT)
+        .build()?
+        .post(url)
+        .body(body);
+    http::send(req_builder).await?.error_for_status()?;
+    Ok(())
+}
+
+/// Ping the end of a run, it fails if the run fails, or any name is failing.
+pub async fn finish(config: &Config, result: Result<&[NameStatus], &anyhow::Error>) {
+    match result {
+        Ok(statuses) => {
+            let ping = if statuses.iter().any(|s| s.last_error.is_some()) {
+                Ping::Fail
+            } else {
+                Ping::Success
+            };
+            self::ping(config, ping, status::summary(statuses)).await;
+        }
+        Err(e) => ping(config, Ping::Fail, format!("{:#}", e)).await,
+    }
+}
@@ -0,0 +1,72 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use tokio::process::Command;
+
+use crate::config::HookConf;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What a hook is run for, it is passed to the hook by env vars.
+pub struct HookEnv<'a> {
+    pub name: &'a str,
+    pub family: &'a str,
+    /// Ips in the record before the update.
+    pub old_ips: &'a [IpAddr],
+    pub new_ip: IpAddr,
+}
+
+/// Run a hook, e.g. `pre_update`, its failure is only logged, unless it is fatal.
+pub async fn run(kind: &str, hook: &HookConf, env: &HookEnv<'_>) -> Result<()> {
+    match execute(hook, env).await {
+        Ok(()) => Ok(()),
+        Err(e) if hook.fatal() => Err(e.context(format!("{} hook failed", kind))),
+        Err(e) => {
+            tracing::warn!("{} hook of {} failed: {:?}", kind, env.name, e);
+            Ok(())
+        }
+    }
+}
+
+async fn execute(hook: &HookConf, env: &HookEnv<'_>) -> Result<()> {
+    let Some((program, args)) = hook.command().split_first() else {
+        return Ok(());
+    };
+    let old_ips = env
+        .old_ips
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    let timeout = hook.timeout().unwrap_or(DEFAULT_TIMEOUT);
+    let output = tokio::time::timeout(
+        timeout,
+        Command::new(program)
+            .args(args)
+            .env("DNS_RENEW_NAME", env.name)
+            .env("DNS_RENEW_FAMILY", env.family)
+            .env("DNS_RENEW_OLD_IP", old_ips)
+            .env("DNS_RENEW_NEW_IP", env.new_ip.to_string())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .with_context(|| format!("{} is timed out after {:?}", program, timeout))?
+    .with_context(|| format!("failed to run {}", program))?;
+    tracing::debug!(
+        "hook {:?} exits with {}, stdout: {:?}, stderr: {:?}",
+        hook.command(),
+        output.status,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    if !output.status.success() {
+        bail!(
+            "{} exits with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
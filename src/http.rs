@@ -0,0 +1,293 @@
+use std::{
+    collections::HashMap,
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex, RwLock},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use reqwest::{
+    Certificate, Client, ClientBuilder, Method, Proxy, RequestBuilder, Response, StatusCode,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::{Config, HttpClientConf, HttpConf};
+
+const DEFAULT_MAX_CONNECTIONS_PER_HOST: usize = 4;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const DEFAULT_USER_AGENT: &str = concat!("dns-renew/", env!("CARGO_PKG_VERSION"));
+
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// It is replaced in reloading, a request being sent keeps the defaults it started with.
+static DEFAULTS: RwLock<Option<Arc<Defaults>>> = RwLock::new(None);
+
+/// Defaults of `[http]`, the client is shared by requests without their own settings.
+struct Defaults {
+    timeout: Duration,
+    user_agent: String,
+    proxy: Option<Proxy>,
+    ca_file: Option<PathBuf>,
+    ca_certs: Vec<Certificate>,
+    retries: u32,
+    retry_interval: Duration,
+    /// It is `max_connections_per_host` of the config, not of `[http]`.
+    max_connections_per_host: usize,
+    client: Client,
+}
+
+impl Defaults {
+    fn new(conf: &HttpConf) -> Result<Self> {
+        let proxy = conf.proxy().as_deref().map(parse_proxy).transpose()?;
+        let timeout = conf.timeout().unwrap_or(DEFAULT_TIMEOUT);
+        let user_agent = conf
+            .user_agent()
+            .clone()
+            .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string());
+        let ca_certs = match conf.ca_file() {
+            Some(ca_file) => load_ca_file(ca_file)?,
+            None => vec![],
+        };
+        let client = builder_with(timeout, &user_agent, proxy.as_ref(), &ca_certs).build()?;
+        Ok(Self {
+            timeout,
+            user_agent,
+            proxy,
+            ca_file: conf.ca_file().clone(),
+            ca_certs,
+            retries: conf.retries().unwrap_or_default(),
+            retry_interval: conf.retry_interval().unwrap_or(DEFAULT_RETRY_INTERVAL),
+            max_connections_per_host: DEFAULT_MAX_CONNECTIONS_PER_HOST,
+            client,
+        })
+    }
+
+    fn builder(&self) -> ClientBuilder {
+        self.builder_with_proxy(self.proxy.as_ref())
+    }
+
+    fn builder_with_proxy(&self, proxy: Option<&Proxy>) -> ClientBuilder {
+        builder_with(self.timeout, &self.user_agent, proxy, &self.ca_certs)
+    }
+}
+
+fn parse_proxy(proxy: &str) -> Result<Proxy> {
+    Proxy::all(proxy).with_context(|| format!("invalid proxy: {}", proxy))
+}
+
+fn load_ca_file(path: &Path) -> Result<Vec<Certificate>> {
+    let pem = fs::read(path).with_context(|| format!("failed to read ca file: {:?}", path))?;
+    Certificate::from_pem_bundle(&pem).with_context(|| format!("invalid ca file: {:?}", path))
+}
+
+fn builder_with(
+    timeout: Duration,
+    user_agent: &str,
+    proxy: Option<&Proxy>,
+    ca_certs: &[Certificate],
+) -> ClientBuilder {
+    let mut builder = Client::builder().timeout(timeout).user_agent(user_agent);
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy.clone());
+    }
+    for cert in ca_certs {
+        builder = builder.add_root_certificate(cert.clone());
+    }
+    builder
+}
+
+fn defaults() -> Arc<Defaults> {
+    if let Some(defaults) = &*DEFAULTS.read().expect("http defaults are poisoned") {
+        return defaults.clone();
+    }
+    DEFAULTS
+        .write()
+        .expect("http defaults are poisoned")
+        .get_or_insert_with(|| {
+            Arc::new(Defaults::new(&HttpConf::default()).expect("the default http client is valid"))
+        })
+        .clone()
+}
+
+/// The shared client with defaults of `[http]`.
+pub fn client() -> Client {
+    defaults().client.clone()
+}
+
+/// A builder with defaults of `[http]`, for a client with its own settings, e.g. a timeout.
+pub fn builder() -> ClientBuilder {
+    defaults().builder()
+}
+
+/// A builder with defaults of `[http]`, overridden by the settings of a provider.
+pub fn builder_of(conf: &HttpClientConf) -> Result<ClientBuilder> {
+    let defaults = defaults();
+    let mut builder = match conf.proxy().as_deref() {
+        None => defaults.builder(),
+        // Proxies of environment variables are skipped too.
+        Some("") => defaults.builder_with_proxy(None).no_proxy(),
+        Some(proxy) => defaults.builder_with_proxy(Some(&parse_proxy(proxy)?)),
+    };
+    if let Some(ca_file) = conf.ca_file() {
+        for cert in load_ca_file(ca_file)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    Ok(builder)
+}
+
+/// The client of a provider, it is the shared one if the provider has no settings of its own.
+pub fn client_of(conf: &HttpClientConf) -> Result<Client> {
+    if conf.proxy().is_none() && conf.ca_file().is_none() {
+        return Ok(client());
+    }
+    Ok(builder_of(conf)?.build()?)
+}
+
+/// Files of extra root certificates of a provider, including the one of `[http]`, for a client
+/// which is not built by reqwest.
+pub fn ca_files(conf: &HttpClientConf) -> Vec<PathBuf> {
+    defaults()
+        .ca_file
+        .iter()
+        .chain(conf.ca_file())
+        .cloned()
+        .collect()
+}
+
+static HOST_SEMAPHORES: LazyLock<Mutex<HashMap<String, Arc<Semaphore>>>> =
+    LazyLock::new(Default::default);
+
+pub fn init(config: &Config) -> Result<()> {
+    let mut defaults = Defaults::new(config.http())?;
+    if let Some(max) = config.max_connections_per_host() {
+        defaults.max_connections_per_host = max.max(1);
+    }
+    let mut current = DEFAULTS.write().expect("http defaults are poisoned");
+    // Semaphores are made again with the new limit, requests in flight keep their permits.
+    if current
+        .as_ref()
+        .is_some_and(|c| c.max_connections_per_host != defaults.max_connections_per_host)
+    {
+        HOST_SEMAPHORES
+            .lock()
+            .expect("host semaphores are poisoned")
+            .clear();
+    }
+    *current = Some(Arc::new(defaults));
+    Ok(())
+}
+
+/// A permit of making a connection to a host, it is released when dropped.
+async fn acquire(host: &str) -> OwnedSemaphorePermit {
+    let max = defaults().max_connections_per_host;
+    let semaphore = HOST_SEMAPHORES
+        .lock()
+        .expect("host semaphores are poisoned")
+        .entry(host.to_string())
+        .or_insert_with(|| Arc::new(Semaphore::new(max)))
+        .clone();
+    semaphore
+        .acquire_owned()
+        .await
+        .expect("host semaphores are never closed")
+}
+
+/// A response which holds the permit of its host until the body is consumed.
+pub struct PermittedResponse {
+    response: Response,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PermittedResponse {
+    pub fn error_for_status(self) -> Result<Self> {
+        Ok(Self {
+            response: self.response.error_for_status()?,
+            _permit: self._permit,
+        })
+    }
+
+    pub async fn bytes(self) -> Result<Bytes> {
+        Ok(self.response.bytes().await?)
+    }
+
+    pub async fn text(self) -> Result<String> {
+        Ok(self.response.text().await?)
+    }
+}
+
+impl Deref for PermittedResponse {
+    type Target = Response;
+
+    fn deref(&self) -> &Self::Target {
+        &self.response
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Send a request, at most `max_connections_per_host` requests to the same host are in flight
+/// at the same time. It is sent again on a transient failure, up to `retries` of `[http]`, a
+/// request which is not idempotent is only sent again if it fails to connect.
+pub async fn send(req_builder: RequestBuilder) -> Result<PermittedResponse> {
+    let (client, request) = req_builder.build_split();
+    let mut request = request?;
+    let defaults = defaults();
+    let mut attempt = 0;
+    loop {
+        // A request with a streamed body can't be sent again.
+        let next = (attempt < defaults.retries)
+            .then(|| request.try_clone())
+            .flatten();
+        // Only the origin is logged, a path or a query may carry a secret, e.g. a bot token.
+        let origin = request.url().origin().ascii_serialization();
+        // A create by POST, or a PATCH, may be applied even if its response is lost, only a
+        // request which is never sent is sent again.
+        let idempotent = matches!(
+            *request.method(),
+            Method::GET | Method::HEAD | Method::PUT | Method::DELETE
+        );
+        let permit = acquire(request.url().host_str().unwrap_or_default()).await;
+        let result = client.execute(request).await;
+        let failure = match (result, next) {
+            (Ok(response), Some(next)) if idempotent && is_retryable_status(response.status()) => {
+                request = next;
+                response.status().to_string()
+            }
+            (Err(e), Some(next)) if e.is_connect() || (idempotent && e.is_timeout()) => {
+                request = next;
+                e.without_url().to_string()
+            }
+            (result, _) => {
+                return Ok(PermittedResponse {
+                    response: result?,
+                    _permit: permit,
+                })
+            }
+        };
+        drop(permit);
+        attempt += 1;
+        tracing::warn!(
+            "request to {} fails: {}, retry {}/{} in {:?}",
+            origin,
+            failure,
+            attempt,
+            defaults.retries,
+            defaults.retry_interval
+        );
+        tokio::time::sleep(defaults.retry_interval).await;
+    }
+}
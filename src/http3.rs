@@ -0,0 +1,101 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use http::Request;
+use quinn::{crypto::rustls::QuicClientConfig, Endpoint};
+use rustls::pki_types::{pem::PemObject, CertificateDer};
+use rustls_platform_verifier::Verifier;
+
+/// Send a request over HTTP/3, and return the body of a successful response. reqwest only
+/// supports HTTP/3 as an unstable feature, so a minimal client is built on h3 and quinn.
+/// Certificates in `ca_files` are trusted besides the ones of the system.
+pub async fn send(
+    request: Request<Vec<u8>>,
+    timeout: Duration,
+    ca_files: &[PathBuf],
+) -> Result<Bytes> {
+    tokio::time::timeout(timeout, do_send(request, ca_files))
+        .await
+        .context("timeout")?
+}
+
+fn client_config(ca_files: &[PathBuf]) -> Result<quinn::ClientConfig> {
+    let mut extra_roots = vec![];
+    for ca_file in ca_files {
+        for cert in CertificateDer::pem_file_iter(ca_file)
+            .with_context(|| format!("failed to read ca file: {:?}", ca_file))?
+        {
+            extra_roots.push(cert.with_context(|| format!("invalid ca file: {:?}", ca_file))?);
+        }
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = Verifier::new_with_extra_roots(extra_roots, provider.clone())?;
+    let mut tls = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    Ok(quinn::ClientConfig::new(Arc::new(
+        QuicClientConfig::try_from(tls)?,
+    )))
+}
+
+async fn do_send(request: Request<Vec<u8>>, ca_files: &[PathBuf]) -> Result<Bytes> {
+    let uri = request.uri().clone();
+    let Some(host) = uri.host() else {
+        bail!("no host in {}", uri);
+    };
+    // The host of an ipv6 address is in brackets.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addr = tokio::net::lookup_host((host, uri.port_u16().unwrap_or(443)))
+        .await?
+        .next()
+        .with_context(|| format!("no address of {}", host))?;
+    let bind_addr = if addr.is_ipv6() {
+        SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+    } else {
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+    };
+    let mut endpoint = Endpoint::client(bind_addr)?;
+    endpoint.set_default_client_config(client_config(ca_files)?);
+    let connection = endpoint
+        .connect(addr, host)?
+        .await
+        .with_context(|| format!("failed to connect to {} by quic", addr))?;
+
+    let (mut driver, mut send_request) = h3::client::new(h3_quinn::Connection::new(connection))
+        .await
+        .context("failed to set up http3 connection")?;
+    let driver = tokio::spawn(async move { driver.wait_idle().await });
+
+    let (parts, body) = request.into_parts();
+    let mut stream = send_request
+        .send_request(Request::from_parts(parts, ()))
+        .await?;
+    if !body.is_empty() {
+        stream.send_data(Bytes::from(body)).await?;
+    }
+    stream.finish().await?;
+    let response = stream.recv_response().await?;
+    let mut response_body = BytesMut::new();
+    while let Some(chunk) = stream.recv_data().await? {
+        response_body.put(chunk);
+    }
+    tracing::debug!("http3 response of {}: {:?}", uri, response);
+
+    drop(send_request);
+    driver.abort();
+    endpoint.close(0u32.into(), b"");
+
+    if !response.status().is_success() {
+        bail!("http3 request to {} failed: {}", uri, response.status());
+    }
+    Ok(response_body.freeze())
+}
@@ -1,10 +1,19 @@
-use std::net::IpAddr;
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{Arc, Mutex},
+};
 
 use crate::{
     config::{Config, IpProviderType},
-    DEFAULT_TIMEOUT,
+    plugin::PluginProvider,
+    registry, DEFAULT_TIMEOUT,
 };
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use tokio::sync::OnceCell;
 
 mod ifconfigio {
     use std::{
@@ -13,40 +22,120 @@ mod ifconfigio {
     };
 
     use super::IpProvider;
-    use anyhow::{bail, Context, Result};
-    use reqwest::blocking::Client;
+    use crate::{config::HttpClientConf, http};
+    use anyhow::{Context, Result};
+    use async_trait::async_trait;
 
     pub(super) struct IfconfigIoIpProvider {
         pub(super) url: String,
         pub(super) timeout: Duration,
+        pub(super) http_client: HttpClientConf,
+    }
+
+    /// Get the body of `url`, by a connection of the family.
+    pub(super) async fn get(
+        url: &str,
+        timeout: Duration,
+        http_client: &HttpClientConf,
+        is_v6: bool,
+    ) -> Result<String> {
+        let mut builder = http::builder_of(http_client)?.timeout(timeout);
+        if is_v6 {
+            builder = builder.local_address(Some(Ipv6Addr::UNSPECIFIED.into()))
+        } else {
+            builder = builder.local_address(Some(Ipv4Addr::UNSPECIFIED.into()))
+        }
+        let response = http::send(builder.build()?.get(url))
+            .await?
+            .error_for_status()?;
+        response.text().await
     }
 
+    #[async_trait]
     impl IpProvider for IfconfigIoIpProvider {
         #[tracing::instrument(skip(self), err)]
-        fn query(&self, is_v6: bool) -> Result<IpAddr> {
-            let mut builder = Client::builder().timeout(self.timeout);
-            if is_v6 {
-                builder = builder.local_address(Some(Ipv6Addr::UNSPECIFIED.into()))
-            } else {
-                builder = builder.local_address(Some(Ipv4Addr::UNSPECIFIED.into()))
-            }
-            let response = builder.build()?.get(&self.url).send()?.error_for_status()?;
-            let text = response.text()?;
+        async fn query(&self, is_v6: bool) -> Result<IpAddr> {
+            let text = get(&self.url, self.timeout, &self.http_client, is_v6).await?;
             let ip = text
                 .trim()
                 .parse::<IpAddr>()
                 .with_context(|| format!("invalid ip: {}", text))?;
-            if is_v6 && ip.is_ipv4() {
-                bail!("query v6 from {}, but got v4: {}", self.url, ip);
-            }
-            if !is_v6 && ip.is_ipv6() {
-                bail!("query v4 from {}, but got v6: {}", self.url, ip);
-            }
+            // The family is checked by the caller.
             Ok(ip)
         }
     }
 }
 
+mod httpjson {
+    use std::{net::IpAddr, time::Duration};
+
+    use super::{ifconfigio, IpProvider};
+    use crate::config::HttpClientConf;
+    use anyhow::{Context, Result};
+    use async_trait::async_trait;
+    use serde_json::Value;
+
+    pub(super) struct HttpJsonIpProvider {
+        pub(super) url: String,
+        pub(super) pointer: String,
+        pub(super) timeout: Duration,
+        pub(super) http_client: HttpClientConf,
+    }
+
+    #[async_trait]
+    impl IpProvider for HttpJsonIpProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn query(&self, is_v6: bool) -> Result<IpAddr> {
+            let text = ifconfigio::get(&self.url, self.timeout, &self.http_client, is_v6).await?;
+            let value: Value =
+                serde_json::from_str(&text).with_context(|| format!("invalid json: {}", text))?;
+            let ip = value
+                .pointer(&self.pointer)
+                .and_then(Value::as_str)
+                .with_context(|| format!("no string at {} of {}", self.pointer, text))?;
+            ip.trim()
+                .parse::<IpAddr>()
+                .with_context(|| format!("invalid ip: {}", ip))
+        }
+    }
+}
+
+mod httpregex {
+    use std::{net::IpAddr, time::Duration};
+
+    use super::{ifconfigio, IpProvider};
+    use crate::config::HttpClientConf;
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
+    use regex::Regex;
+
+    pub(super) struct HttpRegexIpProvider {
+        pub(super) url: String,
+        pub(super) regex: Regex,
+        pub(super) timeout: Duration,
+        pub(super) http_client: HttpClientConf,
+    }
+
+    #[async_trait]
+    impl IpProvider for HttpRegexIpProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn query(&self, is_v6: bool) -> Result<IpAddr> {
+            let text = ifconfigio::get(&self.url, self.timeout, &self.http_client, is_v6).await?;
+            // The first match of the family is used, a page may contain both an ipv4 and an ipv6.
+            for captures in self.regex.captures_iter(&text) {
+                let matched = captures.get(1).or_else(|| captures.get(0));
+                match matched.map(|m| m.as_str().trim().parse::<IpAddr>()) {
+                    Some(Ok(ip)) if ip.is_ipv6() == is_v6 => return Ok(ip),
+                    Some(Ok(ip)) => tracing::debug!("skip {} of the other family", ip),
+                    Some(Err(e)) => tracing::debug!("skip an invalid ip: {:?}", e),
+                    None => {}
+                }
+            }
+            bail!("no ip is matched by {} in: {}", self.regex, text.trim())
+        }
+    }
+}
+
 mod sslipio {
     use std::{net::IpAddr, time::Duration};
 
@@ -54,6 +143,7 @@ mod sslipio {
 
     use super::IpProvider;
     use anyhow::{bail, Result};
+    use async_trait::async_trait;
     use hickory_proto::rr::{RData, RecordType};
 
     pub(super) struct SslipIoIpProvider {
@@ -63,9 +153,10 @@ mod sslipio {
         pub(super) timeout: Duration,
     }
 
+    #[async_trait]
     impl IpProvider for SslipIoIpProvider {
         #[tracing::instrument(skip(self), err)]
-        fn query(&self, is_v6: bool) -> Result<IpAddr> {
+        async fn query(&self, is_v6: bool) -> Result<IpAddr> {
             let client = DnsClient::new(
                 &self.name_server_host,
                 self.name_server_port,
@@ -73,7 +164,9 @@ mod sslipio {
                 true,
                 false,
             )?;
-            let dns_response = client.query(&self.name, RecordType::TXT, Some(is_v6))?;
+            let dns_response = client
+                .query(&self.name, RecordType::TXT, Some(is_v6))
+                .await?;
             let mut ips = dns_response.answers().iter().filter_map(|r| {
                 if let Some(data) = r.data() {
                     match data {
@@ -116,18 +209,881 @@ mod sslipio {
     }
 }
 
+mod opendns {
+    use std::{net::IpAddr, time::Duration};
+
+    use crate::dns::DnsClient;
+
+    use super::IpProvider;
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
+    use hickory_proto::rr::{RData, RecordType};
+
+    pub(super) const DEFAULT_NAME_SERVER_HOST: &str = "resolver1.opendns.com";
+    pub(super) const DEFAULT_NAME: &str = "myip.opendns.com";
+
+    pub(super) struct OpenDnsIpProvider {
+        pub(super) name_server_host: String,
+        pub(super) name_server_port: Option<u16>,
+        pub(super) name: String,
+        pub(super) timeout: Duration,
+    }
+
+    #[async_trait]
+    impl IpProvider for OpenDnsIpProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn query(&self, is_v6: bool) -> Result<IpAddr> {
+            let client = DnsClient::new(
+                &self.name_server_host,
+                self.name_server_port,
+                self.timeout,
+                true,
+                false,
+            )?;
+            // The resolver answers the address which the query comes from, so the query is sent
+            // in the family.
+            let record_type = if is_v6 {
+                RecordType::AAAA
+            } else {
+                RecordType::A
+            };
+            let dns_response = client.query(&self.name, record_type, Some(is_v6)).await?;
+            let ip = dns_response.answers().iter().find_map(|r| match r.data() {
+                Some(RData::A(a)) => Some(IpAddr::from(a.0)),
+                Some(RData::AAAA(aaaa)) => Some(IpAddr::from(aaaa.0)),
+                _ => None,
+            });
+            match ip {
+                Some(ip) => Ok(ip),
+                None => bail!("no ip resolved"),
+            }
+        }
+    }
+}
+
+mod cloudflarewhoami {
+    use std::{net::IpAddr, time::Duration};
+
+    use crate::dns::DnsClient;
+
+    use super::IpProvider;
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
+    use hickory_proto::rr::{DNSClass, RData, RecordType};
+
+    const DEFAULT_NAME_SERVER_HOST_V4: &str = "1.1.1.1";
+    const DEFAULT_NAME_SERVER_HOST_V6: &str = "2606:4700:4700::1111";
+    const NAME: &str = "whoami.cloudflare";
+
+    pub(super) struct CloudflareWhoamiIpProvider {
+        pub(super) name_server_host: Option<String>,
+        pub(super) name_server_port: Option<u16>,
+        pub(super) timeout: Duration,
+    }
+
+    #[async_trait]
+    impl IpProvider for CloudflareWhoamiIpProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn query(&self, is_v6: bool) -> Result<IpAddr> {
+            let host = self.name_server_host.as_deref().unwrap_or(if is_v6 {
+                DEFAULT_NAME_SERVER_HOST_V6
+            } else {
+                DEFAULT_NAME_SERVER_HOST_V4
+            });
+            let client = DnsClient::new(host, self.name_server_port, self.timeout, true, false)?;
+            let dns_response = client
+                .query_in_class(NAME, RecordType::TXT, DNSClass::CH, Some(is_v6))
+                .await?;
+            for record in dns_response.answers() {
+                if let Some(RData::TXT(txt)) = record.data() {
+                    let data = txt.txt_data().concat();
+                    let text = String::from_utf8_lossy(&data);
+                    match text.trim_matches('"').parse::<IpAddr>() {
+                        Ok(ip) => return Ok(ip),
+                        Err(e) => tracing::warn!("txt data is not a valid ip: {} , {}", text, e),
+                    }
+                }
+            }
+            bail!("no ip resolved");
+        }
+    }
+}
+
+mod stun {
+    use std::{
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+        time::Duration,
+    };
+
+    use anyhow::{bail, Context, Result};
+    use async_trait::async_trait;
+    use openssl::rand::rand_bytes;
+    use tokio::{
+        net::{lookup_host, UdpSocket},
+        time::Instant,
+    };
+
+    use super::IpProvider;
+
+    const DEFAULT_PORT: u16 = 3478;
+    const MAGIC_COOKIE: u32 = 0x2112_a442;
+    const BINDING_REQUEST: u16 = 0x0001;
+    const BINDING_SUCCESS: u16 = 0x0101;
+    const MAPPED_ADDRESS: u16 = 0x0001;
+    const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+    /// Requests are retransmitted, as they are sent over udp.
+    const ATTEMPTS: u32 = 3;
+
+    pub(super) struct StunIpProvider {
+        pub(super) server: String,
+        pub(super) timeout: Duration,
+    }
+
+    impl StunIpProvider {
+        async fn server_addr(&self, is_v6: bool) -> Result<SocketAddr> {
+            let addrs: Vec<SocketAddr> = match lookup_host(self.server.as_str()).await {
+                Ok(addrs) => addrs.collect(),
+                // No port in the server.
+                Err(_) => lookup_host((self.server.as_str(), DEFAULT_PORT))
+                    .await?
+                    .collect(),
+            };
+            addrs
+                .into_iter()
+                .find(|a| a.is_ipv6() == is_v6)
+                .with_context(|| format!("no address of {} in the family", self.server))
+        }
+    }
+
+    /// Parse the mapped address in a response of a binding request.
+    fn parse_response(response: &[u8], transaction_id: &[u8; 12]) -> Result<IpAddr> {
+        if response.len() < 20 {
+            bail!("response too short: {} bytes", response.len());
+        }
+        let message_type = u16::from_be_bytes([response[0], response[1]]);
+        if message_type != BINDING_SUCCESS {
+            bail!("unexpected message type: {:#06x}", message_type);
+        }
+        if response[8..20] != transaction_id[..] {
+            bail!("transaction id mismatched");
+        }
+        let length = usize::from(u16::from_be_bytes([response[2], response[3]]));
+        let attributes = response
+            .get(20..20 + length)
+            .context("response truncated")?;
+        let mut mapped = None;
+        let mut offset = 0;
+        while offset + 4 <= attributes.len() {
+            let attribute_type = u16::from_be_bytes([attributes[offset], attributes[offset + 1]]);
+            let attribute_length = usize::from(u16::from_be_bytes([
+                attributes[offset + 2],
+                attributes[offset + 3],
+            ]));
+            let value = attributes
+                .get(offset + 4..offset + 4 + attribute_length)
+                .context("attribute truncated")?;
+            match attribute_type {
+                XOR_MAPPED_ADDRESS => return parse_address(value, Some(transaction_id)),
+                // Only used by old servers of RFC 3489.
+                MAPPED_ADDRESS => mapped = Some(parse_address(value, None)?),
+                _ => {}
+            }
+            // Attributes are padded to 4 bytes.
+            offset += 4 + attribute_length.div_ceil(4) * 4;
+        }
+        mapped.context("no mapped address in the response")
+    }
+
+    fn parse_address(value: &[u8], xor_with: Option<&[u8; 12]>) -> Result<IpAddr> {
+        let mut key = [0u8; 16];
+        if let Some(transaction_id) = xor_with {
+            key[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+            key[4..].copy_from_slice(transaction_id);
+        }
+        let xor = |bytes: &[u8]| -> Vec<u8> { bytes.iter().zip(key).map(|(b, k)| b ^ k).collect() };
+        match (value.get(1), value.len()) {
+            (Some(0x01), 8) => {
+                let octets: [u8; 4] = xor(&value[4..8]).try_into().unwrap();
+                Ok(Ipv4Addr::from(octets).into())
+            }
+            (Some(0x02), 20) => {
+                let octets: [u8; 16] = xor(&value[4..20]).try_into().unwrap();
+                Ok(Ipv6Addr::from(octets).into())
+            }
+            _ => bail!("invalid mapped address: {:?}", value),
+        }
+    }
+
+    #[async_trait]
+    impl IpProvider for StunIpProvider {
+        #[tracing::instrument(skip(self), fields(server = %self.server), err)]
+        async fn query(&self, is_v6: bool) -> Result<IpAddr> {
+            let server = self.server_addr(is_v6).await?;
+            let socket = if is_v6 {
+                UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await?
+            } else {
+                UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?
+            };
+            socket.connect(server).await?;
+
+            let mut transaction_id = [0u8; 12];
+            rand_bytes(&mut transaction_id)?;
+            let mut request = Vec::with_capacity(20);
+            request.extend(BINDING_REQUEST.to_be_bytes());
+            request.extend(0u16.to_be_bytes());
+            request.extend(MAGIC_COOKIE.to_be_bytes());
+            request.extend(transaction_id);
+
+            let deadline = Instant::now() + self.timeout;
+            let mut buf = [0u8; 1024];
+            for _ in 0..ATTEMPTS {
+                socket.send(&request).await?;
+                let wait = deadline
+                    .saturating_duration_since(Instant::now())
+                    .min(self.timeout / ATTEMPTS);
+                match tokio::time::timeout(wait, socket.recv(&mut buf)).await {
+                    Ok(Ok(size)) => match parse_response(&buf[..size], &transaction_id) {
+                        Ok(ip) => return Ok(ip),
+                        Err(e) => tracing::warn!("invalid response from {}: {:?}", server, e),
+                    },
+                    Ok(Err(e)) => return Err(e.into()),
+                    Err(_) => tracing::debug!("no response from {}, retry", server),
+                }
+                if Instant::now() >= deadline {
+                    break;
+                }
+            }
+            bail!("no response from {}", server)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// The transaction id of the sample responses of RFC 5769.
+        const TRANSACTION_ID: [u8; 12] = [
+            0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+        ];
+
+        #[test]
+        fn xor_mapped_address() {
+            let v4 = [0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43];
+            assert_eq!(
+                parse_address(&v4, Some(&TRANSACTION_ID)).unwrap(),
+                "192.0.2.1".parse::<IpAddr>().unwrap()
+            );
+            let v6 = [
+                0x00, 0x02, 0xa1, 0x47, 0x01, 0x13, 0xa9, 0xfa, 0xa5, 0xd3, 0xf1, 0x79, 0xbc, 0x25,
+                0xf4, 0xb5, 0xbe, 0xd2, 0xb9, 0xd9,
+            ];
+            assert_eq!(
+                parse_address(&v6, Some(&TRANSACTION_ID)).unwrap(),
+                "2001:db8:1234:5678:11:2233:4455:6677"
+                    .parse::<IpAddr>()
+                    .unwrap()
+            );
+        }
+
+        #[test]
+        fn mapped_address() {
+            let v4 = [0x00, 0x01, 0x80, 0x55, 0xc0, 0x00, 0x02, 0x01];
+            assert_eq!(
+                parse_address(&v4, None).unwrap(),
+                "192.0.2.1".parse::<IpAddr>().unwrap()
+            );
+            let mut v6 = vec![0x00, 0x02, 0x80, 0x55];
+            v6.extend("2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+            assert_eq!(
+                parse_address(&v6, None).unwrap(),
+                "2001:db8::1".parse::<IpAddr>().unwrap()
+            );
+            // The length doesn't match the family.
+            assert!(parse_address(&v4[..6], None).is_err());
+            assert!(parse_address(&v6[..8], None).is_err());
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) mod interface {
+    use std::{
+        fs,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    };
+
+    use anyhow::{bail, Context, Result};
+    use async_trait::async_trait;
+    use netlink_packet_core::{
+        NetlinkHeader, NetlinkMessage, NetlinkPayload, NLM_F_DUMP, NLM_F_REQUEST,
+    };
+    use netlink_packet_route::{
+        address::{CacheInfo, CacheInfoBuffer, Nla},
+        AddressMessage, RtnlMessage, AF_INET, AF_INET6, IFA_F_DADFAILED, IFA_F_DEPRECATED,
+        IFA_F_TEMPORARY, IFA_F_TENTATIVE, RT_SCOPE_UNIVERSE,
+    };
+    use netlink_packet_utils::traits::Parseable;
+    use netlink_sys::{protocols::NETLINK_ROUTE, Socket, SocketAddr};
+
+    use super::IpProvider;
+
+    /// An address of an interface, with its flags and valid lifetime in seconds.
+    pub(crate) struct Address {
+        pub(crate) ip: IpAddr,
+        pub(crate) flags: u32,
+        valid_lft: u32,
+    }
+
+    pub(super) struct InterfaceIpProvider {
+        pub(super) interface: Option<String>,
+        pub(super) prefix: Option<(IpAddr, u8)>,
+        pub(super) exclude_temporary: bool,
+        pub(super) include_deprecated: bool,
+    }
+
+    /// Dump addresses of a family, of the interface with `index` if it is set.
+    fn dump_addresses(is_v6: bool, index: Option<u32>) -> Result<Vec<Address>> {
+        let mut socket = Socket::new(NETLINK_ROUTE)?;
+        socket.bind_auto()?;
+        socket.connect(&SocketAddr::new(0, 0))?;
+
+        let mut message = AddressMessage::default();
+        message.header.family = if is_v6 { AF_INET6 } else { AF_INET } as u8;
+        let mut packet = NetlinkMessage::new(
+            NetlinkHeader::default(),
+            NetlinkPayload::from(RtnlMessage::GetAddress(message)),
+        );
+        packet.header.flags = NLM_F_DUMP | NLM_F_REQUEST;
+        packet.header.sequence_number = 1;
+        packet.finalize();
+        let mut buf = vec![0; packet.buffer_len()];
+        packet.serialize(&mut buf[..]);
+        socket.send(&buf[..], 0)?;
+
+        let mut addresses = vec![];
+        let mut receive_buffer = vec![0; 65536];
+        loop {
+            let size = socket.recv(&mut &mut receive_buffer[..], 0)?;
+            let mut offset = 0;
+            while offset < size {
+                let packet: NetlinkMessage<RtnlMessage> =
+                    NetlinkMessage::deserialize(&receive_buffer[offset..size])
+                        .context("invalid netlink message")?;
+                match packet.payload {
+                    NetlinkPayload::Done(_) => return Ok(addresses),
+                    NetlinkPayload::Error(e) => bail!("failed to dump addresses: {:?}", e),
+                    NetlinkPayload::InnerMessage(RtnlMessage::NewAddress(message))
+                        if message.header.scope == RT_SCOPE_UNIVERSE
+                            && index.is_none_or(|i| i == message.header.index) =>
+                    {
+                        addresses.extend(parse_address(&message));
+                    }
+                    _ => {}
+                }
+                if packet.header.length == 0 {
+                    break;
+                }
+                offset += packet.header.length as usize;
+            }
+        }
+    }
+
+    /// Global addresses of all interfaces, including tentative ones.
+    pub(crate) fn global_addresses() -> Result<Vec<Address>> {
+        let mut addresses = dump_addresses(false, None)?;
+        addresses.extend(dump_addresses(true, None)?);
+        Ok(addresses)
+    }
+
+    pub(crate) fn parse_address(message: &AddressMessage) -> Option<Address> {
+        let mut ip = None;
+        let mut flags = u32::from(message.header.flags);
+        let mut valid_lft = u32::MAX;
+        for nla in &message.nlas {
+            match nla {
+                // For point-to-point links, `Local` is the address of this side.
+                Nla::Local(bytes) => ip = ip_of(bytes),
+                Nla::Address(bytes) if ip.is_none() => ip = ip_of(bytes),
+                Nla::Flags(f) => flags = *f,
+                Nla::CacheInfo(bytes) => {
+                    if let Ok(info) =
+                        CacheInfoBuffer::new_checked(bytes).and_then(|buf| CacheInfo::parse(&buf))
+                    {
+                        valid_lft = info.ifa_valid as u32;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some(Address {
+            ip: ip?,
+            flags,
+            valid_lft,
+        })
+    }
+
+    fn ip_of(bytes: &[u8]) -> Option<IpAddr> {
+        if let Ok(octets) = <[u8; 4]>::try_from(bytes) {
+            Some(Ipv4Addr::from(octets).into())
+        } else if let Ok(octets) = <[u8; 16]>::try_from(bytes) {
+            Some(Ipv6Addr::from(octets).into())
+        } else {
+            None
+        }
+    }
+
+    #[async_trait]
+    impl IpProvider for InterfaceIpProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn query(&self, is_v6: bool) -> Result<IpAddr> {
+            let index = match &self.interface {
+                Some(interface) => {
+                    let path = format!("/sys/class/net/{}/ifindex", interface);
+                    let index = fs::read_to_string(&path)
+                        .with_context(|| format!("interface not found: {}", interface))?;
+                    Some(index.trim().parse::<u32>()?)
+                }
+                None => None,
+            };
+            let mut addresses = dump_addresses(is_v6, index)?;
+            addresses.retain(|a| {
+                if a.flags & (IFA_F_TENTATIVE | IFA_F_DADFAILED) != 0
+                    || (!self.include_deprecated && a.flags & IFA_F_DEPRECATED != 0)
+                    || (self.exclude_temporary && a.flags & IFA_F_TEMPORARY != 0)
+                {
+                    return false;
+                }
+                match &self.prefix {
+                    Some(prefix) => super::in_prefix(&a.ip, prefix),
+                    // Unique local addresses are not reachable from the internet.
+                    None => !matches!(a.ip, IpAddr::V6(ip) if ip.segments()[0] & 0xfe00 == 0xfc00),
+                }
+            });
+            // A temporary address is replaced regularly, and inbound connections are usually
+            // not accepted on it.
+            addresses.sort_by_key(|a| (a.flags & IFA_F_TEMPORARY != 0, u32::MAX - a.valid_lft));
+            tracing::debug!(
+                "candidates: {:?}",
+                addresses.iter().map(|a| a.ip).collect::<Vec<_>>()
+            );
+            match addresses.first() {
+                Some(address) => Ok(address.ip),
+                None => bail!("no address is matched"),
+            }
+        }
+    }
+}
+
+mod multi {
+    use std::net::IpAddr;
+
+    use anyhow::{anyhow, bail, Result};
+    use async_trait::async_trait;
+
+    use super::IpProvider;
+    use crate::metrics;
+
+    /// Query providers in order, until an ip is returned by `min_agreement` of them. It is also
+    /// the `Fallback` provider, with a `min_agreement` of 1.
+    pub(super) struct MultiIpProvider {
+        pub(super) providers: Vec<Box<dyn IpProvider>>,
+        pub(super) min_agreement: usize,
+        /// Query the rest of providers after an ip is agreed, to find disagreements of them.
+        pub(super) check_disagreement: bool,
+    }
+
+    #[async_trait]
+    impl IpProvider for MultiIpProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn query(&self, is_v6: bool) -> Result<IpAddr> {
+            let mut votes: Vec<(IpAddr, usize)> = vec![];
+            let mut agreed = None;
+            let mut last_err = None;
+            for provider in &self.providers {
+                let ip = match provider.query(is_v6).await {
+                    Ok(ip) => ip,
+                    Err(e) => {
+                        tracing::warn!("ip provider failed, try the next one: {:?}", e);
+                        last_err = Some(e);
+                        continue;
+                    }
+                };
+                let count = match votes.iter_mut().find(|(i, _)| *i == ip) {
+                    Some((_, count)) => {
+                        *count += 1;
+                        *count
+                    }
+                    None => {
+                        votes.push((ip, 1));
+                        1
+                    }
+                };
+                if agreed.is_none() && count >= self.min_agreement {
+                    agreed = Some(ip);
+                    if !self.check_disagreement {
+                        break;
+                    }
+                }
+            }
+            let Some(ip) = agreed else {
+                if votes.is_empty() {
+                    return Err(last_err.unwrap_or_else(|| anyhow!("no ip provider")));
+                }
+                bail!(
+                    "no ip is returned by {} providers: {:?}",
+                    self.min_agreement,
+                    votes
+                );
+            };
+            // The agreed ip is still used, but a persistent disagreement usually means a
+            // misconfiguration of NAT or proxy.
+            if votes.len() > 1 {
+                let count = metrics::inc_ip_provider_disagreements();
+                tracing::warn!(
+                    votes = ?votes,
+                    disagreements = count,
+                    "ip providers disagree, {} is used",
+                    ip
+                );
+            }
+            Ok(ip)
+        }
+    }
+}
+
+mod leasefile {
+    use std::{
+        fs,
+        net::IpAddr,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use anyhow::{bail, Context, Result};
+    use async_trait::async_trait;
+
+    use super::IpProvider;
+    use crate::config::LeaseFileFormat;
+
+    pub(super) struct LeaseFileIpProvider {
+        pub(super) format: LeaseFileFormat,
+        pub(super) interface: Option<String>,
+        pub(super) path: Option<String>,
+    }
+
+    /// Whether the `expire` of a dhclient lease is passed, e.g. `expire 4 2026/10/15 12:00:00;`
+    /// in UTC, or `expire epoch 1760529600;`.
+    fn is_expired(expire: &str, now: u64) -> bool {
+        let mut fields = expire.split_whitespace();
+        let timestamp = match (fields.next(), fields.next(), fields.next()) {
+            (Some("epoch"), Some(epoch), _) => epoch.parse::<u64>().ok(),
+            (Some(_), Some(date), Some(time)) => {
+                humantime::parse_rfc3339_weak(&format!("{} {}", date.replace('/', "-"), time))
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs())
+            }
+            // `never`
+            _ => None,
+        };
+        timestamp.is_some_and(|t| t <= now)
+    }
+
+    /// Get the address of the last lease which is not expired, in the format of dhclient.
+    fn parse_dhclient(content: &str, interface: Option<&str>, is_v6: bool) -> Option<IpAddr> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let mut ip = None;
+        let keyword = if is_v6 { "lease6 {" } else { "lease {" };
+        for lease in content.split(keyword).skip(1) {
+            let mut lease_ip = None;
+            let mut lease_interface = None;
+            let mut expired = false;
+            for line in lease
+                .lines()
+                .map(|l| l.trim().trim_end_matches([';', '{']).trim())
+            {
+                if let Some(name) = line.strip_prefix("interface ") {
+                    lease_interface = Some(name.trim_matches('"'));
+                } else if let Some(address) = line
+                    .strip_prefix("fixed-address ")
+                    .or_else(|| line.strip_prefix("iaaddr "))
+                {
+                    lease_ip = address.parse::<IpAddr>().ok();
+                } else if let Some(expire) = line.strip_prefix("expire ") {
+                    expired = expired || is_expired(expire, now);
+                }
+            }
+            if !expired && interface.is_none_or(|i| Some(i) == lease_interface) {
+                ip = lease_ip.or(ip);
+            }
+        }
+        ip
+    }
+
+    fn ifindex(interface: &str) -> Result<u32> {
+        let path = format!("/sys/class/net/{}/ifindex", interface);
+        let index = fs::read_to_string(&path)
+            .with_context(|| format!("interface not found: {}", interface))?;
+        Ok(index.trim().parse()?)
+    }
+
+    #[async_trait]
+    impl IpProvider for LeaseFileIpProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn query(&self, is_v6: bool) -> Result<IpAddr> {
+            let interface = self.interface.as_deref();
+            match self.format {
+                LeaseFileFormat::Dhclient => {
+                    let path = self.path.as_deref().unwrap_or(if is_v6 {
+                        "/var/lib/dhcp/dhclient6.leases"
+                    } else {
+                        "/var/lib/dhcp/dhclient.leases"
+                    });
+                    let content = fs::read_to_string(path)
+                        .with_context(|| format!("failed to read {}", path))?;
+                    parse_dhclient(&content, interface, is_v6)
+                        .with_context(|| format!("no valid lease in {}", path))
+                }
+                LeaseFileFormat::Networkd => {
+                    if is_v6 {
+                        bail!("no support of v6 in leases of systemd-networkd");
+                    }
+                    let path = match &self.path {
+                        Some(path) => path.clone(),
+                        None => {
+                            let interface = interface.context("interface is required")?;
+                            format!("/run/systemd/netif/leases/{}", ifindex(interface)?)
+                        }
+                    };
+                    let content = fs::read_to_string(&path)
+                        .with_context(|| format!("failed to read {}", path))?;
+                    let address = content
+                        .lines()
+                        .find_map(|l| l.strip_prefix("ADDRESS="))
+                        .with_context(|| format!("no ADDRESS in {}", path))?;
+                    address
+                        .trim()
+                        .parse::<IpAddr>()
+                        .with_context(|| format!("invalid ip: {}", address))
+                }
+                LeaseFileFormat::Ppp => {
+                    // pppd writes its pid in the first line, and the interface in the second.
+                    let path = self
+                        .path
+                        .clone()
+                        .unwrap_or_else(|| format!("/var/run/{}.pid", interface.unwrap_or("ppp0")));
+                    let content = fs::read_to_string(&path)
+                        .with_context(|| format!("failed to read {}", path))?;
+                    let interface = content
+                        .lines()
+                        .nth(1)
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty())
+                        .with_context(|| format!("no interface in {}, pppd is down", path))?;
+                    ppp_address(interface, is_v6).await
+                }
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn ppp_address(interface: &str, is_v6: bool) -> Result<IpAddr> {
+        super::interface::InterfaceIpProvider {
+            interface: Some(interface.to_string()),
+            prefix: None,
+            exclude_temporary: false,
+            include_deprecated: false,
+        }
+        .query(is_v6)
+        .await
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn ppp_address(_interface: &str, _is_v6: bool) -> Result<IpAddr> {
+        bail!("Ppp is only supported on linux")
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const LEASES: &str = r#"
+lease {
+  interface "eth0";
+  fixed-address 192.0.2.1;
+  expire 4 2000/01/06 12:00:00;
+}
+lease {
+  interface "eth0";
+  fixed-address 192.0.2.2;
+  expire 4 2999/01/01 12:00:00;
+}
+lease {
+  interface "eth1";
+  fixed-address 198.51.100.1;
+  expire never;
+}
+lease {
+  interface "eth0";
+  fixed-address 192.0.2.3;
+  expire epoch 946728000;
+}
+lease6 {
+  interface "eth0";
+  ia-na 1a:2b:3c:4d {
+    iaaddr 2001:db8::10 {
+      max-life 7200;
+    }
+  }
+}
+"#;
+
+        #[test]
+        fn expire() {
+            let now = 1_000_000_000;
+            assert!(is_expired("4 2000/01/06 12:00:00", now));
+            assert!(!is_expired("4 2999/01/01 12:00:00", now));
+            assert!(is_expired("epoch 999999999", now));
+            assert!(!is_expired("epoch 1000000001", now));
+            assert!(!is_expired("never", now));
+        }
+
+        #[test]
+        fn dhclient_leases() {
+            let ip = |s: &str| s.parse::<IpAddr>().ok();
+            // The last lease which is not expired.
+            assert_eq!(parse_dhclient(LEASES, None, false), ip("198.51.100.1"));
+            assert_eq!(parse_dhclient(LEASES, Some("eth0"), false), ip("192.0.2.2"));
+            assert_eq!(
+                parse_dhclient(LEASES, Some("eth1"), false),
+                ip("198.51.100.1")
+            );
+            assert_eq!(parse_dhclient(LEASES, Some("eth2"), false), None);
+            assert_eq!(
+                parse_dhclient(LEASES, Some("eth0"), true),
+                ip("2001:db8::10")
+            );
+            assert_eq!(parse_dhclient(LEASES, Some("eth1"), true), None);
+        }
+    }
+}
+
+mod derived {
+    use std::net::{IpAddr, Ipv6Addr};
+
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
+
+    use super::IpProvider;
+
+    pub(super) struct DerivedIpProvider {
+        pub(super) provider: Box<dyn IpProvider>,
+        pub(super) address: Ipv6Addr,
+        pub(super) prefix_length: u8,
+    }
+
+    #[async_trait]
+    impl IpProvider for DerivedIpProvider {
+        #[tracing::instrument(skip(self), fields(address = %self.address), err)]
+        async fn query(&self, is_v6: bool) -> Result<IpAddr> {
+            if !is_v6 {
+                bail!("Derived is only supported in a v6 section");
+            }
+            let IpAddr::V6(observed) = self.provider.query(is_v6).await? else {
+                bail!("query v6, but got v4");
+            };
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(self.prefix_length))
+                .unwrap_or(0);
+            let ip = (u128::from(observed) & mask) | (u128::from(self.address) & !mask);
+            let ip = Ipv6Addr::from(ip);
+            tracing::debug!("derive {} from {}", ip, observed);
+            Ok(ip.into())
+        }
+    }
+}
+
+tokio::task_local! {
+    /// Ips queried in the current run, keyed by the config of the provider and the family, see
+    /// `with_run_cache`.
+    static RUN_CACHE: Mutex<HashMap<(String, bool), Arc<OnceCell<IpAddr>>>>;
+}
+
+/// Run `f` with ips from ip providers cached until it returns, so a provider shared by many names
+/// is queried once in a run, even if names are renewed concurrently.
+pub async fn with_run_cache<F: Future>(f: F) -> F::Output {
+    RUN_CACHE.scope(Mutex::new(HashMap::new()), f).await
+}
+
+/// Query the ip by the provider of `ip_provider_type`, a successful result is cached in a run.
+pub async fn query_cached(
+    ip_provider_type: &IpProviderType,
+    config: &Config,
+    is_v6: bool,
+) -> Result<IpAddr> {
+    let key = (format!("{:?}", ip_provider_type), is_v6);
+    // It is not cached outside of a run.
+    let Ok(cell) = RUN_CACHE.try_with(|c| {
+        c.lock()
+            .expect("run cache is poisoned")
+            .entry(key)
+            .or_default()
+            .clone()
+    }) else {
+        return init_ip_provider(ip_provider_type, config)?
+            .query(is_v6)
+            .await;
+    };
+    if let Some(ip) = cell.get() {
+        tracing::debug!("use the ip queried in this run: {}", ip);
+        return Ok(*ip);
+    }
+    // A concurrent query of the same provider is waited for, instead of querying it again.
+    cell.get_or_try_init(|| async {
+        init_ip_provider(ip_provider_type, config)?
+            .query(is_v6)
+            .await
+    })
+    .await
+    .copied()
+}
+
 pub fn init_ip_provider(
     ip_provider_type: &IpProviderType,
-    _config: &Config,
+    config: &Config,
 ) -> Result<Box<dyn IpProvider>> {
     match ip_provider_type {
         IpProviderType::Static { ip } => Ok(Box::new(StaticIpProvider(*ip))),
-        IpProviderType::IfconfigIo { url, timeout } => {
-            Ok(Box::new(ifconfigio::IfconfigIoIpProvider {
-                url: url.clone(),
-                timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
-            }))
-        }
+        IpProviderType::IfconfigIo {
+            url,
+            timeout,
+            http_client,
+        } => Ok(Box::new(ifconfigio::IfconfigIoIpProvider {
+            url: url.clone(),
+            timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
+            http_client: http_client.clone(),
+        })),
+        IpProviderType::HttpJson {
+            url,
+            pointer,
+            timeout,
+            http_client,
+        } => Ok(Box::new(httpjson::HttpJsonIpProvider {
+            url: url.clone(),
+            pointer: pointer.clone(),
+            timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
+            http_client: http_client.clone(),
+        })),
+        IpProviderType::HttpRegex {
+            url,
+            regex,
+            timeout,
+            http_client,
+        } => Ok(Box::new(httpregex::HttpRegexIpProvider {
+            url: url.clone(),
+            regex: Regex::new(regex).with_context(|| format!("invalid regex: {}", regex))?,
+            timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
+            http_client: http_client.clone(),
+        })),
         IpProviderType::SslipIo {
             name_server_host,
             name_server_port,
@@ -139,17 +1095,271 @@ pub fn init_ip_provider(
             name: name.clone(),
             timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
         })),
+        IpProviderType::Stun { server, timeout } => Ok(Box::new(stun::StunIpProvider {
+            server: server.clone(),
+            timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
+        })),
+        #[cfg(target_os = "linux")]
+        IpProviderType::Interface {
+            interface,
+            prefix,
+            exclude_temporary,
+            include_deprecated,
+        } => Ok(Box::new(interface::InterfaceIpProvider {
+            interface: interface.clone(),
+            prefix: prefix.as_deref().map(parse_prefix).transpose()?,
+            exclude_temporary: *exclude_temporary,
+            include_deprecated: *include_deprecated,
+        })),
+        #[cfg(not(target_os = "linux"))]
+        IpProviderType::Interface { .. } => bail!("Interface is only supported on linux"),
+        IpProviderType::OpenDns {
+            name_server_host,
+            name_server_port,
+            name,
+            timeout,
+        } => Ok(Box::new(opendns::OpenDnsIpProvider {
+            name_server_host: name_server_host
+                .as_deref()
+                .unwrap_or(opendns::DEFAULT_NAME_SERVER_HOST)
+                .to_string(),
+            name_server_port: *name_server_port,
+            name: name.as_deref().unwrap_or(opendns::DEFAULT_NAME).to_string(),
+            timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
+        })),
+        IpProviderType::CloudflareWhoami {
+            name_server_host,
+            name_server_port,
+            timeout,
+        } => Ok(Box::new(cloudflarewhoami::CloudflareWhoamiIpProvider {
+            name_server_host: name_server_host.clone(),
+            name_server_port: *name_server_port,
+            timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
+        })),
+        IpProviderType::Fallback {
+            providers,
+            check_disagreement,
+        } => Ok(Box::new(multi::MultiIpProvider {
+            providers: providers
+                .iter()
+                .map(|p| init_ip_provider(p, config))
+                .collect::<Result<_>>()?,
+            min_agreement: 1,
+            check_disagreement: *check_disagreement,
+        })),
+        IpProviderType::LeaseFile {
+            format,
+            interface,
+            path,
+        } => Ok(Box::new(leasefile::LeaseFileIpProvider {
+            format: *format,
+            interface: interface.clone(),
+            path: path.clone(),
+        })),
+        IpProviderType::Derived {
+            provider,
+            address,
+            prefix_length,
+        } => {
+            if *prefix_length > 128 {
+                bail!("invalid prefix_length: {}", prefix_length);
+            }
+            Ok(Box::new(derived::DerivedIpProvider {
+                provider: init_ip_provider(provider, config)?,
+                address: *address,
+                prefix_length: *prefix_length,
+            }))
+        }
+        IpProviderType::Multi {
+            providers,
+            min_agreement,
+        } => {
+            if *min_agreement == 0 || *min_agreement > providers.len() {
+                bail!(
+                    "min_agreement should be in 1..={}, but got {}",
+                    providers.len(),
+                    min_agreement
+                );
+            }
+            Ok(Box::new(multi::MultiIpProvider {
+                providers: providers
+                    .iter()
+                    .map(|p| init_ip_provider(p, config))
+                    .collect::<Result<_>>()?,
+                min_agreement: *min_agreement,
+                check_disagreement: false,
+            }))
+        }
+        IpProviderType::Custom { name, options } => registry::ip_provider(name, options),
+        IpProviderType::Plugin {
+            plugin,
+            options,
+            timeout,
+        } => Ok(Box::new(PluginProvider::new(
+            config, plugin, options, *timeout,
+        )?)),
+    }
+}
+
+/// An address of an interface, e.g. to bind queries to it, the one which is preferred by the
+/// `Interface` ip provider is used.
+#[cfg(target_os = "linux")]
+pub async fn interface_address(interface: &str, is_v6: bool) -> Result<IpAddr> {
+    interface::InterfaceIpProvider {
+        interface: Some(interface.to_string()),
+        prefix: None,
+        exclude_temporary: false,
+        include_deprecated: false,
+    }
+    .query(is_v6)
+    .await
+    .with_context(|| {
+        format!(
+            "no {} address of {}",
+            if is_v6 { "v6" } else { "v4" },
+            interface
+        )
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn interface_address(interface: &str, _is_v6: bool) -> Result<IpAddr> {
+    bail!(
+        "binding to interface {} is only supported on linux",
+        interface
+    )
+}
+
+/// Parse a prefix like `2001:db8::/32`.
+pub fn parse_prefix(prefix: &str) -> Result<(IpAddr, u8)> {
+    let (ip, len) = prefix
+        .split_once('/')
+        .with_context(|| format!("prefix without length: {}", prefix))?;
+    let ip = ip
+        .parse::<IpAddr>()
+        .with_context(|| format!("invalid prefix: {}", prefix))?;
+    let len = len
+        .parse::<u8>()
+        .with_context(|| format!("invalid prefix length: {}", prefix))?;
+    if len > if ip.is_ipv4() { 32 } else { 128 } {
+        bail!("invalid prefix length: {}", prefix);
     }
+    Ok((ip, len))
 }
 
-pub trait IpProvider {
-    fn query(&self, is_v6: bool) -> Result<IpAddr>;
+pub fn in_prefix(ip: &IpAddr, (prefix, len): &(IpAddr, u8)) -> bool {
+    let mask = |bits: u32| u128::MAX.checked_shl(bits - u32::from(*len)).unwrap_or(0);
+    match (ip, prefix) {
+        (IpAddr::V4(ip), IpAddr::V4(prefix)) => {
+            let mask = mask(32) as u32;
+            u32::from(*ip) & mask == u32::from(*prefix) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(prefix)) => {
+            let mask = mask(128);
+            u128::from(*ip) & mask == u128::from(*prefix) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Ranges which are not reachable from the internet, IANA special-purpose address registries.
+const NON_GLOBAL_RANGES: &[(&str, &str)] = &[
+    ("0.0.0.0/8", "this network"),
+    ("10.0.0.0/8", "private"),
+    ("100.64.0.0/10", "CGNAT"),
+    ("127.0.0.0/8", "loopback"),
+    ("169.254.0.0/16", "link-local"),
+    ("172.16.0.0/12", "private"),
+    ("192.0.0.0/24", "IETF protocol assignments"),
+    ("192.0.2.0/24", "documentation"),
+    ("192.168.0.0/16", "private"),
+    ("198.18.0.0/15", "benchmarking"),
+    ("198.51.100.0/24", "documentation"),
+    ("203.0.113.0/24", "documentation"),
+    ("224.0.0.0/4", "multicast"),
+    ("240.0.0.0/4", "reserved"),
+    ("::/127", "unspecified or loopback"),
+    ("::ffff:0:0/96", "ipv4-mapped"),
+    ("100::/64", "discard-only"),
+    ("2001:db8::/32", "documentation"),
+    ("3fff::/20", "documentation"),
+    ("fc00::/7", "ULA"),
+    ("fe80::/10", "link-local"),
+    ("ff00::/8", "multicast"),
+];
+
+/// Reject an ip in a non-global range, unless it is in one of `allowed_prefixes`.
+pub fn validate(ip: IpAddr, allowed_prefixes: &[String]) -> Result<()> {
+    for prefix in allowed_prefixes {
+        if in_prefix(&ip, &parse_prefix(prefix)?) {
+            return Ok(());
+        }
+    }
+    for (range, kind) in NON_GLOBAL_RANGES {
+        if in_prefix(&ip, &parse_prefix(range)?) {
+            bail!(
+                "{} is in the {} range {}, it is rejected, add it to allowed_prefixes if it is expected",
+                ip,
+                kind,
+                range
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The well-known prefix of NAT64, RFC 6052.
+const NAT64_WELL_KNOWN_PREFIX: Ipv6Addr = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
+
+/// Get the ipv4 address embedded in an ipv4-mapped address (`::ffff:0:0/96`) or an address
+/// synthesized by NAT64 with the well-known prefix or `nat64_prefix`, only /96 is supported.
+fn embedded_ipv4(ip: &Ipv6Addr, nat64_prefix: Option<&Ipv6Addr>) -> Option<Ipv4Addr> {
+    if let Some(ipv4) = ip.to_ipv4_mapped() {
+        return Some(ipv4);
+    }
+    let segments = ip.segments();
+    [Some(&NAT64_WELL_KNOWN_PREFIX), nat64_prefix]
+        .into_iter()
+        .flatten()
+        .find(|prefix| prefix.segments()[..6] == segments[..6])
+        .map(|_| Ipv4Addr::from((u32::from(segments[6]) << 16) | u32::from(segments[7])))
+}
+
+/// Check the family of an ip from an ip provider. An ipv4 address in ipv6 is unwrapped in a v4
+/// query if `unwrap_ipv4_in_ipv6` is set, it is never accepted in a v6 query.
+pub fn check_family(
+    ip: IpAddr,
+    is_v6: bool,
+    unwrap_ipv4_in_ipv6: bool,
+    nat64_prefix: Option<&Ipv6Addr>,
+) -> Result<IpAddr> {
+    match ip {
+        IpAddr::V4(_) if is_v6 => bail!("query v6, but got v4: {}", ip),
+        IpAddr::V6(ipv6) => match embedded_ipv4(&ipv6, nat64_prefix) {
+            Some(ipv4) if !is_v6 && unwrap_ipv4_in_ipv6 => {
+                tracing::debug!("unwrap {} from {}", ipv4, ipv6);
+                Ok(ipv4.into())
+            }
+            Some(ipv4) if is_v6 => {
+                bail!("query v6, but got v4 {} in v6: {}", ipv4, ipv6)
+            }
+            _ if !is_v6 => bail!("query v4, but got v6: {}", ip),
+            _ => Ok(ip),
+        },
+        _ => Ok(ip),
+    }
+}
+
+#[async_trait]
+pub trait IpProvider: Send + Sync {
+    async fn query(&self, is_v6: bool) -> Result<IpAddr>;
 }
 
 struct StaticIpProvider(IpAddr);
 
+#[async_trait]
 impl IpProvider for StaticIpProvider {
-    fn query(&self, is_v6: bool) -> Result<IpAddr> {
+    async fn query(&self, is_v6: bool) -> Result<IpAddr> {
         if is_v6 && self.0.is_ipv4() {
             bail!("ipv4 ip is provided in a v6 section");
         }
@@ -159,3 +1369,60 @@ impl IpProvider for StaticIpProvider {
         Ok(self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn check_family_of_ips() {
+        assert_eq!(
+            check_family(ip("192.0.2.1"), false, false, None).unwrap(),
+            ip("192.0.2.1")
+        );
+        assert!(check_family(ip("192.0.2.1"), true, false, None).is_err());
+        assert_eq!(
+            check_family(ip("2001:db8::1"), true, false, None).unwrap(),
+            ip("2001:db8::1")
+        );
+        assert!(check_family(ip("2001:db8::1"), false, true, None).is_err());
+    }
+
+    #[test]
+    fn check_family_of_ipv4_mapped() {
+        let mapped = ip("::ffff:192.0.2.1");
+        assert_eq!(
+            check_family(mapped, false, true, None).unwrap(),
+            ip("192.0.2.1")
+        );
+        assert!(check_family(mapped, false, false, None).is_err());
+        assert!(check_family(mapped, true, true, None).is_err());
+    }
+
+    #[test]
+    fn check_family_of_nat64() {
+        let synthesized = ip("64:ff9b::c000:201");
+        assert_eq!(
+            check_family(synthesized, false, true, None).unwrap(),
+            ip("192.0.2.1")
+        );
+        assert!(check_family(synthesized, true, true, None).is_err());
+
+        let prefix = "2001:db8:64::".parse::<Ipv6Addr>().unwrap();
+        let synthesized = ip("2001:db8:64::c000:201");
+        // It is a plain v6 address without the prefix.
+        assert_eq!(
+            check_family(synthesized, true, true, None).unwrap(),
+            synthesized
+        );
+        assert_eq!(
+            check_family(synthesized, false, true, Some(&prefix)).unwrap(),
+            ip("192.0.2.1")
+        );
+        assert!(check_family(synthesized, true, true, Some(&prefix)).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+//! Logs as json lines. Fields of all spans of an event are flattened into it, e.g. `tenant` and
+//! `name` of the renewed name, so logs can be filtered by them without parsing text.
+
+use std::fmt;
+
+use serde_json::{Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::{
+        format::{JsonFields, Writer},
+        time::{FormatTime, SystemTime},
+        FmtContext, FormatEvent, FormattedFields,
+    },
+    registry::LookupSpan,
+};
+
+/// Span fields are formatted by `JsonFields`, so they can be merged into the line.
+pub(crate) struct JsonFormat {
+    pub(crate) timestamp: bool,
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+impl<S> FormatEvent<S, JsonFields> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut line = Map::new();
+        if self.timestamp {
+            let mut timestamp = String::new();
+            SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+            line.insert("timestamp".to_string(), timestamp.into());
+        }
+        line.insert(
+            "level".to_string(),
+            event.metadata().level().to_string().into(),
+        );
+        line.insert("target".to_string(), event.metadata().target().into());
+        if let Some(scope) = ctx.event_scope() {
+            let mut spans = vec![];
+            // Fields of an inner span override the ones of outer spans.
+            for span in scope.from_root() {
+                spans.push(span.name());
+                if let Some(fields) = span.extensions().get::<FormattedFields<JsonFields>>() {
+                    if let Ok(Value::Object(fields)) = serde_json::from_str(fields) {
+                        line.extend(fields);
+                    }
+                }
+            }
+            line.insert("spans".to_string(), spans.join(":").into());
+        }
+        event.record(&mut JsonVisitor(&mut line));
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
@@ -0,0 +1,1781 @@
+//! Renew dns records of names with the ip of this host. The renew engine is [`renew_all`], which
+//! renews names in the `name_conf_dir` of a [`config::Config`], by providers of
+//! [`query::QueryProvider`], [`ip::IpProvider`] and [`update::UpdateProvider`]. Custom providers
+//! can be registered by [`registry`].
+
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    fmt::{Display, Formatter},
+    fs::{self, DirEntry},
+    io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use config::{
+    ChangeDetection, Config, DiscoveryConf, ExternalChangePolicy, FamilyState, LogFormat, NameConf,
+    NameProvidersConf, NameState, PendingIp, QueryProviderType, RecordKind,
+};
+use data_encoding::HEXLOWER;
+use events::EventKind;
+use figment::{
+    providers::{Env, Format, Toml},
+    Figment,
+};
+use futures_util::{stream, StreamExt};
+use hook::HookEnv;
+use jsonlog::JsonFormat;
+use metrics::Counter;
+use notifier::Event;
+use query::QueryProvider;
+use state::StateStore;
+use tracing::Instrument;
+use tracing_subscriber::{
+    fmt::{self, format::JsonFields, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+use update::{
+    CompanionRecord, RecordDiff, RecordSnapshot, RenewAction, RenewOutcome, TemplateContext,
+    UpdateProvider,
+};
+
+mod api;
+mod audit;
+pub mod cli;
+mod clock;
+pub mod config;
+mod daemon;
+mod discovery;
+mod dns;
+mod events;
+mod healthcheck;
+mod hook;
+mod http;
+mod http3;
+pub mod ip;
+mod jsonlog;
+mod lock;
+mod logfile;
+mod metrics;
+#[cfg(target_os = "linux")]
+mod netwatch;
+mod notifier;
+mod plugin;
+mod prune;
+pub mod query;
+pub mod registry;
+mod script;
+mod sd_notify;
+mod state;
+mod status;
+mod suggest;
+mod systemd;
+mod tui;
+pub mod update;
+mod validate;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_MAX_CONCURRENCY: usize = 4;
+/// The shortest interval scheduled by a ttl, so a ttl of 0 doesn't renew a family in every cycle.
+const MIN_TTL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Some names failed, e.g. to renew, the others are done.
+#[derive(Debug)]
+pub struct NamesFailed(pub usize);
+
+impl Display for NamesFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} name(s) failed", self.0)
+    }
+}
+
+impl std::error::Error for NamesFailed {}
+
+/// Options of renewing names, shared by `run` and `daemon`.
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct Args {
+    /// The global `--config`.
+    #[arg(skip)]
+    pub config: PathBuf,
+
+    /// Keep running, and renew names whenever they are due.
+    #[arg(skip)]
+    pub daemon: bool,
+
+    /// Dry run, only check if update is needed, no update will be performed, and states are not
+    /// changed. Intended changes are printed.
+    #[arg(long, default_missing_value = "true")]
+    pub dry_run: bool,
+
+    /// Write events of renewing as json lines to this inherited file descriptor.
+    #[arg(long, value_name = "FD", conflicts_with = "events_file")]
+    pub events_fd: Option<i32>,
+
+    /// Append events of renewing as json lines to this file.
+    #[arg(long, value_name = "PATH")]
+    pub events_file: Option<PathBuf>,
+
+    /// Wait for another run holding the lock of the state dir up to this duration, e.g. `30s`,
+    /// instead of failing at once.
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub wait_lock: Option<Duration>,
+
+    /// Stop at the first name which failed to renew, the rest of names are left to the next run.
+    /// It can't be used in daemon mode.
+    #[arg(long, default_missing_value = "true")]
+    pub fail_fast: bool,
+
+    /// Only renew names (or aliases) matching this glob pattern, e.g. `*.lab.bar.com`, it can be
+    /// repeated.
+    #[arg(long = "name", value_name = "PATTERN")]
+    pub names: Vec<glob::Pattern>,
+
+    /// Renew names even if they are not due, and update records even if they already point to
+    /// the ip, without waiting for `confirmations`, e.g. after the config of an update provider is
+    /// changed. It can't be used in daemon mode.
+    #[arg(long, default_missing_value = "true")]
+    pub force: bool,
+
+    /// The format of logs, it overrides `log_format` of the config.
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// Check names even if they are not due, e.g. after the network is changed. Unlike `force`,
+    /// records are only updated if they don't point to the ip.
+    #[arg(skip)]
+    pub check_all: bool,
+
+    /// Paths of name configs which are changed, their names are checked even if they are not
+    /// due, like `check_all`.
+    #[arg(skip)]
+    pub changed_confs: Vec<PathBuf>,
+
+    /// Only renew one family of names, e.g. requested by the api.
+    #[arg(skip)]
+    pub request: Option<RenewRequest>,
+}
+
+/// A renew of one family of a name requested from outside, e.g. by the api.
+#[derive(Clone, Copy, Debug)]
+pub struct RenewRequest {
+    pub is_v6: bool,
+    /// It is used instead of the ip from the ip provider, it is still checked like a detected one.
+    pub ip: Option<IpAddr>,
+}
+
+impl Args {
+    /// If a name is selected by `--name`, all names are selected without it.
+    fn is_selected(&self, name: &str) -> bool {
+        self.names.is_empty() || self.names.iter().any(|p| p.matches(name))
+    }
+}
+
+pub fn init_config(args: &Args) -> Result<Config> {
+    const ENV_PREFIX: &str = "DNS_RENEW_";
+
+    let figment = Figment::new()
+        .merge(Toml::file(&args.config))
+        .merge(Env::raw().filter_map(|k| {
+            if k.starts_with(ENV_PREFIX) {
+                Some(k[ENV_PREFIX.len()..].into())
+            } else {
+                None
+            }
+        }));
+    let mut config: Config = figment.extract()?;
+    config.load_update_credentials_file()?;
+    Ok(config)
+}
+
+/// A config root, the main one has no tenant name.
+struct Tenant {
+    name: Option<String>,
+    config_path: PathBuf,
+    config: Config,
+}
+
+impl Tenant {
+    fn span(&self) -> tracing::Span {
+        tracing::info_span!("tenant", tenant = self.name.as_deref().unwrap_or("-"))
+    }
+
+    /// Load the config of a tenant, unlike the main config, it is not overridden by envs.
+    fn load_config(path: &Path) -> Result<Config> {
+        let mut config: Config = Figment::new()
+            .merge(Toml::file(path))
+            .extract()
+            .with_context(|| format!("failed to read from tenant config file: {:?}", path))?;
+        config.load_update_credentials_file()?;
+        if !config.tenants().is_empty() {
+            tracing::warn!("tenants in tenant config {:?} are ignored", path);
+        }
+        Ok(config)
+    }
+
+    /// The main config and configs of all tenants. A tenant is skipped if its config is invalid,
+    /// errors of skipped tenants are returned, so the caller decides whether it is fatal.
+    fn init_all(args: &Args, config: Config) -> (Vec<Tenant>, Vec<anyhow::Error>) {
+        let mut tenants = vec![];
+        let mut errors = vec![];
+        for (name, path) in config.tenants() {
+            match Self::load_config(path) {
+                Ok(tenant_config) => tenants.push(Tenant {
+                    name: Some(name.clone()),
+                    config_path: path.clone(),
+                    config: tenant_config,
+                }),
+                Err(e) => {
+                    tracing::error!("skip tenant {}: {:?}", name, e);
+                    errors.push(e.context(format!("invalid config of tenant {}", name)));
+                }
+            }
+        }
+        tenants.insert(
+            0,
+            Tenant {
+                name: None,
+                config_path: args.config.clone(),
+                config,
+            },
+        );
+        (tenants, errors)
+    }
+
+    fn reload_config(&self, args: &Args) -> Result<Config> {
+        match self.name {
+            Some(_) => Self::load_config(&self.config_path),
+            None => init_config(args),
+        }
+    }
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+fn log_layer(format: LogFormat, timestamp: bool, writer: BoxMakeWriter, ansi: bool) -> BoxedLayer {
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Text if timestamp => layer.boxed(),
+        LogFormat::Text => layer.without_time().boxed(),
+        LogFormat::Json => layer
+            .fmt_fields(JsonFields::new())
+            .event_format(JsonFormat { timestamp })
+            .boxed(),
+    }
+}
+
+fn init_log(args: &Args, config: &Config) -> Result<()> {
+    let timestamp = config.log_timestamp().unwrap_or(true);
+    let format = args.log_format.or(config.log_format()).unwrap_or_default();
+    let mut layers = vec![];
+    match config.log_file() {
+        Some(log_file) => {
+            if log_file.stdout().unwrap_or(true) {
+                layers.push(log_layer(
+                    format,
+                    timestamp,
+                    BoxMakeWriter::new(io::stdout),
+                    true,
+                ));
+            }
+            // No color codes in the file.
+            layers.push(log_layer(
+                format,
+                timestamp,
+                logfile::writer(log_file)?,
+                false,
+            ));
+        }
+        None => layers.push(log_layer(
+            format,
+            timestamp,
+            BoxMakeWriter::new(io::stdout),
+            true,
+        )),
+    }
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(EnvFilter::from_default_env())
+        .try_init()?;
+    Ok(())
+}
+
+/// The result of renewing one ip family of a name.
+pub(crate) struct RenewReport {
+    pub(crate) name: String,
+    /// `v4`, `v6` or `cname`.
+    pub(crate) family: &'static str,
+    /// The detected ip, it is `None` for a CNAME.
+    pub(crate) ip: Option<IpAddr>,
+    pub(crate) action: RenewAction,
+    pub(crate) diff: Option<RecordDiff>,
+}
+
+/// Renew names of a config which are due, the number of failed names is returned.
+pub async fn renew_all(args: &Args, config: &Config) -> Result<usize> {
+    let _lock = lock::acquire(config.name_state_dir(), args.wait_lock).await?;
+    ip::with_run_cache(async {
+        let state_store = StateStore::new(config)?;
+        let childrens = config
+            .name_conf_dir()
+            .read_dir()
+            .with_context(|| format!("{:?} not found", config.name_conf_dir()))?;
+
+        // Names are renewed concurrently, reports are kept in the order of names.
+        let max_concurrency = config
+            .max_concurrency()
+            .unwrap_or(DEFAULT_MAX_CONCURRENCY)
+            .max(1);
+        // With fail_fast, names which haven't started are skipped after a failure.
+        let failed = AtomicBool::new(false);
+        let mut reports = stream::iter(childrens)
+            .map(|child| {
+                let span = tracing::info_span!(
+                    "renew_name",
+                    path = child
+                        .as_ref()
+                        .ok()
+                        .and_then(|c| c.path().to_str().map(ToString::to_string))
+                        .unwrap_or_else(|| "invalid path".to_string())
+                );
+                let state_store = &state_store;
+                let failed = &failed;
+                async move {
+                    let mut reports = RunReports::default();
+                    if args.fail_fast && failed.load(Ordering::Relaxed) {
+                        tracing::info!("skipped after a failure");
+                        return reports;
+                    }
+                    if let Err(e) = renew_name(args, child, config, state_store, &mut reports).await
+                    {
+                        tracing::error!("failed to renew: {:?}", e);
+                        reports.failures += 1;
+                    }
+                    if reports.failures > 0 {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    reports
+                }
+                .instrument(span)
+            })
+            .buffered(max_concurrency)
+            .fold(RunReports::default(), |all, reports| async {
+                all.merge(reports)
+            })
+            .await;
+        if args.fail_fast && reports.failures > 0 {
+            tracing::error!("discovery and deletion of removed names are skipped after a failure");
+            return Ok(reports.failures);
+        }
+
+        if let Some(discovery_conf) = config.discovery() {
+            match discovery::discover(discovery_conf).await {
+                Ok(names) => {
+                    let discovered_reports = stream::iter(names)
+                        .map(|name| {
+                            let span = tracing::info_span!("renew_discovered_name", name = name);
+                            let state_store = &state_store;
+                            async move {
+                                let mut reports = RunReports::default();
+                                collect_reports(
+                                    renew_discovered_name(
+                                        args,
+                                        discovery_conf,
+                                        name,
+                                        config,
+                                        state_store,
+                                    )
+                                    .await,
+                                    &mut reports,
+                                );
+                                reports
+                            }
+                            .instrument(span)
+                        })
+                        .buffered(max_concurrency)
+                        .fold(RunReports::default(), |all, reports| async {
+                            all.merge(reports)
+                        })
+                        .await;
+                    reports = reports.merge(discovered_reports);
+                }
+                Err(e) => {
+                    tracing::error!("failed to discover names: {:?}", e);
+                    reports.failures += 1;
+                }
+            }
+        }
+
+        if let Err(e) = delete_removed_names(args, config, &state_store, &mut reports).await {
+            tracing::error!("failed to delete records of removed names: {:?}", e);
+            reports.failures += 1;
+        }
+
+        if args.dry_run {
+            // The report is written to stdout, so it can be asserted by provisioning pipelines.
+            for report in reports.reports {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    report.name,
+                    report.family,
+                    report.action,
+                    report.diff.map(|d| d.to_string()).unwrap_or_default()
+                );
+            }
+        }
+        Ok(reports.failures)
+    })
+    .await
+}
+
+/// A name config in `name_conf_dir` of a name or one of its aliases, with its source and the key
+/// of its state.
+pub(crate) struct FoundNameConf {
+    pub(crate) name_conf: NameConf,
+    source: String,
+    state_key: OsString,
+}
+
+/// Find the name config of a name, or one of its aliases, in `name_conf_dir`.
+pub(crate) fn find_name_conf(config: &Config, name: &str) -> Result<Option<FoundNameConf>> {
+    let childrens = config
+        .name_conf_dir()
+        .read_dir()
+        .with_context(|| format!("{:?} not found", config.name_conf_dir()))?;
+    for child in childrens {
+        let conf_path = child?.path();
+        if !(conf_path.is_file() && conf_path.extension().filter(|&ext| ext == "toml").is_some()) {
+            continue;
+        }
+        let source = fs::read_to_string(&conf_path)
+            .with_context(|| format!("failed to read name config file: {:?}", conf_path))?;
+        let mut name_conf = parse_name_conf(&source)
+            .with_context(|| format!("failed to read from name config file: {:?}", conf_path))?;
+        let mut state_key = conf_path
+            .file_stem()
+            .ok_or_else(|| anyhow!("it should have a file name"))?
+            .to_owned();
+        if name_conf.name() != name {
+            if !name_conf.aliases().iter().any(|a| a == name) {
+                continue;
+            }
+            state_key.push(format!("@{}", name));
+            name_conf.set_name(name.to_string());
+        }
+        return Ok(Some(FoundNameConf {
+            name_conf,
+            source,
+            state_key,
+        }));
+    }
+    Ok(None)
+}
+
+/// Renew a found name at once, by the same path as [`renew_all`], so its state is kept and the
+/// change is audited. Only the family of `args.request` is renewed if it is set.
+pub(crate) async fn renew_found(
+    args: &Args,
+    config: &Config,
+    found: &FoundNameConf,
+) -> Result<Vec<RenewReport>> {
+    let _lock = lock::acquire(config.name_state_dir(), args.wait_lock).await?;
+    ip::with_run_cache(async {
+        let state_store = StateStore::new(config)?;
+        let reports = renew_name_conf(
+            args,
+            &found.name_conf,
+            Some(&found.source),
+            &found.state_key,
+            config,
+            &state_store,
+        )
+        .await?;
+        Ok(reports.unwrap_or_default())
+    })
+    .await
+}
+
+/// Reports of names in a run, with the number of failures.
+#[derive(Default)]
+struct RunReports {
+    reports: Vec<RenewReport>,
+    failures: usize,
+}
+
+impl RunReports {
+    fn merge(mut self, other: Self) -> Self {
+        self.reports.extend(other.reports);
+        self.failures += other.failures;
+        self
+    }
+}
+
+fn collect_reports(result: Result<Option<Vec<RenewReport>>>, reports: &mut RunReports) {
+    match result {
+        Ok(Some(name_reports)) => {
+            if name_reports.iter().any(|r| r.action.is_changed()) {
+                tracing::info!("renew {} successfully", name_reports[0].name);
+            } else {
+                tracing::info!("skip path");
+            }
+            reports.reports.extend(name_reports);
+        }
+        Ok(None) => tracing::info!("skip path"),
+        Err(e) => {
+            tracing::error!("failed to renew: {:?}", e);
+            reports.failures += 1;
+        }
+    }
+}
+
+fn now() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+fn next(interval: &Duration) -> Result<u64> {
+    SystemTime::now()
+        .checked_add(*interval)
+        .ok_or_else(|| anyhow!("unable to get next time"))
+        .and_then(|t| {
+            t.duration_since(SystemTime::UNIX_EPOCH)
+                .with_context(|| "failed to get timestamp in creating NameState".to_string())
+        })
+        .map(|t| t.as_secs())
+}
+
+fn read_state(
+    state_store: &StateStore,
+    state_key: &OsStr,
+    name_conf: &NameConf,
+    force: bool,
+) -> Result<Option<NameState>> {
+    let name_state = match state_store.load(state_key)? {
+        Some(state) => {
+            clock::observe(state.epoch());
+            let now = now()?;
+            if state.name() != name_conf.name() {
+                tracing::info!(
+                    "name has been changed from [{}] to [{}] in state file",
+                    state.name(),
+                    name_conf.name()
+                );
+                NameState::new(name_conf.name(), next(name_conf.renew_interval())?)
+            } else if state.epoch() < clock::epoch()
+                || state.next() > now.saturating_add(longest_interval(name_conf).as_secs())
+            {
+                // The schedule was made with a wall clock that has jumped since, renew it now and
+                // start over, instead of waiting for a next which may be far in the future.
+                tracing::info!(
+                    "schedule of [{}] is made before a clock jump, renew it now",
+                    name_conf.name()
+                );
+                let mut state = state;
+                state.set_next(next(name_conf.renew_interval())?);
+                state.set_epoch(clock::epoch());
+                for family_state in state.families_mut().values_mut() {
+                    family_state.set_next(0);
+                }
+                state
+            } else if state.next() > now && !force {
+                tracing::debug!("renew of [{}] is not due", name_conf.name());
+                return Ok(None);
+            } else {
+                let mut state = state;
+                state.set_next(next(name_conf.renew_interval())?);
+                state
+            }
+        }
+        None => NameState::new(name_conf.name(), next(name_conf.renew_interval())?),
+    };
+    Ok(Some(name_state))
+}
+
+/// The interval to the next renew of a family, it is shortened to the ttl of its records with
+/// `schedule_by_ttl`.
+fn family_interval(name_conf: &NameConf, ttl: Option<u32>) -> Duration {
+    let interval = *name_conf.renew_interval();
+    match ttl.filter(|_| name_conf.schedule_by_ttl()) {
+        Some(ttl) => interval.min(Duration::from_secs(ttl.into()).max(MIN_TTL_INTERVAL)),
+        None => interval,
+    }
+}
+
+/// The longest time to the next renew of a name, a next beyond it is made before a clock jump.
+fn longest_interval(name_conf: &NameConf) -> Duration {
+    (*name_conf.renew_interval()).max(name_conf.retry_interval().unwrap_or_default())
+}
+
+/// The next attempt after `failures` consecutive failures, it is after `renew_interval` like a
+/// successful renew without `retry_interval`. Otherwise `retry_interval` is doubled for each
+/// failure after the first one, up to `renew_interval`, so a provider which keeps failing isn't
+/// hit at the pace of `retry_interval` forever.
+fn retry_next(name_conf: &NameConf, failures: u32) -> Result<u64> {
+    next(&retry_interval(name_conf, failures))
+}
+
+fn retry_interval(name_conf: &NameConf, failures: u32) -> Duration {
+    let renew_interval = *name_conf.renew_interval();
+    match name_conf.retry_interval() {
+        Some(retry_interval) => retry_interval
+            .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+            .min(renew_interval.max(retry_interval)),
+        None => renew_interval,
+    }
+}
+
+/// Renew the name of a name config and its aliases, each of them has its own state.
+async fn renew_name(
+    args: &Args,
+    entry: io::Result<DirEntry>,
+    config: &Config,
+    state_store: &StateStore,
+    reports: &mut RunReports,
+) -> Result<()> {
+    let entry = entry?;
+    let conf_path = entry.path();
+    if !(entry.file_type()?.is_file()
+        && conf_path.extension().filter(|&ext| ext == "toml").is_some())
+    {
+        tracing::info!("skip path");
+        return Ok(());
+    }
+
+    let conf_source = fs::read_to_string(&conf_path)
+        .with_context(|| format!("failed to read name config file: {:?}", conf_path))?;
+    let name_conf = parse_name_conf(&conf_source)
+        .with_context(|| format!("failed to read from name config file: {:?}", conf_path))?;
+    let state_key = conf_path
+        .file_stem()
+        .ok_or_else(|| anyhow!("it should have a file name"))?;
+    // The schedule of a changed name config is made with the old one.
+    let changed_args;
+    let args = if args.changed_confs.contains(&conf_path) {
+        changed_args = Args {
+            check_all: true,
+            ..args.clone()
+        };
+        &changed_args
+    } else {
+        args
+    };
+
+    let aliases = name_conf.aliases().clone();
+    collect_reports(
+        renew_name_conf(
+            args,
+            &name_conf,
+            Some(&conf_source),
+            state_key,
+            config,
+            state_store,
+        )
+        .await,
+        reports,
+    );
+    for alias in aliases {
+        let span = tracing::info_span!("alias", alias = alias.as_str());
+
+        let mut alias_state_key = state_key.to_owned();
+        alias_state_key.push(format!("@{}", alias));
+        let mut alias_conf = parse_name_conf(&conf_source)?;
+        alias_conf.set_name(alias);
+        collect_reports(
+            renew_name_conf(
+                args,
+                &alias_conf,
+                Some(&conf_source),
+                &alias_state_key,
+                config,
+                state_store,
+            )
+            .instrument(span)
+            .await,
+            reports,
+        );
+    }
+    Ok(())
+}
+
+/// The shortest renew interval of all names in all tenants.
+/// Paths of name configs of a tenant, with the template of discovered names.
+fn name_conf_paths(config: &Config) -> Result<Vec<PathBuf>> {
+    let mut paths = config
+        .name_conf_dir()
+        .read_dir()
+        .with_context(|| format!("{:?} not found", config.name_conf_dir()))?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|p| p.is_file() && p.extension().filter(|&ext| ext == "toml").is_some());
+    if let Some(discovery_conf) = config.discovery() {
+        paths.push(discovery_conf.template().clone());
+    }
+    Ok(paths)
+}
+
+fn shortest_renew_interval(tenants: &[Tenant]) -> Result<Option<Duration>> {
+    let mut shortest: Option<Duration> = None;
+    for tenant in tenants {
+        for path in name_conf_paths(&tenant.config)? {
+            let interval = *read_name_conf(&path)?.renew_interval();
+            shortest = Some(shortest.map_or(interval, |s| s.min(interval)));
+        }
+    }
+    Ok(shortest)
+}
+
+fn read_name_conf(conf_path: &Path) -> Result<NameConf> {
+    tracing::debug!("reading NameConf from {:?}", conf_path);
+    Figment::new()
+        .merge(Toml::file(conf_path))
+        .extract::<NameConf>()
+        .with_context(|| format!("failed to read from name config file: {:?}", conf_path))
+}
+
+fn parse_name_conf(source: &str) -> Result<NameConf> {
+    Ok(Figment::new()
+        .merge(Toml::string(source))
+        .extract::<NameConf>()?)
+}
+
+/// The providers of a family, the other one is used if it is not set and `shared` is set.
+fn name_providers_conf(name_conf: &NameConf, is_v6: bool) -> Option<&NameProvidersConf> {
+    let (this, other) = if is_v6 {
+        (name_conf.v6(), name_conf.v4())
+    } else {
+        (name_conf.v4(), name_conf.v6())
+    };
+    this.as_ref().or_else(|| {
+        if name_conf.shared() {
+            other.as_ref()
+        } else {
+            None
+        }
+    })
+}
+
+/// `conf_source` is the source of `name_conf`, it is kept in the state with `delete_on_disable`.
+async fn renew_name_conf(
+    args: &Args,
+    name_conf: &NameConf,
+    conf_source: Option<&str>,
+    state_key: &OsStr,
+    config: &Config,
+    state_store: &StateStore,
+) -> Result<Option<Vec<RenewReport>>> {
+    if !args.is_selected(name_conf.name()) {
+        tracing::debug!("{} is not selected", name_conf.name());
+        return Ok(None);
+    }
+    metrics::set_labels(
+        name_conf.name(),
+        notifier::route(config.notification(), name_conf.tags()).map(|r| r.labels()),
+    );
+    let mut name_state = match read_state(
+        state_store,
+        state_key,
+        name_conf,
+        args.force || args.check_all,
+    )? {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+
+    name_state.set_conf(
+        conf_source
+            .filter(|_| name_conf.delete_on_disable())
+            .map(ToString::to_string),
+    );
+
+    let v4_name_providers_conf = name_providers_conf(name_conf, false).filter(|c| c.enabled());
+    let v6_name_providers_conf = name_providers_conf(name_conf, true).filter(|c| c.enabled());
+
+    // States of families which are no longer renewed are dropped, so they don't hold `next`.
+    name_state.families_mut().retain(|family, _| {
+        name_conf.record() != RecordKind::Cname
+            && match family.as_str() {
+                "v4" => v4_name_providers_conf.is_some(),
+                "v6" => v6_name_providers_conf.is_some(),
+                _ => false,
+            }
+    });
+
+    let now = now()?;
+    let mut reports = vec![];
+    let result = async {
+        if name_conf.delete_on_disable() {
+            for is_v6 in [false, true] {
+                if let Some(c) = name_providers_conf(name_conf, is_v6).filter(|c| !c.enabled()) {
+                    reports.extend(
+                        delete_records(args, name_conf, c, config, is_v6, &mut name_state).await?,
+                    );
+                }
+            }
+        }
+        if name_conf.record() == RecordKind::Cname {
+            // A CNAME is for both families, providers of v4 are preferred.
+            if let Some(c) = v4_name_providers_conf.or(v6_name_providers_conf) {
+                let report = renew_cname(args, name_conf, c, config)
+                    .await
+                    .inspect_err(|e| {
+                        let mut event =
+                            events::Event::new(EventKind::Failed, name_conf.name(), None);
+                        event.error = Some(format!("{:#}", e));
+                        events::emit(&event, args.dry_run);
+                    })?;
+                reports.push(report);
+            }
+            return Ok(());
+        }
+        // Families are renewed independently, a failed family doesn't stop the other one, and its
+        // state is restored, so it isn't advanced by a partial renew.
+        let mut errors = vec![];
+        for (name_providers_conf, is_v6) in [
+            (v4_name_providers_conf, false),
+            (v6_name_providers_conf, true),
+        ] {
+            if let Some(name_providers_conf) = name_providers_conf {
+                if args.request.is_some_and(|r| r.is_v6 != is_v6) {
+                    continue;
+                }
+                let family = if is_v6 { "v6" } else { "v4" };
+                if !(args.force || args.check_all)
+                    && name_state
+                        .families()
+                        .get(family)
+                        .is_some_and(|s| s.next() > now)
+                {
+                    tracing::debug!("renew of {} of [{}] is not due", family, name_conf.name());
+                    continue;
+                }
+                let snapshot = name_state.snapshot_family(family);
+                metrics::inc(Counter::Attempts, name_conf.name(), family);
+                match renew(
+                    args,
+                    name_conf,
+                    name_providers_conf,
+                    config,
+                    is_v6,
+                    &mut name_state,
+                )
+                .await
+                {
+                    Ok((ip, outcome, ttl)) => {
+                        metrics::inc(Counter::Successes, name_conf.name(), family);
+                        let family_state = name_state
+                            .families_mut()
+                            .entry(family.to_string())
+                            .or_default();
+                        family_state.set_next(next(&family_interval(name_conf, ttl))?);
+                        family_state.set_last_renew(Some(now));
+                        family_state.set_last_error(None);
+                        family_state.set_failures(0);
+                        if !args.dry_run && outcome.action.is_changed() {
+                            metrics::inc(Counter::IpChanges, name_conf.name(), family);
+                            family_state.record_change(ip, now);
+                        }
+                        if !args.dry_run && outcome.action == RenewAction::Create {
+                            family_state.set_created_at(Some(now));
+                        }
+                        reports.push(RenewReport {
+                            name: name_conf.name().clone(),
+                            family,
+                            ip: Some(ip),
+                            action: outcome.action,
+                            diff: outcome.diff,
+                        });
+                    }
+                    Err(e) => {
+                        metrics::inc(Counter::Failures, name_conf.name(), family);
+                        let mut event =
+                            events::Event::new(EventKind::Failed, name_conf.name(), Some(is_v6));
+                        event.error = Some(format!("{:#}", e));
+                        events::emit(&event, args.dry_run);
+                        name_state.restore_family(family, snapshot);
+                        // The ip of the last successful renew is kept.
+                        let family_state = name_state
+                            .families_mut()
+                            .entry(family.to_string())
+                            .or_default();
+                        family_state.set_last_error(Some(format!("{:#}", e)));
+                        family_state.set_failures(family_state.failures().saturating_add(1));
+                        family_state.set_next(retry_next(name_conf, family_state.failures())?);
+                        errors.push(e.context(format!("failed to renew {}", family)));
+                    }
+                }
+            }
+        }
+        match errors
+            .into_iter()
+            .reduce(|a, b| anyhow!("{:#}; {:#}", a, b))
+        {
+            Some(e) => Err(e),
+            None => Ok::<_, anyhow::Error>(()),
+        }
+    }
+    .await;
+
+    // Changes of renewed families are applied, even if the other family fails.
+    if !args.dry_run {
+        for report in reports.iter().filter(|r| r.action.is_changed()) {
+            query::invalidate_answers(&report.name);
+            notifier::notify(
+                config,
+                name_conf.tags(),
+                &Event::Changed {
+                    name: &report.name,
+                    target: match (report.ip, name_conf.cname_target()) {
+                        (Some(ip), _) => ip.to_string(),
+                        (None, target) => target.clone().unwrap_or_default(),
+                    },
+                    diff: report.diff.as_ref(),
+                },
+            )
+            .await;
+        }
+    }
+    // Only ips pushed to, or seen in, the records are shown, not ones waiting for confirmations.
+    let ips: Vec<IpAddr> = name_state
+        .applied_ips()
+        .iter()
+        .filter(|(family, _)| name_state.families().contains_key(*family))
+        .map(|(_, ip)| *ip)
+        .collect();
+    name_state.set_ips(ips);
+    let families_next = name_state.families().values().map(FamilyState::next).min();
+
+    match result {
+        Ok(()) => {
+            if let Some(families_next) = families_next {
+                name_state.set_next(families_next);
+            }
+            name_state.set_last_renew(Some(now));
+            name_state.set_last_error(None);
+            let failures = name_state.failures();
+            name_state.set_failures(0);
+            // A dry run doesn't advance the state, the name is still due in the next run.
+            if !args.dry_run {
+                state_store.save(state_key, &name_state)?;
+            }
+            if failures > 0 && !args.dry_run {
+                notifier::notify(
+                    config,
+                    name_conf.tags(),
+                    &Event::Recovered {
+                        name: name_conf.name(),
+                        failures,
+                    },
+                )
+                .await;
+            }
+            Ok(Some(reports))
+        }
+        Err(e) => {
+            // It is retried after `retry_interval` with a backoff, or `renew_interval`, the error
+            // is kept for showing the status.
+            name_state.set_failures(name_state.failures().saturating_add(1));
+            name_state.set_next(
+                retry_next(name_conf, name_state.failures())?
+                    .min(families_next.unwrap_or(u64::MAX)),
+            );
+            // A cached zone id may be stale, resolve it again in the next renew.
+            name_state.zone_ids_mut().clear();
+            name_state.set_last_error(Some(format!("{:#}", e)));
+            if !args.dry_run {
+                state_store.save(state_key, &name_state)?;
+                notifier::notify(
+                    config,
+                    name_conf.tags(),
+                    &Event::Failed {
+                        name: name_conf.name(),
+                        error: &e,
+                    },
+                )
+                .await;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Renew a name discovered from dns with the template name config.
+async fn renew_discovered_name(
+    args: &Args,
+    discovery_conf: &DiscoveryConf,
+    name: String,
+    config: &Config,
+    state_store: &StateStore,
+) -> Result<Option<Vec<RenewReport>>> {
+    let template_path = discovery_conf.template();
+    let mut name_conf = Figment::new()
+        .merge(Toml::file(template_path))
+        .extract::<NameConf>()
+        .with_context(|| format!("failed to read from template file: {:?}", template_path))?;
+    let state_key = OsString::from(format!("discovered-{}", name));
+    name_conf.set_name(name);
+
+    renew_name_conf(args, &name_conf, None, &state_key, config, state_store).await
+}
+
+/// Delete records of a disabled family, if they were renewed before.
+async fn delete_records(
+    args: &Args,
+    name_conf: &NameConf,
+    name_providers_conf: &NameProvidersConf,
+    config: &Config,
+    is_v6: bool,
+    name_state: &mut NameState,
+) -> Result<Option<RenewReport>> {
+    let family = if is_v6 { "v6" } else { "v4" };
+    // The family is already dropped from `ips`, its applied ip is kept until the delete succeeds.
+    let Some(ip) = name_state.applied_ips().get(family).copied().or_else(|| {
+        name_state
+            .ips()
+            .iter()
+            .find(|ip| ip.is_ipv6() == is_v6)
+            .copied()
+    }) else {
+        return Ok(None);
+    };
+    if args.dry_run {
+        tracing::info!("dry run, delete of {} is skipped", name_conf.name());
+    } else {
+        let change = audit::Change {
+            name: name_conf.name(),
+            family,
+            old: vec![ip.to_string()],
+            new: None,
+        };
+        for update_provider_type in name_providers_conf.update_provider_type().as_slice() {
+            let result = async {
+                update::init_update_provider(
+                    update_provider_type,
+                    config,
+                    name_conf,
+                    name_state.zone_ids_mut(),
+                )
+                .await?
+                .delete(name_conf.name(), is_v6)
+                .await
+            }
+            .await;
+            audit::record(
+                config,
+                &change,
+                update_provider_type,
+                result.as_ref().map(|_| RenewAction::Delete),
+            );
+            result?;
+        }
+        name_state.rrset_checksums_mut().remove(family);
+        name_state.applied_ips_mut().remove(family);
+        tracing::info!("records of {} are deleted", name_conf.name());
+    }
+    Ok(Some(RenewReport {
+        name: name_conf.name().clone(),
+        family,
+        ip: Some(ip),
+        action: RenewAction::Delete,
+        diff: None,
+    }))
+}
+
+/// Delete records of names whose config is removed, by the config kept in their states. A state
+/// is removed after its records are deleted.
+async fn delete_removed_names(
+    args: &Args,
+    config: &Config,
+    state_store: &StateStore,
+    reports: &mut RunReports,
+) -> Result<()> {
+    for (state_key, mut name_state) in state_store.load_all()? {
+        let Some(conf_source) = name_state.conf().clone() else {
+            continue;
+        };
+        if !args.is_selected(name_state.name()) {
+            continue;
+        }
+        // The state of an alias is keyed by `<stem>@<alias>`.
+        let key = state_key.to_string_lossy();
+        let stem = key.split('@').next().unwrap_or_default();
+        if config
+            .name_conf_dir()
+            .join(format!("{}.toml", stem))
+            .exists()
+        {
+            continue;
+        }
+
+        let span = tracing::info_span!("delete_removed_name", name = name_state.name());
+        let result = delete_name_records(args, config, &conf_source, &mut name_state)
+            .instrument(span.clone())
+            .await;
+        let _enter = span.enter();
+        match result {
+            Ok(name_reports) => {
+                reports.reports.extend(name_reports);
+                if !args.dry_run {
+                    state_store.remove(&state_key)?;
+                }
+            }
+            Err(e) => {
+                tracing::error!("failed to delete records: {:?}", e);
+                reports.failures += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Delete records of both families of a name, by providers in `conf_source`, which is kept in the
+/// state with `delete_on_disable`.
+async fn delete_name_records(
+    args: &Args,
+    config: &Config,
+    conf_source: &str,
+    name_state: &mut NameState,
+) -> Result<Vec<RenewReport>> {
+    let mut name_conf = parse_name_conf(conf_source)?;
+    name_conf.set_name(name_state.name().clone());
+    let mut reports = vec![];
+    for is_v6 in [false, true] {
+        if let Some(c) = name_providers_conf(&name_conf, is_v6) {
+            reports.extend(delete_records(args, &name_conf, c, config, is_v6, name_state).await?);
+        }
+    }
+    Ok(reports)
+}
+
+/// Point the name to `cname_target` by a CNAME, instead of A/AAAA records.
+#[tracing::instrument(skip(args, name_conf, name_providers_conf, config), fields(name = name_conf.name()), err)]
+async fn renew_cname(
+    args: &Args,
+    name_conf: &NameConf,
+    name_providers_conf: &NameProvidersConf,
+    config: &Config,
+) -> Result<RenewReport> {
+    let Some(target) = name_conf.cname_target() else {
+        bail!("cname_target of {} is not set", name_conf.name());
+    };
+    events::emit(
+        &events::Event::new(EventKind::Started, name_conf.name(), None),
+        args.dry_run,
+    );
+    let query_provider =
+        init_query_provider(name_conf, name_providers_conf, config, &mut BTreeMap::new()).await?;
+    let current = query_provider.query_cname(name_conf.name()).await?;
+    tracing::debug!("current CNAME of domain: {:?}", current);
+
+    let is_dummy_query = matches!(
+        name_providers_conf.query_provider_type(),
+        QueryProviderType::Dummy
+    );
+    let normalize = |name: &str| name.trim_end_matches('.').to_lowercase();
+    let action = match &current {
+        Some(current) if !args.force && normalize(current) == normalize(target) => {
+            RenewAction::Unchanged
+        }
+        None if !is_dummy_query => RenewAction::Create,
+        _ => RenewAction::Update,
+    };
+    let mut event = events::Event::new(EventKind::Decided, name_conf.name(), None);
+    event.action = Some(action);
+    events::emit(&event, args.dry_run);
+
+    let snapshot = |content: &str| RecordSnapshot {
+        content: Some(content.to_string()),
+        ..Default::default()
+    };
+    let queried_diff = RecordDiff {
+        before: current.as_deref().map(snapshot),
+        after: snapshot(target),
+    };
+    let report = |action, diff| RenewReport {
+        name: name_conf.name().clone(),
+        family: "cname",
+        ip: None,
+        action,
+        diff,
+    };
+    if action == RenewAction::Unchanged {
+        return Ok(report(action, None));
+    }
+    if args.dry_run {
+        tracing::info!(
+            "dry run, {} of {} is skipped: {}",
+            action,
+            name_conf.name(),
+            queried_diff
+        );
+        return Ok(report(action, Some(queried_diff)));
+    }
+
+    // Zone ids are not cached, since a CNAME is renewed rarely.
+    let mut zone_ids = Default::default();
+    let mut outcome: Option<RenewOutcome> = None;
+    let change = audit::Change {
+        name: name_conf.name(),
+        family: "cname",
+        old: current.iter().cloned().collect(),
+        new: Some(target.clone()),
+    };
+    for update_provider_type in name_providers_conf.update_provider_type().as_slice() {
+        let result = async {
+            update::init_update_provider(update_provider_type, config, name_conf, &mut zone_ids)
+                .await?
+                .update_cname(name_conf.name(), target)
+                .await
+                .map(|o| o.or_decided(action))
+        }
+        .await;
+        audit::record(
+            config,
+            &change,
+            update_provider_type,
+            result.as_ref().map(|o| o.action),
+        );
+        let provider_outcome = result?;
+        if !outcome.as_ref().is_some_and(|o| o.action.is_changed()) {
+            outcome = Some(provider_outcome);
+        }
+    }
+    let Some(mut outcome) = outcome else {
+        bail!("no update provider of {}", name_conf.name());
+    };
+    if outcome.action.is_changed() {
+        let diff = outcome.diff.get_or_insert(queried_diff);
+        tracing::info!("CNAME of {} is changed: {}", name_conf.name(), diff);
+        let mut event = events::Event::new(EventKind::Updated, name_conf.name(), None);
+        event.action = Some(outcome.action);
+        event.diff = Some(diff);
+        events::emit(&event, args.dry_run);
+    }
+    Ok(report(outcome.action, outcome.diff))
+}
+
+#[tracing::instrument(skip(args, name_conf, name_providers_conf, config, name_state), fields(name = name_conf.name()), err, ret)]
+async fn renew(
+    args: &Args,
+    name_conf: &NameConf,
+    name_providers_conf: &NameProvidersConf,
+    config: &Config,
+    is_v6: bool,
+    name_state: &mut NameState,
+) -> Result<(IpAddr, RenewOutcome, Option<u32>)> {
+    events::emit(
+        &events::Event::new(EventKind::Started, name_conf.name(), Some(is_v6)),
+        args.dry_run,
+    );
+    let family = if is_v6 { "v6" } else { "v4" };
+    let from_state = name_conf.detection() == ChangeDetection::State;
+    // The ttl of the records, which is only known by querying them.
+    let (ips, ttl) = if from_state {
+        let ips = name_state
+            .applied_ips()
+            .get(family)
+            .into_iter()
+            .copied()
+            .collect::<Vec<_>>();
+        tracing::debug!("last applied ips of domain: {:?}", ips);
+        (ips, None)
+    } else {
+        let query_provider = init_query_provider(
+            name_conf,
+            name_providers_conf,
+            config,
+            name_state.zone_ids_mut(),
+        )
+        .await?;
+        let (ips, ttl) = metrics::timed(
+            "query",
+            name_conf.name(),
+            query_provider.query_with_ttl(name_conf.name(), is_v6),
+        )
+        .await?;
+        tracing::debug!("current ips of domain: {:?}, ttl: {:?}", ips, ttl);
+        (ips, ttl)
+    };
+
+    let detected = match args.request.and_then(|r| r.ip) {
+        Some(ip) => ip,
+        None => {
+            metrics::timed(
+                "ip",
+                name_conf.name(),
+                ip::query_cached(name_providers_conf.ip_provider_type(), config, is_v6),
+            )
+            .await?
+        }
+    };
+    let ip = ip::check_family(
+        detected,
+        is_v6,
+        name_providers_conf.unwrap_ipv4_in_ipv6(),
+        name_providers_conf.nat64_prefix().as_ref(),
+    )?;
+    if name_providers_conf.reject_non_global() {
+        ip::validate(ip, name_providers_conf.allowed_prefixes())?;
+    }
+    tracing::debug!("current ip: {}", ip);
+
+    let decided = |action| {
+        let mut event = events::Event::new(EventKind::Decided, name_conf.name(), Some(is_v6));
+        event.ip = Some(ip);
+        event.current_ips = Some(&ips);
+        event.action = Some(action);
+        events::emit(&event, args.dry_run);
+    };
+    // Records are unknown to a dummy query provider, or if they are not queried at all.
+    let is_blind = from_state
+        || matches!(
+            name_providers_conf.query_provider_type(),
+            QueryProviderType::Dummy
+        );
+    let current_checksum = rrset_checksum(&ips);
+    if args.force {
+        tracing::info!("update of {} is forced", name_conf.name());
+    } else if !is_blind
+        && check_external_change(args, config, name_conf, family, &ips, name_state).await
+    {
+        decided(RenewAction::Unchanged);
+        return Ok((ip, RenewOutcome::unchanged(), ttl));
+    }
+    if !args.force && ips.contains(&ip) {
+        decided(RenewAction::Unchanged);
+        if !args.dry_run {
+            let checksums = name_state
+                .rrset_checksums_mut()
+                .entry(family.to_string())
+                .or_default();
+            checksums.set_known(vec![current_checksum]);
+            checksums.set_respected(None);
+            name_state.pending_ips_mut().remove(family);
+            name_state.applied_ips_mut().insert(family.to_string(), ip);
+        }
+        return Ok((ip, RenewOutcome::unchanged(), ttl));
+    }
+
+    // A forced update doesn't wait for confirmations.
+    let confirmations = name_providers_conf.confirmations();
+    if confirmations > 1 && !args.force {
+        let pending = observe_pending(name_state, family, ip);
+        if !args.dry_run {
+            name_state
+                .pending_ips_mut()
+                .insert(family.to_string(), pending);
+        }
+        if pending.count() < confirmations {
+            tracing::info!(
+                "{} of {} is observed {}/{} times, wait for confirmations",
+                ip,
+                name_conf.name(),
+                pending.count(),
+                confirmations
+            );
+            decided(RenewAction::Unchanged);
+            return Ok((ip, RenewOutcome::unchanged(), ttl));
+        }
+    }
+
+    // Without records, it can't tell whether a record exists.
+    let action = if ips.is_empty() && !is_blind {
+        tracing::info!(
+            action = %RenewAction::Create,
+            "no record of {} found, creating record for the first time with {}",
+            name_conf.name(),
+            ip
+        );
+        RenewAction::Create
+    } else {
+        tracing::debug!(
+            action = %RenewAction::Update,
+            "{} is not in {:?}, ready to update",
+            ip,
+            ips
+        );
+        RenewAction::Update
+    };
+    decided(action);
+    // The diff seen from the query provider, it is used if the update provider can't tell.
+    let queried_diff = RecordDiff {
+        before: (!ips.is_empty()).then(|| RecordSnapshot {
+            content: Some(
+                ips.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            ..Default::default()
+        }),
+        after: RecordSnapshot {
+            content: Some(ip.to_string()),
+            ..Default::default()
+        },
+    };
+    if args.dry_run {
+        tracing::info!(
+            action = %action,
+            before = ?queried_diff.before,
+            after = ?queried_diff.after,
+            "dry run, {} of {} is skipped: {}",
+            action,
+            name_conf.name(),
+            queried_diff
+        );
+        return Ok((ip, RenewOutcome::new(action, Some(queried_diff)), ttl));
+    }
+    let hook_env = HookEnv {
+        name: name_conf.name(),
+        family,
+        old_ips: &ips,
+        new_ip: ip,
+    };
+    if let Some(hook) = name_conf.pre_update() {
+        hook::run("pre_update", hook, &hook_env).await?;
+    }
+    // Every update provider in a chain is tried, a failure of one doesn't stop the others.
+    let update_provider_types = name_providers_conf.update_provider_type().as_slice();
+    let mut outcome: Option<RenewOutcome> = None;
+    let mut errors = vec![];
+    let change = audit::Change {
+        name: name_conf.name(),
+        family,
+        old: ips.iter().map(ToString::to_string).collect(),
+        new: Some(ip.to_string()),
+    };
+    for (i, update_provider_type) in update_provider_types.iter().enumerate() {
+        let result = async {
+            let update_provider = update::init_update_provider(
+                update_provider_type,
+                config,
+                name_conf,
+                name_state.zone_ids_mut(),
+            )
+            .await?;
+            let outcome = metrics::timed(
+                "update",
+                name_conf.name(),
+                update_provider.update(name_conf.name(), ip),
+            )
+            .await?
+            .or_decided(action);
+            if outcome.action == RenewAction::Create {
+                create_companion_records(update_provider.as_ref(), name_conf, ip).await;
+            }
+            Ok::<_, anyhow::Error>(outcome)
+        }
+        .await;
+        audit::record(
+            config,
+            &change,
+            update_provider_type,
+            result.as_ref().map(|o| o.action),
+        );
+        match result {
+            Ok(provider_outcome) => {
+                // The first change is reported.
+                if !outcome.as_ref().is_some_and(|o| o.action.is_changed()) {
+                    outcome = Some(provider_outcome);
+                }
+            }
+            Err(e) if update_provider_types.len() == 1 => return Err(e),
+            Err(e) => {
+                tracing::error!(
+                    "update provider {} of {} failed: {:?}",
+                    i,
+                    name_conf.name(),
+                    e
+                );
+                errors.push(format!("update provider {}: {:#}", i, e));
+            }
+        }
+    }
+    if !errors.is_empty() {
+        bail!(
+            "{} of {} update providers failed: {}",
+            errors.len(),
+            update_provider_types.len(),
+            errors.join("; ")
+        );
+    }
+    let Some(mut outcome) = outcome else {
+        bail!("no update provider of {}", name_conf.name());
+    };
+    name_state.pending_ips_mut().remove(family);
+    name_state.applied_ips_mut().insert(family.to_string(), ip);
+    // Resolvers may still return the old rrset for a while, both of them are written by us.
+    let checksums = name_state
+        .rrset_checksums_mut()
+        .entry(family.to_string())
+        .or_default();
+    checksums.set_known(vec![current_checksum, rrset_checksum(&[ip])]);
+    checksums.set_respected(None);
+    if outcome.action.is_changed() {
+        let diff = outcome.diff.get_or_insert(queried_diff);
+        tracing::info!(
+            action = %outcome.action,
+            before = ?diff.before,
+            after = ?diff.after,
+            "record of {} is changed: {}",
+            name_conf.name(),
+            diff
+        );
+        let mut event = events::Event::new(EventKind::Updated, name_conf.name(), Some(is_v6));
+        event.ip = Some(ip);
+        event.action = Some(outcome.action);
+        event.diff = Some(diff);
+        events::emit(&event, args.dry_run);
+        if let Some(hook) = name_conf.post_update() {
+            hook::run("post_update", hook, &hook_env).await?;
+        }
+    }
+    Ok((ip, outcome, ttl))
+}
+
+/// Init the query provider of a name, the `Provider` one asks the first update provider.
+pub(crate) async fn init_query_provider(
+    name_conf: &NameConf,
+    name_providers_conf: &NameProvidersConf,
+    config: &Config,
+    zone_ids: &mut BTreeMap<String, String>,
+) -> Result<Box<dyn QueryProvider>> {
+    let query_provider_type = name_providers_conf.query_provider_type();
+    if !matches!(query_provider_type, QueryProviderType::Provider) {
+        return query::init_query_provider(query_provider_type, config);
+    }
+    let Some(update_provider_type) = name_providers_conf
+        .update_provider_type()
+        .as_slice()
+        .first()
+    else {
+        bail!("no update provider of {}", name_conf.name());
+    };
+    Ok(query::init_provider_query_provider(
+        update::init_update_provider(update_provider_type, config, name_conf, zone_ids).await?,
+    ))
+}
+
+/// Observe a detected ip once more, the count starts over if it differs from the pending one.
+fn observe_pending(name_state: &NameState, family: &str, ip: IpAddr) -> PendingIp {
+    let mut pending = PendingIp::new(ip);
+    if let Some(last) = name_state.pending_ips().get(family) {
+        if last.ip() == ip {
+            pending.set_count(last.count() + 1);
+        }
+    }
+    pending
+}
+
+fn rrset_checksum(ips: &[IpAddr]) -> String {
+    let mut ips = ips.iter().map(ToString::to_string).collect::<Vec<_>>();
+    ips.sort();
+    HEXLOWER.encode(&openssl::sha::sha256(ips.join(",").as_bytes()))
+}
+
+/// Check if the rrset is changed by someone else since the last renew, return true if the update
+/// should be skipped to respect the change.
+async fn check_external_change(
+    args: &Args,
+    config: &Config,
+    name_conf: &NameConf,
+    family: &str,
+    ips: &[IpAddr],
+    name_state: &mut NameState,
+) -> bool {
+    let checksum = rrset_checksum(ips);
+    let respect = name_conf.on_external_change() == ExternalChangePolicy::Respect;
+    let Some(checksums) = name_state.rrset_checksums_mut().get_mut(family) else {
+        return false;
+    };
+    if checksums.respected().as_ref() == Some(&checksum) {
+        tracing::debug!(
+            "rrset of {} is still changed by someone else, skip",
+            name_conf.name()
+        );
+        return true;
+    }
+    if checksums.known().is_empty() || checksums.known().contains(&checksum) {
+        return false;
+    }
+
+    tracing::warn!(
+        "external modification of {} is detected, current rrset: {:?}",
+        name_conf.name(),
+        ips
+    );
+    if !args.dry_run {
+        notifier::notify(
+            config,
+            name_conf.tags(),
+            &Event::ExternalChange {
+                name: name_conf.name(),
+                ips,
+                respected: respect,
+            },
+        )
+        .await;
+        if respect {
+            checksums.set_respected(Some(checksum));
+        }
+    }
+    respect
+}
+
+/// Create companion records of a newly created record, a failure doesn't fail the renew.
+async fn create_companion_records(
+    update_provider: &dyn UpdateProvider,
+    name_conf: &NameConf,
+    ip: IpAddr,
+) {
+    let context = TemplateContext::new(name_conf);
+    for conf in name_conf.companion_records() {
+        let result = async {
+            let vars = context.vars(name_conf.name(), ip)?;
+            let record = CompanionRecord {
+                name: match conf.name() {
+                    Some(name) => context.render(name, &vars)?,
+                    None => name_conf.name().clone(),
+                },
+                record_type: conf.record_type().clone(),
+                content: context.render(conf.content(), &vars)?,
+                ttl: *conf.ttl(),
+                data: conf.data().clone(),
+            };
+            update_provider.create_record(&record).await?;
+            tracing::info!(
+                "companion {} record of {} is created: {}",
+                record.record_type,
+                record.name,
+                record.content
+            );
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+        if let Err(e) = result {
+            tracing::error!(
+                "failed to create companion {} record of {}: {:?}",
+                conf.record_type(),
+                name_conf.name(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name_conf(extra: &str) -> NameConf {
+        parse_name_conf(&format!(
+            "name = \"a.example.com\"\nrenew_interval = \"10m\"\nshared = false\n{}",
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn retry_backoff() {
+        let conf = name_conf("retry_interval = \"1m\"");
+        let minutes = |failures| retry_interval(&conf, failures).as_secs() / 60;
+        assert_eq!(
+            [1, 2, 3, 4, 5, 100].map(minutes),
+            [1, 2, 4, 8, 10, 10],
+            "it is doubled up to renew_interval"
+        );
+
+        // A retry_interval longer than renew_interval is kept.
+        let conf = name_conf("retry_interval = \"1h\"");
+        assert_eq!(retry_interval(&conf, 3), Duration::from_secs(3600));
+
+        let conf = name_conf("");
+        assert_eq!(retry_interval(&conf, 3), Duration::from_secs(600));
+    }
+
+    fn ips(ips: &[&str]) -> Vec<IpAddr> {
+        ips.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn rrset_checksums() {
+        assert_eq!(
+            rrset_checksum(&ips(&["192.0.2.1", "192.0.2.2"])),
+            rrset_checksum(&ips(&["192.0.2.2", "192.0.2.1"])),
+            "the order of an rrset doesn't matter"
+        );
+        assert_ne!(
+            rrset_checksum(&ips(&["192.0.2.1"])),
+            rrset_checksum(&ips(&["192.0.2.1", "192.0.2.2"]))
+        );
+    }
+
+    #[tokio::test]
+    async fn external_changes() {
+        let args = Args::default();
+        let config = Config::default();
+        let written = ips(&["192.0.2.1"]);
+        let changed = ips(&["198.51.100.1"]);
+        let mut name_state = NameState::new("a.example.com", 0);
+
+        // Nothing is known before the first renew.
+        let conf = name_conf("on_external_change = \"Respect\"");
+        assert!(
+            !check_external_change(&args, &config, &conf, "v4", &changed, &mut name_state).await
+        );
+
+        name_state
+            .rrset_checksums_mut()
+            .entry("v4".to_string())
+            .or_default()
+            .set_known(vec![rrset_checksum(&written)]);
+        assert!(
+            !check_external_change(&args, &config, &conf, "v4", &written, &mut name_state).await
+        );
+        let reconcile = name_conf("");
+        assert!(
+            !check_external_change(&args, &config, &reconcile, "v4", &changed, &mut name_state)
+                .await
+        );
+        assert!(
+            check_external_change(&args, &config, &conf, "v4", &changed, &mut name_state).await
+        );
+        // The change is still respected, until the rrset is changed again.
+        assert!(
+            check_external_change(&args, &config, &conf, "v4", &changed, &mut name_state).await
+        );
+        assert!(
+            !check_external_change(&args, &config, &reconcile, "v4", &written, &mut name_state)
+                .await
+        );
+    }
+
+    #[test]
+    fn confirmations() {
+        let mut name_state = NameState::new("a.example.com", 0);
+        let mut observe = |ip: &str| {
+            let pending = observe_pending(&name_state, "v4", ip.parse().unwrap());
+            name_state
+                .pending_ips_mut()
+                .insert("v4".to_string(), pending);
+            pending.count()
+        };
+        assert_eq!(
+            [
+                "192.0.2.1",
+                "192.0.2.1",
+                "192.0.2.2",
+                "192.0.2.2",
+                "192.0.2.2"
+            ]
+            .map(&mut observe),
+            [1, 2, 1, 2, 3],
+            "a different ip starts over"
+        );
+        let pending = observe_pending(&name_state, "v6", "2001:db8::1".parse().unwrap());
+        assert_eq!(pending.count(), 1, "families are counted apart");
+    }
+}
@@ -0,0 +1,46 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use tokio::time::Instant;
+
+/// The lock file in `name_state_dir`, it is hidden, so it isn't taken as a state.
+const LOCK_FILE: &str = ".dns-renew.lock";
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An exclusive lock of a state dir, so overlapping runs don't race on states and apis of
+/// providers. It is released when it is dropped, or the process exits.
+pub struct RunLock {
+    _file: File,
+}
+
+/// Take the lock of a state dir, it fails at once if another run holds it, unless `wait` is
+/// set.
+pub async fn acquire(dir: &Path, wait: Option<Duration>) -> Result<RunLock> {
+    let path = dir.join(LOCK_FILE);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("failed to open lock file: {:?}", path))?;
+    let deadline = Instant::now() + wait.unwrap_or_default();
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(RunLock { _file: file }),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+            Err(TryLockError::WouldBlock) => {
+                bail!("{:?} is locked by another run", path)
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("failed to lock {:?}", path));
+            }
+        }
+    }
+}
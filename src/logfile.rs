@@ -0,0 +1,135 @@
+//! The log file of `log_file`, rotated by time with tracing-appender, or by size.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{bail, Context, Result};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::{writer::BoxMakeWriter, MakeWriter};
+
+use crate::config::{LogFileConf, LogRotation};
+
+const DEFAULT_MAX_FILES: usize = 5;
+
+pub(crate) fn writer(conf: &LogFileConf) -> Result<BoxMakeWriter> {
+    let path = conf.path();
+    let max_files = conf.max_files().unwrap_or(DEFAULT_MAX_FILES).max(1);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {:?}", dir))?;
+    if let Some(max_size) = conf.max_size() {
+        if conf.rotation().is_some() {
+            bail!("rotation and max_size of log_file can't be both set");
+        }
+        return Ok(BoxMakeWriter::new(SizeRotatingFile::open(
+            path.clone(),
+            max_size,
+            max_files,
+        )?));
+    }
+
+    let rotation = match conf.rotation().unwrap_or(LogRotation::Daily) {
+        LogRotation::Minutely => Rotation::MINUTELY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .max_log_files(max_files);
+    // `dns-renew.log` is rotated as `dns-renew.2024-06-01.log`.
+    if let Some(prefix) = path.file_stem() {
+        builder = builder.filename_prefix(prefix.to_string_lossy());
+    }
+    if let Some(suffix) = path.extension() {
+        builder = builder.filename_suffix(suffix.to_string_lossy());
+    }
+    let appender = builder
+        .build(dir)
+        .with_context(|| format!("failed to open log file {:?}", path))?;
+    Ok(BoxMakeWriter::new(appender))
+}
+
+/// A file rotated when it would grow over `max_size`, `<path>.1` is the newest rotated one.
+struct SizeRotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    current: Mutex<(File, u64)>,
+}
+
+fn open_append(path: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+impl SizeRotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> Result<Self> {
+        let current =
+            open_append(&path).with_context(|| format!("failed to open log file {:?}", path))?;
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            current: Mutex::new(current),
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn rotate(&self) -> io::Result<(File, u64)> {
+        if self.max_files > 1 {
+            let _ = fs::remove_file(self.rotated(self.max_files - 1));
+            for index in (1..self.max_files - 1).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+        open_append(&self.path)
+    }
+}
+
+impl Write for &SizeRotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        // A line is written at once, so it is never split between two files.
+        if current.1 > 0 && current.1 + buf.len() as u64 > self.max_size {
+            *current = self.rotate()?;
+        }
+        let size = current.0.write(buf)?;
+        current.1 += size as u64;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.current
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .0
+            .flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for SizeRotatingFile {
+    type Writer = &'a SizeRotatingFile;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    future::Future,
+    io::Write as _,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use tiny_http::{Header, Response, Server};
+
+use crate::status::{NameStatus, SharedStatuses};
+
+/// Counters of renews of a family of a name.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Counter {
+    Attempts,
+    Successes,
+    Failures,
+    /// The record is changed to a newly detected ip.
+    IpChanges,
+}
+
+impl Counter {
+    fn metric(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Attempts => ("dns_renew_attempts_total", "Renews attempted."),
+            Self::Successes => ("dns_renew_successes_total", "Renews succeeded."),
+            Self::Failures => ("dns_renew_failures_total", "Renews failed."),
+            Self::IpChanges => (
+                "dns_renew_ip_changes_total",
+                "Records changed to a newly detected ip.",
+            ),
+        }
+    }
+}
+
+/// A name with its tenant, the tenant is `None` for names of the main config.
+type TenantName = (Option<String>, String);
+
+/// Labels of a name from the route of its tags, see `set_labels`.
+type Labels = BTreeMap<String, String>;
+
+/// Labels attached to series of names, keyed by the name.
+static LABELS: Mutex<BTreeMap<TenantName, Labels>> = Mutex::new(BTreeMap::new());
+
+/// Counters keyed by the counter, the name and the family.
+static COUNTERS: Mutex<BTreeMap<(Counter, TenantName, &'static str), u64>> =
+    Mutex::new(BTreeMap::new());
+
+/// How many times ip providers of `Fallback` or `Multi` disagree.
+static IP_PROVIDER_DISAGREEMENTS: AtomicU64 = AtomicU64::new(0);
+
+/// How many cycles of the daemon take longer than the shortest renew interval.
+static CYCLE_OVERRUNS: AtomicU64 = AtomicU64::new(0);
+
+/// Calls of providers of a name.
+#[derive(Clone, Copy, Default)]
+struct Calls {
+    /// The sum of seconds they take.
+    seconds: f64,
+    count: u64,
+}
+
+/// Calls keyed by the kind of provider and the name.
+static CALLS: Mutex<BTreeMap<(&'static str, TenantName), Calls>> = Mutex::new(BTreeMap::new());
+
+tokio::task_local! {
+    /// The tenant whose names are renewed, see `with_tenant`.
+    static TENANT: Option<String>;
+}
+
+/// Run `f` with metrics of names labeled by `tenant`, so names of different tenants are kept
+/// apart even if they are the same.
+pub async fn with_tenant<F: Future>(tenant: Option<String>, f: F) -> F::Output {
+    TENANT.scope(tenant, f).await
+}
+
+fn tenant_name(name: &str) -> TenantName {
+    (
+        TENANT.try_with(Clone::clone).ok().flatten(),
+        name.to_string(),
+    )
+}
+
+/// Set labels attached to series of a name, they are the labels of the notification route of its
+/// tags. Labels named like the ones of dns-renew, e.g. `name`, are ignored.
+pub fn set_labels(name: &str, labels: Option<&Labels>) {
+    let mut all = LABELS.lock().expect("labels are poisoned");
+    match labels.filter(|l| !l.is_empty()) {
+        Some(labels) => all.insert(tenant_name(name), labels.clone()),
+        None => all.remove(&tenant_name(name)),
+    };
+}
+
+pub fn inc(counter: Counter, name: &str, family: &'static str) {
+    *COUNTERS
+        .lock()
+        .expect("counters are poisoned")
+        .entry((counter, tenant_name(name), family))
+        .or_default() += 1;
+}
+
+/// Count a disagreement of ip providers, the count since start is returned.
+pub fn inc_ip_provider_disagreements() -> u64 {
+    IP_PROVIDER_DISAGREEMENTS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Count an overrun of a cycle of the daemon, the count since start is returned.
+pub fn inc_cycle_overruns() -> u64 {
+    CYCLE_OVERRUNS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Run a call of a provider, and record how long it takes, `provider` is one of `query`, `ip`
+/// and `update`.
+pub async fn timed<T>(provider: &'static str, name: &str, f: impl Future<Output = T>) -> T {
+    let started = Instant::now();
+    let output = f.await;
+    observe(provider, name, started.elapsed());
+    output
+}
+
+fn observe(provider: &'static str, name: &str, elapsed: Duration) {
+    let mut calls = CALLS.lock().expect("calls are poisoned");
+    let calls = calls.entry((provider, tenant_name(name))).or_default();
+    calls.seconds += elapsed.as_secs_f64();
+    calls.count += 1;
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Labels of a name, with labels of its route.
+fn name_labels(all: &BTreeMap<TenantName, Labels>, tenant: Option<&str>, name: &str) -> String {
+    let mut text = match tenant {
+        Some(tenant) => format!("tenant=\"{}\",name=\"{}\"", escape(tenant), escape(name)),
+        None => format!("name=\"{}\"", escape(name)),
+    };
+    let key = (tenant.map(ToString::to_string), name.to_string());
+    for (label, value) in all.get(&key).into_iter().flatten() {
+        if !matches!(label.as_str(), "tenant" | "name" | "family" | "provider") {
+            let _ = write!(text, ",{}=\"{}\"", label, escape(value));
+        }
+    }
+    text
+}
+
+fn status_labels(all: &BTreeMap<TenantName, Labels>, status: &NameStatus) -> String {
+    name_labels(all, status.tenant.as_deref(), &status.name)
+}
+
+/// Render metrics in the text format of prometheus. Counters are of this process, gauges of
+/// names are from their statuses.
+pub fn render(statuses: &[NameStatus]) -> String {
+    let mut text = String::new();
+    let labels = LABELS.lock().expect("labels are poisoned").clone();
+    let counters = COUNTERS.lock().expect("counters are poisoned").clone();
+    let mut last_metric = None;
+    for ((counter, (tenant, name), family), value) in counters {
+        let (metric, help) = counter.metric();
+        if last_metric != Some(metric) {
+            let _ = writeln!(
+                text,
+                "# HELP {} {}\n# TYPE {} counter",
+                metric, help, metric
+            );
+            last_metric = Some(metric);
+        }
+        let _ = writeln!(
+            text,
+            "{}{{{},family=\"{}\"}} {}",
+            metric,
+            name_labels(&labels, tenant.as_deref(), &name),
+            family,
+            value
+        );
+    }
+
+    let _ = writeln!(
+        text,
+        "# HELP dns_renew_ip_provider_disagreements_total Queries of ip providers which disagree.\n\
+         # TYPE dns_renew_ip_provider_disagreements_total counter\n\
+         dns_renew_ip_provider_disagreements_total {}",
+        IP_PROVIDER_DISAGREEMENTS.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        text,
+        "# HELP dns_renew_cycle_overruns_total Cycles of the daemon longer than the shortest renew \
+         interval.\n\
+         # TYPE dns_renew_cycle_overruns_total counter\n\
+         dns_renew_cycle_overruns_total {}",
+        CYCLE_OVERRUNS.load(Ordering::Relaxed)
+    );
+
+    let calls = CALLS.lock().expect("calls are poisoned").clone();
+    if !calls.is_empty() {
+        text.push_str(
+            "# HELP dns_renew_provider_duration_seconds Time spent in calls of providers.\n\
+             # TYPE dns_renew_provider_duration_seconds summary\n",
+        );
+    }
+    for ((provider, (tenant, name)), Calls { seconds, count }) in calls {
+        let labels = format!(
+            "provider=\"{}\",{}",
+            provider,
+            name_labels(&labels, tenant.as_deref(), &name)
+        );
+        let _ = writeln!(
+            text,
+            "dns_renew_provider_duration_seconds_sum{{{}}} {}\n\
+             dns_renew_provider_duration_seconds_count{{{}}} {}",
+            labels, seconds, labels, count
+        );
+    }
+
+    text.push_str(
+        "# HELP dns_renew_last_success_timestamp_seconds The time of the last successful renew.\n\
+         # TYPE dns_renew_last_success_timestamp_seconds gauge\n",
+    );
+    for status in statuses {
+        if let Some(last_renew) = status.last_renew {
+            let _ = writeln!(
+                text,
+                "dns_renew_last_success_timestamp_seconds{{{}}} {}",
+                status_labels(&labels, status),
+                last_renew
+            );
+        }
+    }
+    text.push_str(
+        "# HELP dns_renew_consecutive_failures The number of consecutive failed renews.\n\
+         # TYPE dns_renew_consecutive_failures gauge\n",
+    );
+    for status in statuses {
+        let _ = writeln!(
+            text,
+            "dns_renew_consecutive_failures{{{}}} {}",
+            status_labels(&labels, status),
+            status.failures
+        );
+    }
+    text
+}
+
+/// Serve metrics on `GET /metrics` in a background thread.
+pub fn serve(listen: SocketAddr, statuses: SharedStatuses) -> Result<()> {
+    let server =
+        Server::http(listen).map_err(|e| anyhow!("failed to listen on {}: {}", listen, e))?;
+    tracing::info!("metrics are served on {}", listen);
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let statuses = statuses.lock().expect("statuses are poisoned").clone();
+                Response::from_string(render(&statuses)).with_header(
+                    "Content-Type: text/plain; version=0.0.4"
+                        .parse::<Header>()
+                        .expect("it is a valid header"),
+                )
+            } else {
+                Response::from_string("not found").with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                tracing::warn!("failed to respond: {:?}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Write metrics to a file for the textfile collector of node_exporter. It is written to a
+/// temporary file and renamed, so a half-written file is never collected.
+pub fn write_textfile(path: &Path, statuses: &[NameStatus]) -> Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    let mut file = fs::File::create(&tmp_path)
+        .with_context(|| format!("failed to create metrics file: {:?}", tmp_path))?;
+    file.write_all(render(statuses).as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("failed to rename {:?} to {:?}", tmp_path, path))?;
+    Ok(())
+}
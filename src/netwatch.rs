@@ -0,0 +1,109 @@
+//! Changes of the network reported by netlink, so the daemon can check names right after a
+//! reconnect.
+
+use std::{collections::HashSet, net::IpAddr, thread};
+
+use anyhow::{Context, Result};
+use netlink_packet_core::{NetlinkMessage, NetlinkPayload};
+use netlink_packet_route::{
+    RouteMessage, RtnlMessage, IFA_F_DADFAILED, IFA_F_TENTATIVE, RTN_UNICAST, RT_SCOPE_UNIVERSE,
+    RT_TABLE_MAIN,
+};
+use netlink_sys::{protocols::NETLINK_ROUTE, Socket, SocketAddr};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::ip::interface::{self, Address};
+
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+const RTMGRP_IPV4_ROUTE: u32 = 0x40;
+const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+const RTMGRP_IPV6_ROUTE: u32 = 0x400;
+
+/// Watch global addresses and default routes of the host, a description of each change is sent
+/// to `changes`. An address which is refreshed, e.g. by a router advertisement, is not a change.
+pub fn watch(changes: UnboundedSender<String>) -> Result<()> {
+    let mut socket = Socket::new(NETLINK_ROUTE)?;
+    socket.bind(&SocketAddr::new(
+        0,
+        RTMGRP_IPV4_IFADDR | RTMGRP_IPV4_ROUTE | RTMGRP_IPV6_IFADDR | RTMGRP_IPV6_ROUTE,
+    ))?;
+    // Addresses are known after the subscription, so no change is missed in between.
+    let known = interface::global_addresses()?
+        .into_iter()
+        .filter(is_usable)
+        .map(|a| a.ip)
+        .collect();
+    thread::Builder::new()
+        .name("netwatch".to_string())
+        .spawn(move || {
+            if let Err(e) = receive(socket, known, changes) {
+                tracing::warn!("stop watching the network: {:?}", e);
+            }
+        })?;
+    Ok(())
+}
+
+/// A tentative address can't be used until its duplicate address detection is done.
+fn is_usable(address: &Address) -> bool {
+    address.flags & (IFA_F_TENTATIVE | IFA_F_DADFAILED) == 0
+}
+
+fn is_default_route(message: &RouteMessage) -> bool {
+    message.header.destination_prefix_length == 0
+        && message.header.table == RT_TABLE_MAIN
+        && message.header.kind == RTN_UNICAST
+}
+
+fn receive(
+    socket: Socket,
+    mut known: HashSet<IpAddr>,
+    changes: UnboundedSender<String>,
+) -> Result<()> {
+    let mut receive_buffer = vec![0; 65536];
+    loop {
+        let size = socket.recv(&mut &mut receive_buffer[..], 0)?;
+        let mut offset = 0;
+        while offset < size {
+            let packet: NetlinkMessage<RtnlMessage> =
+                NetlinkMessage::deserialize(&receive_buffer[offset..size])
+                    .context("invalid netlink message")?;
+            if let Some(change) = change_of(&packet.payload, &mut known) {
+                if changes.send(change).is_err() {
+                    // The daemon is stopped.
+                    return Ok(());
+                }
+            }
+            if packet.header.length == 0 {
+                break;
+            }
+            offset += packet.header.length as usize;
+        }
+    }
+}
+
+fn change_of(payload: &NetlinkPayload<RtnlMessage>, known: &mut HashSet<IpAddr>) -> Option<String> {
+    let NetlinkPayload::InnerMessage(message) = payload else {
+        return None;
+    };
+    match message {
+        RtnlMessage::NewAddress(message) if message.header.scope == RT_SCOPE_UNIVERSE => {
+            let address = interface::parse_address(message).filter(is_usable)?;
+            known
+                .insert(address.ip)
+                .then(|| format!("{} is added", address.ip))
+        }
+        RtnlMessage::DelAddress(message) if message.header.scope == RT_SCOPE_UNIVERSE => {
+            let address = interface::parse_address(message)?;
+            known
+                .remove(&address.ip)
+                .then(|| format!("{} is removed", address.ip))
+        }
+        RtnlMessage::NewRoute(message) if is_default_route(message) => {
+            Some("a default route is added".to_string())
+        }
+        RtnlMessage::DelRoute(message) if is_default_route(message) => {
+            Some("a default route is removed".to_string())
+        }
+        _ => None,
+    }
+}
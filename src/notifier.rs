@@ -0,0 +1,245 @@
+use std::{fmt::Display, net::IpAddr};
+
+use anyhow::{bail, Result};
+use reqwest::header::CONTENT_TYPE;
+
+use crate::{
+    config::{
+        Config, NotificationConf, NotifierConf, NotifyEventKind, RouteConf, UpdateCredential,
+    },
+    http,
+    update::{
+        find_optional_update_credential, find_update_credential, with_http_credential, RecordDiff,
+    },
+};
+
+const TELEGRAM_API_URL: &str = "https://api.telegram.org";
+
+pub enum Event<'a> {
+    Changed {
+        name: &'a str,
+        /// The ip, or the target of a CNAME.
+        target: String,
+        diff: Option<&'a RecordDiff>,
+    },
+    Failed {
+        name: &'a str,
+        error: &'a anyhow::Error,
+    },
+    /// A name is renewed after consecutive failures.
+    Recovered { name: &'a str, failures: u32 },
+    /// The rrset is changed by someone else since the last renew.
+    ExternalChange {
+        name: &'a str,
+        ips: &'a [IpAddr],
+        respected: bool,
+    },
+}
+
+impl Event<'_> {
+    pub fn kind(&self) -> NotifyEventKind {
+        match self {
+            Event::Changed { .. } => NotifyEventKind::Changed,
+            Event::Failed { .. } => NotifyEventKind::Failed,
+            Event::Recovered { .. } => NotifyEventKind::Recovered,
+            Event::ExternalChange { .. } => NotifyEventKind::ExternalChange,
+        }
+    }
+}
+
+impl Event<'_> {
+    /// A compact message in markdown, which is rendered by both slack and discord.
+    fn markdown(&self) -> String {
+        match self {
+            Event::Changed { name, target, diff } => {
+                let mut message = format!("\u{1f504} `{}` now points to `{}`", name, target);
+                if let Some(diff) = diff {
+                    message.push_str(&format!(" ({})", diff));
+                }
+                message
+            }
+            Event::Failed { name, error } => {
+                format!("\u{274c} failed to renew `{}`: {:#}", name, error)
+            }
+            Event::Recovered { name, failures } => format!(
+                "\u{2705} `{}` is renewed again after {} failures",
+                name, failures
+            ),
+            Event::ExternalChange { .. } => format!("\u{26a0}\u{fe0f} {}", self),
+        }
+    }
+}
+
+impl Display for Event<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::Changed { name, target, diff } => {
+                write!(f, "{} now points to {}", name, target)?;
+                if let Some(diff) = diff {
+                    write!(f, " ({})", diff)?;
+                }
+                Ok(())
+            }
+            Event::Failed { name, error } => write!(f, "failed to renew {}: {:#}", name, error),
+            Event::Recovered { name, failures } => {
+                write!(f, "{} is renewed again after {} failures", name, failures)
+            }
+            Event::ExternalChange {
+                name,
+                ips,
+                respected,
+            } => write!(
+                f,
+                "external modification of {} is detected, now {:?}, it is {}",
+                name,
+                ips,
+                if *respected {
+                    "respected"
+                } else {
+                    "reconciled"
+                }
+            ),
+        }
+    }
+}
+
+/// Find the route of a name by its tags.
+pub fn route<'a>(conf: &'a NotificationConf, tags: &[String]) -> Option<&'a RouteConf> {
+    conf.routes()
+        .iter()
+        .find(|r| r.tags().iter().all(|t| tags.contains(t)))
+}
+
+/// Log an event with the labels of its route, and send it to the notifier of the route. A
+/// failure of sending is only logged.
+pub async fn notify(config: &Config, tags: &[String], event: &Event<'_>) {
+    let route = route(config.notification(), tags);
+    let labels = route.map(|r| r.labels());
+    tracing::info!(labels = ?labels, "{}", event);
+
+    let Some((route, notifier)) = route.and_then(|r| r.notifier().as_ref().map(|n| (r, n))) else {
+        return;
+    };
+    if route
+        .events()
+        .as_ref()
+        .is_some_and(|events| !events.contains(&event.kind()))
+    {
+        return;
+    }
+    if let Err(e) = send(config, notifier, route, event).await {
+        tracing::warn!("failed to notify {}: {:?}", notifier, without_url(e));
+    }
+}
+
+/// Strip the url from an error of a request, a webhook url, or a url with a bot token, is a
+/// secret.
+fn without_url(e: anyhow::Error) -> anyhow::Error {
+    match e.downcast::<reqwest::Error>() {
+        Ok(e) => e.without_url().into(),
+        Err(e) => e,
+    }
+}
+
+async fn send(config: &Config, notifier: &str, route: &RouteConf, event: &Event<'_>) -> Result<()> {
+    let notifier_conf = config
+        .notification()
+        .notifiers()
+        .get(notifier)
+        .ok_or_else(|| anyhow::anyhow!("Notifier not found: {}", notifier))?;
+    match notifier_conf {
+        NotifierConf::Ntfy { url, credential } => {
+            let mut req_builder = http::client()
+                .post(url)
+                .header("Title", "dns-renew")
+                .body(event.to_string());
+            if let Some(priority) = route.priority() {
+                req_builder = req_builder.header("Priority", priority.to_string());
+            }
+            match event {
+                Event::Failed { .. } | Event::ExternalChange { .. } => {
+                    req_builder = req_builder.header("Tags", "warning");
+                }
+                Event::Recovered { .. } => {
+                    req_builder = req_builder.header("Tags", "white_check_mark");
+                }
+                Event::Changed { .. } => {}
+            }
+            req_builder = with_http_credential(
+                req_builder,
+                find_optional_update_credential(config, credential)?.as_ref(),
+            )?;
+            http::send(req_builder).await?.error_for_status()?;
+        }
+        NotifierConf::Gotify { url, credential } => {
+            let UpdateCredential::ApiKey { key: token } =
+                find_update_credential(config, credential)?
+            else {
+                bail!("Only ApiKey credential is supported when gotify is used.");
+            };
+            let mut body = serde_json::json!({
+                "title": "dns-renew",
+                "message": event.to_string(),
+            });
+            if let Some(priority) = route.priority() {
+                body["priority"] = priority.into();
+            }
+            let req_builder = http::client()
+                .post(format!("{}/message", url.trim_end_matches('/')))
+                .header("X-Gotify-Key", token)
+                .header(CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(&body)?);
+            http::send(req_builder).await?.error_for_status()?;
+        }
+        NotifierConf::Slack { url } => {
+            let body = serde_json::json!({ "text": event.markdown() });
+            let req_builder = http::client()
+                .post(url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(&body)?);
+            http::send(req_builder).await?.error_for_status()?;
+        }
+        NotifierConf::Discord { url } => {
+            let body = serde_json::json!({
+                "username": "dns-renew",
+                "content": event.markdown(),
+            });
+            let req_builder = http::client()
+                .post(url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(&body)?);
+            http::send(req_builder).await?.error_for_status()?;
+        }
+        NotifierConf::Telegram {
+            credential,
+            chat_id,
+            api_url,
+        } => {
+            let UpdateCredential::ApiKey { key: token } =
+                find_update_credential(config, credential)?
+            else {
+                bail!("Only ApiKey credential is supported when telegram is used.");
+            };
+            let url = format!(
+                "{}/bot{}/sendMessage",
+                api_url
+                    .as_deref()
+                    .unwrap_or(TELEGRAM_API_URL)
+                    .trim_end_matches('/'),
+                token
+            );
+            // Messages of a low priority route don't make a sound.
+            let body = serde_json::json!({
+                "chat_id": chat_id,
+                "text": event.to_string(),
+                "disable_notification": route.priority().is_some_and(|p| p <= 2),
+            });
+            let req_builder = http::client()
+                .post(url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(&body)?);
+            http::send(req_builder).await?.error_for_status()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! Providers implemented by executables in `plugin_dir`, which speak json over stdio. A request
+//! object is written to the stdin of the executable, and it writes a response object to stdout:
+//!
+//! - `{"operation": "query", "name": "a.com", "family": "v4", "options": {}}` of a query provider,
+//!   `{"ips": ["203.0.113.7"], "ttl": 300}` is expected, `ttl` is optional.
+//! - `{"operation": "ip", "family": "v4", "options": {}}` of an ip provider, `{"ip": "203.0.113.7"}`
+//!   is expected.
+//! - `{"operation": "update", "name": "a.com", "family": "v4", "ip": "203.0.113.7", "options": {}}`
+//!   of an update provider, `{"changed": false}` means the record is not changed, `{}` is enough
+//!   otherwise.
+//!
+//! `options` is the one of the provider in the name config. A non-zero exit code or
+//! `{"error": "..."}` is a failure.
+
+use std::{
+    net::IpAddr,
+    path::{Component, Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{
+    config::Config,
+    ip::IpProvider,
+    query::QueryProvider,
+    update::{RenewOutcome, UpdateProvider},
+};
+
+const DEFAULT_PLUGIN_DIR: &str = "/usr/lib/dns-renew/plugins";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct PluginProvider {
+    program: PathBuf,
+    options: Value,
+    timeout: Duration,
+}
+
+#[derive(Deserialize)]
+struct QueryResponse {
+    ips: Vec<IpAddr>,
+    ttl: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct IpResponse {
+    ip: IpAddr,
+}
+
+#[derive(Deserialize)]
+struct UpdateResponse {
+    #[serde(default = "changed")]
+    changed: bool,
+}
+
+fn changed() -> bool {
+    true
+}
+
+fn family(is_v6: bool) -> &'static str {
+    if is_v6 {
+        "v6"
+    } else {
+        "v4"
+    }
+}
+
+impl PluginProvider {
+    /// The plugin is an executable in `plugin_dir`, it can't be a path out of it.
+    pub fn new(
+        config: &Config,
+        plugin: &str,
+        options: &Value,
+        timeout: Option<Duration>,
+    ) -> Result<Self> {
+        if !matches!(
+            Path::new(plugin)
+                .components()
+                .collect::<Vec<_>>()
+                .as_slice(),
+            [Component::Normal(_)]
+        ) {
+            bail!("invalid plugin name: {}", plugin);
+        }
+        let program = config
+            .plugin_dir()
+            .as_deref()
+            .unwrap_or(Path::new(DEFAULT_PLUGIN_DIR))
+            .join(plugin);
+        if !program.is_file() {
+            bail!("plugin not found: {:?}", program);
+        }
+        Ok(Self {
+            program,
+            // `options` is always an object for plugins, even if it's omitted.
+            options: if options.is_null() {
+                json!({})
+            } else {
+                options.clone()
+            },
+            timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
+        })
+    }
+
+    async fn call<T: DeserializeOwned>(&self, mut request: Value) -> Result<T> {
+        request["options"] = self.options.clone();
+        let mut child = Command::new(&self.program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("failed to run {:?}", self.program))?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let body = serde_json::to_vec(&request)?;
+        let output = tokio::time::timeout(self.timeout, async {
+            stdin.write_all(&body).await?;
+            // The plugin reads until eof.
+            drop(stdin);
+            child.wait_with_output().await
+        })
+        .await
+        .with_context(|| format!("{:?} is timed out after {:?}", self.program, self.timeout))?
+        .with_context(|| format!("failed to run {:?}", self.program))?;
+        tracing::debug!(
+            "{:?} exits with {}, stdout: {:?}, stderr: {:?}",
+            self.program,
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        if !output.status.success() {
+            bail!(
+                "{:?} exits with {}: {}",
+                self.program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let response: Value = serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("invalid response of {:?}", self.program))?;
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("{:?} fails: {}", self.program, error));
+        }
+        serde_json::from_value(response)
+            .with_context(|| format!("invalid response of {:?}", self.program))
+    }
+}
+
+#[async_trait]
+impl QueryProvider for PluginProvider {
+    async fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
+        Ok(self.query_with_ttl(name, is_v6).await?.0)
+    }
+
+    async fn query_with_ttl(&self, name: &str, is_v6: bool) -> Result<(Vec<IpAddr>, Option<u32>)> {
+        let response: QueryResponse = self
+            .call(json!({ "operation": "query", "name": name, "family": family(is_v6) }))
+            .await?;
+        Ok((response.ips, response.ttl))
+    }
+}
+
+#[async_trait]
+impl IpProvider for PluginProvider {
+    async fn query(&self, is_v6: bool) -> Result<IpAddr> {
+        let response: IpResponse = self
+            .call(json!({ "operation": "ip", "family": family(is_v6) }))
+            .await?;
+        Ok(response.ip)
+    }
+}
+
+#[async_trait]
+impl UpdateProvider for PluginProvider {
+    async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+        let response: UpdateResponse = self
+            .call(json!({
+                "operation": "update",
+                "name": name,
+                "family": family(ip.is_ipv6()),
+                "ip": ip,
+            }))
+            .await?;
+        Ok(if response.changed {
+            RenewOutcome::applied()
+        } else {
+            RenewOutcome::unchanged()
+        })
+    }
+}
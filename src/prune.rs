@@ -0,0 +1,77 @@
+use std::ffi::OsStr;
+
+use anyhow::Result;
+use tracing::Instrument;
+
+use crate::{
+    config::Config, delete_name_records, lock, parse_name_conf, state::StateStore, Args,
+    NamesFailed, Tenant,
+};
+
+/// Remove states of names whose config no longer exists in all tenants. With `delete_records`,
+/// records of them are deleted first, and a state is kept if its records can't be deleted.
+pub async fn run(args: &Args, tenants: &[Tenant], delete_records: bool) -> Result<()> {
+    let mut failures = 0;
+    for tenant in tenants {
+        failures += prune(args, &tenant.config, delete_records)
+            .instrument(tenant.span())
+            .await?;
+    }
+    if failures > 0 {
+        return Err(NamesFailed(failures).into());
+    }
+    Ok(())
+}
+
+async fn prune(args: &Args, config: &Config, delete_records: bool) -> Result<usize> {
+    let _lock = lock::acquire(config.name_state_dir(), args.wait_lock).await?;
+    let state_store = StateStore::new(config)?;
+    let mut failures = 0;
+    for (state_key, mut name_state) in state_store.load_all()? {
+        if !is_orphaned(config, &state_key) {
+            continue;
+        }
+        if delete_records {
+            let Some(conf_source) = name_state.conf().clone() else {
+                tracing::error!(
+                    "records of {} can't be deleted, its config is not kept without delete_on_disable",
+                    name_state.name()
+                );
+                failures += 1;
+                continue;
+            };
+            if let Err(e) = delete_name_records(args, config, &conf_source, &mut name_state).await {
+                tracing::error!("failed to delete records of {}: {:?}", name_state.name(), e);
+                failures += 1;
+                continue;
+            }
+        }
+        if !args.dry_run {
+            state_store.remove(&state_key)?;
+        }
+        println!("{}\t{}", state_key.to_string_lossy(), name_state.name());
+    }
+    Ok(failures)
+}
+
+/// The state of an alias is keyed by `<stem>@<alias>`, and the state of a discovered name is
+/// keyed by `discovered-<name>`, which is only orphaned if the discovery is disabled.
+fn is_orphaned(config: &Config, state_key: &OsStr) -> bool {
+    let key = state_key.to_string_lossy();
+    let (stem, alias) = match key.split_once('@') {
+        Some((stem, alias)) => (stem, Some(alias)),
+        None => (key.as_ref(), None),
+    };
+    let path = config.name_conf_dir().join(format!("{}.toml", stem));
+    if !path.exists() {
+        return !(key.starts_with("discovered-") && config.discovery().is_some());
+    }
+    let Some(alias) = alias else {
+        return false;
+    };
+    // A config which can't be read is kept, it may be fixed later.
+    std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|source| parse_name_conf(&source).ok())
+        .is_some_and(|name_conf| !name_conf.aliases().iter().any(|a| a == alias))
+}
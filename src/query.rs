@@ -1,24 +1,31 @@
-use std::{net::IpAddr, time::Duration};
+use std::{future::Future, net::IpAddr, time::Duration};
 
 use crate::{
-    config::{Config, QueryProviderType},
+    config::{Config, HttpClientConf, QueryProviderType},
     dns::DnsClient,
+    plugin::PluginProvider,
+    registry,
+    update::UpdateProvider,
     DEFAULT_TIMEOUT,
 };
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
 use dns::DnsQueryProvider;
 use dohgoogle::DohGoogleQueryProvider;
 use dohietf::DohIetfQueryProvider;
 use dot::DotQueryProvider;
-use hickory_proto::rr::{RData, RecordType};
+use hickory_proto::rr::{RData, Record, RecordType};
 
 mod dohgoogle {
     use std::{net::IpAddr, time::Duration};
 
     use anyhow::{bail, Result};
-    use reqwest::{blocking::Client, Url};
+    use async_trait::async_trait;
+    use reqwest::Url;
     use serde::Deserialize;
 
+    use crate::{config::HttpClientConf, http};
+
     use super::QueryProvider;
 
     #[derive(Deserialize)]
@@ -34,24 +41,26 @@ mod dohgoogle {
         #[serde(rename = "type")]
         record_type: u32,
         data: String,
+        #[serde(rename = "TTL")]
+        ttl: Option<u32>,
     }
 
     pub(super) struct DohGoogleQueryProvider {
         pub(super) url: String,
         pub(super) name_key: String,
         pub(super) timeout: Duration,
+        pub(super) http_client: HttpClientConf,
     }
 
-    impl QueryProvider for DohGoogleQueryProvider {
-        #[tracing::instrument(skip(self), err)]
-        fn query(&self, name: &str, _is_v6: bool) -> Result<Vec<IpAddr>> {
+    impl DohGoogleQueryProvider {
+        async fn answers(&self, name: &str) -> Result<Vec<DohGoogleAnswer>> {
             let url = Url::parse_with_params(&self.url, &[(&self.name_key, name)])?;
-            let response_body = Client::new()
-                .get(url.clone())
-                .timeout(self.timeout)
-                .send()?
+            let client = http::client_of(&self.http_client)?;
+            let response_body = http::send(client.get(url.clone()).timeout(self.timeout))
+                .await?
                 .error_for_status()?
-                .bytes()?;
+                .bytes()
+                .await?;
 
             tracing::debug!("query through DohGoogle returns: {:?}", response_body);
             let response: DohGoogleResponse = serde_json::from_slice(&response_body)?;
@@ -63,24 +72,49 @@ mod dohgoogle {
                     response.status
                 );
             }
-            Ok(response
-                .answer
-                .unwrap_or_default()
+            Ok(response.answer.unwrap_or_default())
+        }
+    }
+
+    #[async_trait]
+    impl QueryProvider for DohGoogleQueryProvider {
+        async fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
+            Ok(self.query_with_ttl(name, is_v6).await?.0)
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn query_with_ttl(
+            &self,
+            name: &str,
+            _is_v6: bool,
+        ) -> Result<(Vec<IpAddr>, Option<u32>)> {
+            let answers = self.answers(name).await?;
+            let answers = answers
                 .iter()
+                .filter(|i| i.record_type == 1 || i.record_type == 28)
                 .filter_map(|i| {
-                    if i.record_type == 1 || i.record_type == 28 {
-                        i.data
-                            .parse::<IpAddr>()
-                            .inspect_err(|_| {
-                                tracing::warn!("{} is not a valid ip", i.data);
-                            })
-                            .ok()
-                    } else {
-                        // Skip unknown type
-                        None
-                    }
+                    i.data
+                        .parse::<IpAddr>()
+                        .inspect_err(|_| {
+                            tracing::warn!("{} is not a valid ip", i.data);
+                        })
+                        .ok()
+                        .map(|ip| (ip, i.ttl))
                 })
-                .collect())
+                .collect::<Vec<_>>();
+            let ttl = answers.iter().filter_map(|(_, ttl)| *ttl).min();
+            Ok((answers.into_iter().map(|(ip, _)| ip).collect(), ttl))
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn query_cname(&self, name: &str) -> Result<Option<String>> {
+            // A CNAME is followed by its target in the answers of an A query.
+            Ok(self
+                .answers(name)
+                .await?
+                .into_iter()
+                .find(|i| i.record_type == 5)
+                .map(|i| i.data))
         }
     }
 }
@@ -89,57 +123,121 @@ mod dns {
     use std::{net::IpAddr, time::Duration};
 
     use anyhow::Result;
+    use async_trait::async_trait;
+
+    use crate::{
+        config::{BindConf, EdnsConf},
+        dns::DnsClient,
+    };
 
     use super::QueryProvider;
 
     pub(super) struct DnsQueryProvider {
-        pub(super) name_server_host: String,
+        pub(super) name_server_hosts: Vec<String>,
         pub(super) name_server_port: Option<u16>,
         pub(super) timeout: Duration,
         pub(super) use_tcp: bool,
+        pub(super) socks5_proxy: Option<String>,
+        pub(super) edns: EdnsConf,
+        pub(super) bind: BindConf,
     }
 
-    impl QueryProvider for DnsQueryProvider {
-        #[tracing::instrument(skip(self), err)]
-        fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
-            super::query(
-                &self.name_server_host,
+    impl DnsQueryProvider {
+        fn client(&self, host: &str) -> Result<DnsClient> {
+            DnsClient::new(
+                host,
                 self.name_server_port,
                 self.timeout,
                 !self.use_tcp,
                 false,
-                name,
-                is_v6,
+            )?
+            .with_socks5_proxy(self.socks5_proxy.as_deref())?
+            .with_edns(
+                *self.edns.udp_payload_size(),
+                self.edns.client_subnet().as_deref(),
+            )?
+            .with_bind(
+                *self.bind.bind_address(),
+                self.bind.bind_interface().as_deref(),
             )
         }
     }
+
+    #[async_trait]
+    impl QueryProvider for DnsQueryProvider {
+        async fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
+            Ok(self.query_with_ttl(name, is_v6).await?.0)
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn query_with_ttl(
+            &self,
+            name: &str,
+            is_v6: bool,
+        ) -> Result<(Vec<IpAddr>, Option<u32>)> {
+            super::with_fallback(&self.name_server_hosts, |host| async move {
+                super::query(&self.client(host)?, name, is_v6).await
+            })
+            .await
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn query_cname(&self, name: &str) -> Result<Option<String>> {
+            super::with_fallback(&self.name_server_hosts, |host| async move {
+                super::query_cname(&self.client(host)?, name).await
+            })
+            .await
+        }
+    }
 }
 
 mod dohietf {
     use std::{net::IpAddr, str::FromStr, time::Duration};
 
+    use ::http::Request;
     use anyhow::{Context, Result};
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use data_encoding::BASE64URL_NOPAD;
     use hickory_proto::{
         op::{Message, MessageType, Query},
-        rr::{DNSClass, Name, RData, RecordType},
+        rr::{DNSClass, Name, RecordType},
     };
-    use reqwest::{blocking::Client, header::CONTENT_TYPE};
+    use reqwest::header::{ACCEPT, CONTENT_TYPE};
+
+    use crate::{config::HttpClientConf, http, http3};
 
     use super::QueryProvider;
 
     pub(super) struct DohIetfQueryProvider {
         pub(super) url: String,
         pub(super) timeout: Duration,
+        pub(super) use_get: bool,
+        pub(super) use_http3: bool,
+        pub(super) http_client: HttpClientConf,
     }
 
-    impl QueryProvider for DohIetfQueryProvider {
-        #[tracing::instrument(skip(self), err)]
-        fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
-            let record_type = if is_v6 {
-                RecordType::AAAA
+    impl DohIetfQueryProvider {
+        async fn send_over_http3(&self, body: Vec<u8>) -> Result<Bytes> {
+            let request = if self.use_get {
+                let separator = if self.url.contains('?') { '&' } else { '?' };
+                Request::get(format!(
+                    "{}{}dns={}",
+                    self.url,
+                    separator,
+                    BASE64URL_NOPAD.encode(&body)
+                ))
+                .header(ACCEPT, "application/dns-message")
+                .body(vec![])?
             } else {
-                RecordType::A
+                Request::post(&self.url)
+                    .header(CONTENT_TYPE, "application/dns-message")
+                    .body(body)?
             };
+            http3::send(request, self.timeout, &http::ca_files(&self.http_client)).await
+        }
+
+        async fn send(&self, name: &str, record_type: RecordType) -> Result<Message> {
             let mut query = Query::query(Name::from_str(name)?, record_type);
             query.set_query_class(DNSClass::IN);
             let mut message = Message::new();
@@ -152,42 +250,72 @@ mod dohietf {
                 .to_vec()
                 .with_context(|| {
                     format!(
-                        "failed to generate query message for name[{}], is_v6: {}",
-                        name, is_v6
+                        "failed to generate query message for name[{}] in type[{}]",
+                        name, record_type
                     )
                 })?;
-            let response_body = Client::new()
-                .post(&self.url)
-                .header(CONTENT_TYPE, "application/dns-message")
-                .timeout(self.timeout)
-                .body(body)
-                .send()?
-                .error_for_status()?
-                .bytes()?;
+            let response_body = if self.use_http3 {
+                self.send_over_http3(body).await?
+            } else {
+                self.send_over_http(body).await?
+            };
 
             let response_message = Message::from_vec(&response_body).with_context(|| {
                 format!(
-                    "failed to parse response from name[{}], is_v6: {}",
-                    name, is_v6
+                    "failed to parse response from name[{}] in type[{}]",
+                    name, record_type
                 )
             })?;
             tracing::debug!("query through DohIetf returns: {:?}", response_message);
+            Ok(response_message)
+        }
 
-            Ok(response_message
-                .answers()
-                .iter()
-                .filter_map(|r| {
-                    if let Some(data) = r.data() {
-                        match data {
-                            RData::A(ip) => Some(ip.0.into()),
-                            RData::AAAA(ip) => Some(ip.0.into()),
-                            _ => None,
-                        }
-                    } else {
-                        None
-                    }
-                })
-                .collect())
+        async fn send_over_http(&self, body: Vec<u8>) -> Result<Bytes> {
+            let client = http::client_of(&self.http_client)?;
+            let request = if self.use_get {
+                client
+                    .get(&self.url)
+                    .query(&[("dns", BASE64URL_NOPAD.encode(&body))])
+                    .header(ACCEPT, "application/dns-message")
+            } else {
+                client
+                    .post(&self.url)
+                    .header(CONTENT_TYPE, "application/dns-message")
+                    .body(body)
+            };
+            http::send(request.timeout(self.timeout))
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await
+        }
+    }
+
+    #[async_trait]
+    impl QueryProvider for DohIetfQueryProvider {
+        async fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
+            Ok(self.query_with_ttl(name, is_v6).await?.0)
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn query_with_ttl(
+            &self,
+            name: &str,
+            is_v6: bool,
+        ) -> Result<(Vec<IpAddr>, Option<u32>)> {
+            let record_type = if is_v6 {
+                RecordType::AAAA
+            } else {
+                RecordType::A
+            };
+            Ok(super::ips_of(self.send(name, record_type).await?.answers()))
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn query_cname(&self, name: &str) -> Result<Option<String>> {
+            Ok(super::cname_of(
+                self.send(name, RecordType::CNAME).await?.answers(),
+            ))
         }
     }
 }
@@ -196,103 +324,562 @@ mod dot {
     use std::{net::IpAddr, time::Duration};
 
     use anyhow::Result;
+    use async_trait::async_trait;
+
+    use crate::{
+        config::{BindConf, EdnsConf},
+        dns::DnsClient,
+    };
 
     use super::QueryProvider;
 
     pub(super) struct DotQueryProvider {
-        pub(super) name_server_host: String,
+        pub(super) name_server_hosts: Vec<String>,
         pub(super) name_server_port: Option<u16>,
         pub(super) timeout: Duration,
+        pub(super) socks5_proxy: Option<String>,
+        pub(super) edns: EdnsConf,
+        pub(super) bind: BindConf,
     }
 
+    impl DotQueryProvider {
+        fn client(&self, host: &str) -> Result<DnsClient> {
+            DnsClient::new(host, self.name_server_port, self.timeout, false, true)?
+                .with_socks5_proxy(self.socks5_proxy.as_deref())?
+                .with_edns(
+                    *self.edns.udp_payload_size(),
+                    self.edns.client_subnet().as_deref(),
+                )?
+                .with_bind(
+                    *self.bind.bind_address(),
+                    self.bind.bind_interface().as_deref(),
+                )
+        }
+    }
+
+    #[async_trait]
     impl QueryProvider for DotQueryProvider {
+        async fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
+            Ok(self.query_with_ttl(name, is_v6).await?.0)
+        }
+
         #[tracing::instrument(skip(self), err)]
-        fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
-            super::query(
-                &self.name_server_host,
-                self.name_server_port,
-                self.timeout,
-                false,
-                true,
-                name,
-                is_v6,
+        async fn query_with_ttl(
+            &self,
+            name: &str,
+            is_v6: bool,
+        ) -> Result<(Vec<IpAddr>, Option<u32>)> {
+            super::with_fallback(&self.name_server_hosts, |host| async move {
+                super::query(&self.client(host)?, name, is_v6).await
+            })
+            .await
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn query_cname(&self, name: &str) -> Result<Option<String>> {
+            super::with_fallback(&self.name_server_hosts, |host| async move {
+                super::query_cname(&self.client(host)?, name).await
+            })
+            .await
+        }
+    }
+}
+
+mod authoritative {
+    use std::{iter, net::IpAddr, time::Duration};
+
+    use anyhow::{anyhow, Result};
+    use async_trait::async_trait;
+    use hickory_proto::rr::{RData, Record, RecordType};
+
+    use crate::{config::BindConf, dns::DnsClient};
+
+    use super::QueryProvider;
+
+    pub(super) struct AuthoritativeQueryProvider {
+        pub(super) name_server_host: String,
+        pub(super) name_server_port: Option<u16>,
+        pub(super) zone: Option<String>,
+        pub(super) timeout: Duration,
+        pub(super) bind: BindConf,
+    }
+
+    impl AuthoritativeQueryProvider {
+        fn client(&self, host: &str, port: Option<u16>) -> Result<DnsClient> {
+            DnsClient::new(host, port, self.timeout, true, false)?.with_bind(
+                *self.bind.bind_address(),
+                self.bind.bind_interface().as_deref(),
             )
         }
+
+        /// Name servers of the zone, or of the closest parent of `name` with NS records.
+        async fn name_servers(&self, name: &str) -> Result<Vec<String>> {
+            let resolver = self.client(&self.name_server_host, self.name_server_port)?;
+            let name = name.trim_end_matches('.');
+            let candidates: Vec<&str> = match &self.zone {
+                Some(zone) => vec![zone.as_str()],
+                // Top-level domains are skipped.
+                None => iter::once(name)
+                    .chain(name.match_indices('.').map(|(i, _)| &name[i + 1..]))
+                    .filter(|candidate| candidate.contains('.'))
+                    .collect(),
+            };
+            for candidate in candidates {
+                let response = resolver.query(candidate, RecordType::NS, None).await?;
+                // NS records of the target are returned if the candidate is a CNAME.
+                let name_servers: Vec<String> = response
+                    .answers()
+                    .iter()
+                    .filter(|r| {
+                        r.name()
+                            .to_string()
+                            .trim_end_matches('.')
+                            .eq_ignore_ascii_case(candidate)
+                    })
+                    .filter_map(|r| match r.data() {
+                        Some(RData::NS(ns)) => Some(ns.0.to_string().trim_end_matches('.').into()),
+                        _ => None,
+                    })
+                    .collect();
+                if !name_servers.is_empty() {
+                    tracing::debug!("name servers of {}: {:?}", candidate, name_servers);
+                    return Ok(name_servers);
+                }
+            }
+            Err(anyhow!("no NS records of {} or its parents", name))
+        }
+
+        /// Send a query to name servers in order, until one of them answers.
+        async fn query_name_servers<T>(
+            &self,
+            name: &str,
+            record_type: RecordType,
+            f: impl Fn(&[Record]) -> T,
+        ) -> Result<T> {
+            let name_servers = self.name_servers(name).await?;
+            super::with_fallback(&name_servers, |name_server| {
+                let f = &f;
+                async move {
+                    let client = self.client(name_server, None)?;
+                    Ok(f(client.query(name, record_type, None).await?.answers()))
+                }
+            })
+            .await
+        }
+    }
+
+    #[async_trait]
+    impl QueryProvider for AuthoritativeQueryProvider {
+        async fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
+            Ok(self.query_with_ttl(name, is_v6).await?.0)
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn query_with_ttl(
+            &self,
+            name: &str,
+            is_v6: bool,
+        ) -> Result<(Vec<IpAddr>, Option<u32>)> {
+            let record_type = if is_v6 {
+                RecordType::AAAA
+            } else {
+                RecordType::A
+            };
+            self.query_name_servers(name, record_type, super::ips_of)
+                .await
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn query_cname(&self, name: &str) -> Result<Option<String>> {
+            self.query_name_servers(name, RecordType::CNAME, super::cname_of)
+                .await
+        }
     }
 }
 
-fn query(
-    server_host: &str,
-    server_port: Option<u16>,
-    timeout: Duration,
-    is_udp: bool,
-    is_tls: bool,
-    name: &str,
-    is_v6: bool,
-) -> Result<Vec<IpAddr>> {
-    let client = DnsClient::new(server_host, server_port, timeout, is_udp, is_tls)?;
+mod retry {
+    use std::{future::Future, net::IpAddr, time::Duration};
+
+    use anyhow::Result;
+    use async_trait::async_trait;
+
+    use super::QueryProvider;
+
+    pub(super) struct RetryQueryProvider {
+        pub(super) inner: Box<dyn QueryProvider>,
+        pub(super) retries: u32,
+        pub(super) backoff: Duration,
+    }
+
+    impl RetryQueryProvider {
+        async fn retry<T, F, Fut>(&self, f: F) -> Result<T>
+        where
+            F: Fn() -> Fut,
+            Fut: Future<Output = Result<T>>,
+        {
+            let mut backoff = self.backoff;
+            for attempt in 0..self.retries {
+                match f().await {
+                    Ok(t) => return Ok(t),
+                    Err(e) => {
+                        tracing::warn!(
+                            "query failed, retry {}/{} in {:?}: {:?}",
+                            attempt + 1,
+                            self.retries,
+                            backoff,
+                            e
+                        );
+                        tokio::time::sleep(backoff).await;
+                        backoff *= 2;
+                    }
+                }
+            }
+            f().await
+        }
+    }
+
+    #[async_trait]
+    impl QueryProvider for RetryQueryProvider {
+        async fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
+            self.retry(|| self.inner.query(name, is_v6)).await
+        }
+
+        async fn query_with_ttl(
+            &self,
+            name: &str,
+            is_v6: bool,
+        ) -> Result<(Vec<IpAddr>, Option<u32>)> {
+            self.retry(|| self.inner.query_with_ttl(name, is_v6)).await
+        }
+
+        async fn query_cname(&self, name: &str) -> Result<Option<String>> {
+            self.retry(|| self.inner.query_cname(name)).await
+        }
+    }
+}
+
+mod provider {
+    use std::net::IpAddr;
+
+    use anyhow::Result;
+    use async_trait::async_trait;
+
+    use crate::update::UpdateProvider;
+
+    use super::QueryProvider;
+
+    pub(super) struct ProviderQueryProvider {
+        pub(super) update_provider: Box<dyn UpdateProvider>,
+    }
+
+    #[async_trait]
+    impl QueryProvider for ProviderQueryProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
+            self.update_provider.query_ips(name, is_v6).await
+        }
+    }
+}
+
+mod cache {
+    use std::{
+        collections::HashMap,
+        net::IpAddr,
+        sync::Mutex,
+        time::{Duration, Instant},
+    };
+
+    use anyhow::Result;
+    use async_trait::async_trait;
+
+    use super::QueryProvider;
+
+    type Answers = HashMap<(String, String, bool), (Vec<IpAddr>, Instant)>;
+
+    /// Answers cached until their ttl expires, keyed by the provider, the name and the family. It
+    /// is `None` if the cache is disabled.
+    static ANSWERS: Mutex<Option<Answers>> = Mutex::new(None);
+
+    pub(super) fn enable() {
+        let mut answers = ANSWERS.lock().expect("answer cache is poisoned");
+        answers.get_or_insert_with(HashMap::new);
+    }
+
+    pub(super) fn is_enabled() -> bool {
+        ANSWERS.lock().expect("answer cache is poisoned").is_some()
+    }
+
+    pub(super) fn invalidate(name: &str) {
+        if let Some(answers) = ANSWERS.lock().expect("answer cache is poisoned").as_mut() {
+            answers.retain(|(_, n, _), _| n != name);
+        }
+    }
+
+    pub(super) struct CachedQueryProvider {
+        pub(super) inner: Box<dyn QueryProvider>,
+        pub(super) key: String,
+    }
+
+    #[async_trait]
+    impl QueryProvider for CachedQueryProvider {
+        async fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
+            Ok(self.query_with_ttl(name, is_v6).await?.0)
+        }
+
+        async fn query_with_ttl(
+            &self,
+            name: &str,
+            is_v6: bool,
+        ) -> Result<(Vec<IpAddr>, Option<u32>)> {
+            let key = (self.key.clone(), name.to_string(), is_v6);
+            let now = Instant::now();
+            if let Some(answers) = ANSWERS.lock().expect("answer cache is poisoned").as_mut() {
+                answers.retain(|_, (_, expire)| *expire > now);
+                if let Some((ips, expire)) = answers.get(&key) {
+                    tracing::debug!("use cached answers of {}: {:?}", name, ips);
+                    return Ok((ips.clone(), Some((*expire - now).as_secs() as u32)));
+                }
+            }
+            let (ips, ttl) = self.inner.query_with_ttl(name, is_v6).await?;
+            // An empty answer is not cached, a new record may be created soon.
+            if let Some(ttl) = ttl.filter(|ttl| *ttl > 0 && !ips.is_empty()) {
+                if let Some(answers) = ANSWERS.lock().expect("answer cache is poisoned").as_mut() {
+                    let expire = now + Duration::from_secs(u64::from(ttl));
+                    answers.insert(key, (ips.clone(), expire));
+                }
+            }
+            Ok((ips, ttl))
+        }
+
+        async fn query_cname(&self, name: &str) -> Result<Option<String>> {
+            self.inner.query_cname(name).await
+        }
+    }
+}
+
+/// Cache answers of query providers until their ttl expires, it is for the daemon mode, where
+/// names are checked frequently.
+pub fn enable_answer_cache() {
+    cache::enable();
+}
+
+/// Drop cached answers of a name, e.g. after its records are changed.
+pub fn invalidate_answers(name: &str) {
+    cache::invalidate(name);
+}
+
+/// Try name servers in order, until one of them answers.
+async fn with_fallback<'h, T, F, Fut>(hosts: &'h [String], f: F) -> Result<T>
+where
+    F: Fn(&'h str) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut last_err = None;
+    for host in hosts {
+        match f(host).await {
+            Ok(t) => return Ok(t),
+            Err(e) => {
+                tracing::warn!("name server {} failed, try the next one: {:?}", host, e);
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow!("no name server")))
+}
+
+/// Ips in answers, with the minimal ttl of them.
+fn ips_of(answers: &[Record]) -> (Vec<IpAddr>, Option<u32>) {
+    let mut ttl: Option<u32> = None;
+    let ips = answers
+        .iter()
+        .filter_map(|r| {
+            let ip = match r.data()? {
+                RData::A(ip) => IpAddr::from(ip.0),
+                RData::AAAA(ip) => IpAddr::from(ip.0),
+                _ => return None,
+            };
+            ttl = Some(ttl.map_or(r.ttl(), |t| t.min(r.ttl())));
+            Some(ip)
+        })
+        .collect();
+    (ips, ttl)
+}
+
+async fn query(client: &DnsClient, name: &str, is_v6: bool) -> Result<(Vec<IpAddr>, Option<u32>)> {
     let record_type = if is_v6 {
         RecordType::AAAA
     } else {
         RecordType::A
     };
-    let dns_response = client.query(name, record_type, Some(is_v6))?;
-    Ok(dns_response
-        .answers()
-        .iter()
-        .filter_map(|r| {
-            if let Some(data) = r.data() {
-                match data {
-                    RData::A(ip) => Some(ip.0.into()),
-                    RData::AAAA(ip) => Some(ip.0.into()),
-                    _ => None,
-                }
-            } else {
-                None
-            }
-        })
-        .collect())
+    let dns_response = client.query(name, record_type, Some(is_v6)).await?;
+    Ok(ips_of(dns_response.answers()))
+}
+
+async fn query_cname(client: &DnsClient, name: &str) -> Result<Option<String>> {
+    let dns_response = client.query(name, RecordType::CNAME, None).await?;
+    Ok(cname_of(dns_response.answers()))
+}
+
+/// Settings of the http client of DoH, `socks5_proxy` is its proxy if it is set.
+fn doh_http_client(
+    socks5_proxy: &Option<String>,
+    http_client: &HttpClientConf,
+) -> Result<HttpClientConf> {
+    let mut http_client = http_client.clone();
+    if let Some(proxy) = socks5_proxy {
+        if http_client.proxy().is_some() {
+            bail!("socks5_proxy can't be used with proxy");
+        }
+        // Names are resolved by the proxy.
+        http_client.set_proxy(Some(format!(
+            "socks5h://{}",
+            crate::dns::socks5_proxy_addr(proxy)
+        )));
+    }
+    Ok(http_client)
+}
+
+fn cname_of(answers: &[Record]) -> Option<String> {
+    answers.iter().find_map(|r| match r.data() {
+        Some(RData::CNAME(cname)) => Some(cname.0.to_string()),
+        _ => None,
+    })
 }
 
 pub fn init_query_provider(
     query_provider_type: &QueryProviderType,
-    _config: &Config,
+    config: &Config,
+) -> Result<Box<dyn QueryProvider>> {
+    let mut provider = init_query_provider_without_retry(query_provider_type, config)?;
+    if let Some(retry) = query_provider_type.retry().filter(|r| r.retries() > 0) {
+        provider = Box::new(retry::RetryQueryProvider {
+            inner: provider,
+            retries: retry.retries(),
+            backoff: retry.retry_backoff().unwrap_or(Duration::from_secs(1)),
+        });
+    }
+    if cache::is_enabled() && !matches!(query_provider_type, QueryProviderType::Dummy) {
+        provider = Box::new(cache::CachedQueryProvider {
+            inner: provider,
+            key: format!("{:?}", query_provider_type),
+        });
+    }
+    Ok(provider)
+}
+
+fn init_query_provider_without_retry(
+    query_provider_type: &QueryProviderType,
+    config: &Config,
 ) -> Result<Box<dyn QueryProvider>> {
     match query_provider_type {
         QueryProviderType::Dns(dns_query_params) => Ok(Box::new(DnsQueryProvider {
-            name_server_host: dns_query_params.name_server_host().clone(),
+            name_server_hosts: dns_query_params.name_server_host().as_slice().to_vec(),
             name_server_port: *dns_query_params.name_server_port(),
             timeout: dns_query_params.timeout().unwrap_or(DEFAULT_TIMEOUT),
             use_tcp: dns_query_params.use_tcp().unwrap_or(false),
+            socks5_proxy: dns_query_params.socks5_proxy().clone(),
+            edns: dns_query_params.edns().clone(),
+            bind: dns_query_params.bind().clone(),
         })),
         QueryProviderType::DohGoogle(doh_google_query_params) => {
             Ok(Box::new(DohGoogleQueryProvider {
                 url: doh_google_query_params.url().clone(),
                 name_key: doh_google_query_params.name_key().clone(),
                 timeout: doh_google_query_params.timeout().unwrap_or(DEFAULT_TIMEOUT),
+                http_client: doh_http_client(
+                    doh_google_query_params.socks5_proxy(),
+                    doh_google_query_params.http_client(),
+                )?,
+            }))
+        }
+        QueryProviderType::DohIetf(doh_ietf_query_params) => {
+            let http_client = doh_http_client(
+                doh_ietf_query_params.socks5_proxy(),
+                doh_ietf_query_params.http_client(),
+            )?;
+            // HTTP/3 is sent directly over quic.
+            if doh_ietf_query_params.use_http3()
+                && http_client
+                    .proxy()
+                    .as_deref()
+                    .is_some_and(|p| !p.is_empty())
+            {
+                bail!("use_http3 can't be used with socks5_proxy or proxy");
+            }
+            Ok(Box::new(DohIetfQueryProvider {
+                url: doh_ietf_query_params.url().clone(),
+                timeout: doh_ietf_query_params.timeout().unwrap_or(DEFAULT_TIMEOUT),
+                use_get: doh_ietf_query_params.use_get(),
+                use_http3: doh_ietf_query_params.use_http3(),
+                http_client,
+            }))
+        }
+        QueryProviderType::Authoritative(params) => {
+            Ok(Box::new(authoritative::AuthoritativeQueryProvider {
+                name_server_host: params.name_server_host().clone(),
+                name_server_port: *params.name_server_port(),
+                zone: params.zone().clone(),
+                timeout: params.timeout().unwrap_or(DEFAULT_TIMEOUT),
+                bind: params.bind().clone(),
             }))
         }
-        QueryProviderType::DohIetf(doh_ietf_query_params) => Ok(Box::new(DohIetfQueryProvider {
-            url: doh_ietf_query_params.url().clone(),
-            timeout: doh_ietf_query_params.timeout().unwrap_or(DEFAULT_TIMEOUT),
-        })),
         QueryProviderType::Dot(dot_query_params) => Ok(Box::new(DotQueryProvider {
-            name_server_host: dot_query_params.name_server_host().clone(),
+            name_server_hosts: dot_query_params.name_server_host().as_slice().to_vec(),
             name_server_port: *dot_query_params.name_server_port(),
             timeout: dot_query_params.timeout().unwrap_or(DEFAULT_TIMEOUT),
+            socks5_proxy: dot_query_params.socks5_proxy().clone(),
+            edns: dot_query_params.edns().clone(),
+            bind: dot_query_params.bind().clone(),
         })),
         QueryProviderType::Dummy => Ok(Box::new(DummyQueryProvider)),
+        QueryProviderType::Provider => {
+            bail!("Provider query provider can only be used with an update provider")
+        }
+        QueryProviderType::Custom { name, options } => registry::query_provider(name, options),
+        QueryProviderType::Plugin {
+            plugin,
+            options,
+            timeout,
+        } => Ok(Box::new(PluginProvider::new(
+            config, plugin, options, *timeout,
+        )?)),
     }
 }
 
-pub trait QueryProvider {
-    fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>>;
+/// A query provider which asks the update provider for the content of records.
+pub fn init_provider_query_provider(
+    update_provider: Box<dyn UpdateProvider>,
+) -> Box<dyn QueryProvider> {
+    Box::new(provider::ProviderQueryProvider { update_provider })
+}
+
+#[async_trait]
+pub trait QueryProvider: Send + Sync {
+    async fn query(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>>;
+
+    /// The ips with the minimal ttl of them, if it is known.
+    async fn query_with_ttl(&self, name: &str, is_v6: bool) -> Result<(Vec<IpAddr>, Option<u32>)> {
+        Ok((self.query(name, is_v6).await?, None))
+    }
+
+    /// The target of the CNAME of a name, if it exists.
+    async fn query_cname(&self, name: &str) -> Result<Option<String>> {
+        bail!(
+            "querying CNAME of {} is not supported by this provider",
+            name
+        )
+    }
 }
 
 pub struct DummyQueryProvider;
 
+#[async_trait]
 impl QueryProvider for DummyQueryProvider {
-    fn query(&self, _name: &str, _is_v6: bool) -> Result<Vec<IpAddr>> {
+    async fn query(&self, _name: &str, _is_v6: bool) -> Result<Vec<IpAddr>> {
         Ok(vec![])
     }
+
+    async fn query_cname(&self, _name: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
 }
@@ -0,0 +1,86 @@
+//! Providers registered by a program embedding the renew engine, they are used by
+//! `type = "Custom"` in name configs with their names, e.g.
+//! `update_provider_type = { type = "Custom", name = "my-registrar", options = { zone = "a.com" } }`,
+//! `options` is passed to the factory of the provider.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::{anyhow, Result};
+
+use crate::{ip::IpProvider, query::QueryProvider, update::UpdateProvider};
+
+type Factory<T> = Box<dyn Fn(&serde_json::Value) -> Result<Box<T>> + Send + Sync>;
+
+static QUERY_PROVIDERS: Mutex<Option<HashMap<String, Factory<dyn QueryProvider>>>> =
+    Mutex::new(None);
+static IP_PROVIDERS: Mutex<Option<HashMap<String, Factory<dyn IpProvider>>>> = Mutex::new(None);
+static UPDATE_PROVIDERS: Mutex<Option<HashMap<String, Factory<dyn UpdateProvider>>>> =
+    Mutex::new(None);
+
+fn register<T: ?Sized>(
+    registry: &Mutex<Option<HashMap<String, Factory<T>>>>,
+    name: &str,
+    factory: Factory<T>,
+) {
+    registry
+        .lock()
+        .expect("registry is poisoned")
+        .get_or_insert_with(HashMap::new)
+        .insert(name.to_string(), factory);
+}
+
+fn create<T: ?Sized>(
+    registry: &Mutex<Option<HashMap<String, Factory<T>>>>,
+    kind: &str,
+    name: &str,
+    options: &serde_json::Value,
+) -> Result<Box<T>> {
+    let registry = registry.lock().expect("registry is poisoned");
+    let factory = registry
+        .as_ref()
+        .and_then(|r| r.get(name))
+        .ok_or_else(|| anyhow!("Custom {} provider is not registered: {}", kind, name))?;
+    factory(options)
+}
+
+/// Register a query provider, a provider registered with the same name is replaced.
+pub fn register_query_provider(
+    name: &str,
+    factory: impl Fn(&serde_json::Value) -> Result<Box<dyn QueryProvider>> + Send + Sync + 'static,
+) {
+    register(&QUERY_PROVIDERS, name, Box::new(factory));
+}
+
+/// Register an ip provider, a provider registered with the same name is replaced.
+pub fn register_ip_provider(
+    name: &str,
+    factory: impl Fn(&serde_json::Value) -> Result<Box<dyn IpProvider>> + Send + Sync + 'static,
+) {
+    register(&IP_PROVIDERS, name, Box::new(factory));
+}
+
+/// Register an update provider, a provider registered with the same name is replaced.
+pub fn register_update_provider(
+    name: &str,
+    factory: impl Fn(&serde_json::Value) -> Result<Box<dyn UpdateProvider>> + Send + Sync + 'static,
+) {
+    register(&UPDATE_PROVIDERS, name, Box::new(factory));
+}
+
+pub(crate) fn query_provider(
+    name: &str,
+    options: &serde_json::Value,
+) -> Result<Box<dyn QueryProvider>> {
+    create(&QUERY_PROVIDERS, "query", name, options)
+}
+
+pub(crate) fn ip_provider(name: &str, options: &serde_json::Value) -> Result<Box<dyn IpProvider>> {
+    create(&IP_PROVIDERS, "ip", name, options)
+}
+
+pub(crate) fn update_provider(
+    name: &str,
+    options: &serde_json::Value,
+) -> Result<Box<dyn UpdateProvider>> {
+    create(&UPDATE_PROVIDERS, "update", name, options)
+}
@@ -0,0 +1,225 @@
+//! Rhai scripts of generic http providers, they can do what templates can't, e.g. sign a request.
+//! A script may define two functions:
+//!
+//! - `fn request(req)`, `req` is `#{method, url, headers, body, vars}`, `body` is `()` if the
+//!   request has no body, and `vars` are the ones of templates. It returns the request to send.
+//! - `fn response(resp)`, `resp` is `#{status, headers, body}` of a successful response. It fails
+//!   the update by returning `false` or `throw`.
+//!
+//! Besides the builtins of rhai, `sha256(text)`, `hmac_sha256(key, text)`, `hex(blob)`,
+//! `base64(blob)` and `unix_time()` are available.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Result};
+use data_encoding::{BASE64, HEXLOWER};
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use reqwest::{header::HeaderMap, Client, Method, RequestBuilder};
+use rhai::{Blob, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+
+/// A script won't run forever.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A request of a generic http provider before it is sent.
+pub(crate) struct HttpRequest {
+    pub(crate) method: Method,
+    pub(crate) url: String,
+    pub(crate) headers: BTreeMap<String, String>,
+    pub(crate) body: Option<String>,
+}
+
+impl HttpRequest {
+    pub(crate) fn new(method: Method, url: String) -> Self {
+        Self {
+            method,
+            url,
+            headers: BTreeMap::new(),
+            body: None,
+        }
+    }
+
+    pub(crate) fn with_body(mut self, content_type: &str, body: String) -> Self {
+        self.headers
+            .insert("content-type".to_string(), content_type.to_string());
+        self.body = Some(body);
+        self
+    }
+
+    pub(crate) fn builder(self, client: &Client) -> RequestBuilder {
+        let mut req_builder = client.request(self.method, self.url);
+        for (name, value) in self.headers {
+            req_builder = req_builder.header(name, value);
+        }
+        if let Some(body) = self.body {
+            req_builder = req_builder.body(body);
+        }
+        req_builder
+    }
+}
+
+pub(crate) struct Script {
+    engine: Engine,
+    ast: AST,
+    path: String,
+}
+
+fn sha256(text: &str) -> Blob {
+    openssl::sha::sha256(text.as_bytes()).to_vec()
+}
+
+fn hmac_sha256(key: &str, text: &str) -> Result<Blob, Box<EvalAltResult>> {
+    let sign = || -> Result<Blob, openssl::error::ErrorStack> {
+        let key = PKey::hmac(key.as_bytes())?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(text.as_bytes())?;
+        signer.sign_to_vec()
+    };
+    sign().map_err(|e| e.to_string().into())
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn headers_map(headers: &HeaderMap) -> Map {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.as_str().into(),
+                String::from_utf8_lossy(value.as_bytes()).to_string().into(),
+            )
+        })
+        .collect()
+}
+
+impl Script {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.register_fn("sha256", sha256);
+        engine.register_fn("hmac_sha256", hmac_sha256);
+        engine.register_fn("hex", |blob: Blob| HEXLOWER.encode(&blob));
+        engine.register_fn("base64", |blob: Blob| BASE64.encode(&blob));
+        engine.register_fn("unix_time", unix_time);
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| anyhow!("invalid script {:?}: {}", path, e))?;
+        Ok(Self {
+            engine,
+            ast,
+            path: path.display().to_string(),
+        })
+    }
+
+    fn has_fn(&self, name: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == 1)
+    }
+
+    fn call(&self, name: &str, arg: Map) -> Result<Dynamic> {
+        self.engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, name, (arg,))
+            .map_err(|e| anyhow!("{} of script {} fails: {}", name, self.path, e))
+    }
+
+    /// Pass the request through `fn request(req)` of the script, if it is defined.
+    pub(crate) fn shape_request(
+        &self,
+        request: HttpRequest,
+        vars: &HashMap<String, String>,
+    ) -> Result<HttpRequest> {
+        if !self.has_fn("request") {
+            return Ok(request);
+        }
+        let mut req = Map::new();
+        req.insert("method".into(), request.method.to_string().into());
+        req.insert("url".into(), request.url.into());
+        req.insert(
+            "headers".into(),
+            request
+                .headers
+                .into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect::<Map>()
+                .into(),
+        );
+        req.insert(
+            "body".into(),
+            request.body.map(Dynamic::from).unwrap_or(Dynamic::UNIT),
+        );
+        req.insert(
+            "vars".into(),
+            vars.iter()
+                .map(|(k, v)| (k.as_str().into(), v.clone().into()))
+                .collect::<Map>()
+                .into(),
+        );
+
+        let mut req = self
+            .call("request", req)?
+            .try_cast::<Map>()
+            .with_context(|| format!("request of script {} doesn't return a map", self.path))?;
+        let mut take_string = |field: &str| -> Result<Option<String>> {
+            match req.remove(field) {
+                Some(value) if value.is_unit() => Ok(None),
+                Some(value) => value
+                    .into_string()
+                    .map(Some)
+                    .map_err(|t| anyhow!("{} of the request is {}, not a string", field, t)),
+                None => Ok(None),
+            }
+        };
+        let method = take_string("method")?.context("method of the request is missing")?;
+        let url = take_string("url")?.context("url of the request is missing")?;
+        let body = take_string("body")?;
+        let headers = match req.remove("headers") {
+            Some(headers) => headers
+                .try_cast::<Map>()
+                .context("headers of the request is not a map")?
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        Ok(HttpRequest {
+            method: Method::from_bytes(method.to_uppercase().as_bytes())
+                .map_err(|_| anyhow!("invalid method of the request: {}", method))?,
+            url,
+            headers,
+            body,
+        })
+    }
+
+    /// Check the response by `fn response(resp)` of the script, if it is defined.
+    pub(crate) fn check_response(
+        &self,
+        status: u16,
+        headers: &HeaderMap,
+        body: &str,
+    ) -> Result<()> {
+        if !self.has_fn("response") {
+            return Ok(());
+        }
+        let mut resp = Map::new();
+        resp.insert("status".into(), (status as i64).into());
+        resp.insert("headers".into(), headers_map(headers).into());
+        resp.insert("body".into(), body.to_string().into());
+        match self.call("response", resp)?.as_bool() {
+            Ok(false) => Err(anyhow!(
+                "response is rejected by script {}: {}",
+                self.path,
+                body.trim()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
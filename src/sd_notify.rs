@@ -0,0 +1,45 @@
+use std::{env, os::unix::net::UnixDatagram, process, time::Duration};
+
+use anyhow::Result;
+
+/// Send a state to systemd over `NOTIFY_SOCKET`, e.g. `READY=1`. It does nothing if the daemon
+/// isn't run by systemd with `Type=notify`.
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send(&path.to_string_lossy(), state) {
+        tracing::warn!("failed to notify systemd of {:?}: {:?}", state, e);
+    }
+}
+
+fn send(path: &str, state: &str) -> Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    match path.strip_prefix('@') {
+        // A socket in the abstract namespace.
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            let addr = SocketAddr::from_abstract_name(name.as_bytes())?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => anyhow::bail!("abstract socket is only supported on linux"),
+        None => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+/// How often `WATCHDOG=1` is sent, it is half of `WatchdogSec=` of the service, or `None` if the
+/// watchdog isn't enabled for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use age::x25519::Identity;
+use anyhow::{anyhow, Context, Result};
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::config::{Config, NameState, StateBackend};
+
+/// The magic at the beginning of an age encrypted file.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+
+/// Where states of names are kept, a state is keyed by the file stem of its name config.
+pub struct StateStore {
+    backend: Backend,
+    /// States are encrypted to the public key of it, if it is set.
+    identity: Option<Identity>,
+}
+
+enum Backend {
+    /// A file for each state in the dir.
+    Files(PathBuf),
+    /// A row for each state in the `states` table.
+    Sqlite(Mutex<Connection>),
+    /// All states in one document, it is rewritten as a whole under the lock for each save.
+    Single(PathBuf, Mutex<()>),
+}
+
+/// The document of the single state backend.
+pub fn file_path(config: &Config) -> PathBuf {
+    config
+        .state_file()
+        .clone()
+        .unwrap_or_else(|| config.name_state_dir().join("states.toml"))
+}
+
+/// The database of the sqlite state backend.
+pub fn db_path(config: &Config) -> PathBuf {
+    config
+        .state_db()
+        .clone()
+        .unwrap_or_else(|| config.name_state_dir().join("states.sqlite"))
+}
+
+fn open_db(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)
+        .with_context(|| format!("failed to open state database: {:?}", path))?;
+    // With WAL, a write is either in the database or not after a power loss, and readers, e.g.
+    // `status`, don't block the daemon.
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS states (key TEXT PRIMARY KEY, content BLOB NOT NULL)",
+    )?;
+    Ok(conn)
+}
+
+fn lock(conn: &Mutex<Connection>) -> MutexGuard<'_, Connection> {
+    conn.lock().expect("state database is poisoned")
+}
+
+impl StateStore {
+    pub fn new(config: &Config) -> Result<Self> {
+        let identity = config
+            .state_key_file()
+            .as_ref()
+            .map(|p| Self::read_identity(p))
+            .transpose()?;
+        let backend = match config.state_backend() {
+            StateBackend::Files => Backend::Files(config.name_state_dir().clone()),
+            StateBackend::Sqlite => Backend::Sqlite(Mutex::new(open_db(&db_path(config))?)),
+            StateBackend::Single => Backend::Single(file_path(config), Mutex::new(())),
+        };
+        Ok(Self { backend, identity })
+    }
+
+    /// Read the first x25519 identity from an age key file, which is generated by `age-keygen`.
+    fn read_identity(path: &Path) -> Result<Identity> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read state key file: {:?}", path))?;
+        content
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty() && !l.starts_with('#'))
+            .ok_or_else(|| anyhow!("no key found in state key file: {:?}", path))
+            .and_then(|l| {
+                Identity::from_str(l)
+                    .map_err(|e| anyhow!("invalid key in state key file {:?}: {}", path, e))
+            })
+    }
+
+    /// Decode a state, which may be encrypted, `source` is used in errors.
+    fn decode(&self, content: Vec<u8>, source: &dyn std::fmt::Debug) -> Result<NameState> {
+        toml::from_str(&self.decrypt(content, source)?)
+            .with_context(|| format!("failed to read from name state file: {:?}", source))
+    }
+
+    fn encode(&self, state: &NameState) -> Result<Vec<u8>> {
+        self.encrypt(toml::to_string(state)?)
+    }
+
+    fn decrypt(&self, mut content: Vec<u8>, source: &dyn std::fmt::Debug) -> Result<String> {
+        if content.starts_with(AGE_MAGIC) {
+            let identity = self.identity.as_ref().ok_or_else(|| {
+                anyhow!(
+                    "state file {:?} is encrypted, but no state_key_file",
+                    source
+                )
+            })?;
+            content = age::decrypt(identity, &content)
+                .with_context(|| format!("failed to decrypt state file: {:?}", source))?;
+        }
+        // A plain state will be encrypted in the next save, if a key is set.
+        Ok(String::from_utf8(content)?)
+    }
+
+    fn encrypt(&self, content: String) -> Result<Vec<u8>> {
+        let mut content = content.into_bytes();
+        if let Some(identity) = &self.identity {
+            content = age::encrypt(&identity.to_public(), &content)?;
+        }
+        Ok(content)
+    }
+
+    /// Read all states in the document of the single backend, it is json if the extension of
+    /// the file is `json`, or toml.
+    fn read_document(&self, path: &Path) -> Result<BTreeMap<String, NameState>> {
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let content = self.decrypt(fs::read(path)?, &path)?;
+        let states = if is_json(path) {
+            serde_json::from_str(&content).map_err(anyhow::Error::from)
+        } else {
+            toml::from_str(&content).map_err(anyhow::Error::from)
+        };
+        states.with_context(|| format!("failed to read from state file: {:?}", path))
+    }
+
+    /// Write the document of the single backend to a temporary file, and rename it over the old
+    /// one, so it is never half-written.
+    fn write_document(&self, path: &Path, states: &BTreeMap<String, NameState>) -> Result<()> {
+        let content = if is_json(path) {
+            serde_json::to_string_pretty(states)?
+        } else {
+            toml::to_string(states)?
+        };
+        let content = self.encrypt(content)?;
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let mut file = fs::File::create(&tmp_path)
+            .with_context(|| format!("failed to create state file: {:?}", tmp_path))?;
+        file.write_all(&content)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("failed to rename {:?} to {:?}", tmp_path, path))?;
+        Ok(())
+    }
+
+    pub fn load(&self, key: &OsStr) -> Result<Option<NameState>> {
+        match &self.backend {
+            Backend::Files(dir) => {
+                let path = dir.join(key);
+                if !path.exists() {
+                    return Ok(None);
+                }
+                let content = fs::read(&path)?;
+                Ok(Some(self.decode(content, &path)?))
+            }
+            Backend::Sqlite(conn) => {
+                let content = lock(conn)
+                    .query_row(
+                        "SELECT content FROM states WHERE key = ?1",
+                        [key.to_string_lossy()],
+                        |row| row.get::<_, Vec<u8>>(0),
+                    )
+                    .optional()?;
+                content.map(|c| self.decode(c, &key)).transpose()
+            }
+            Backend::Single(path, lock) => {
+                let _guard = lock.lock().expect("state file is poisoned");
+                Ok(self
+                    .read_document(path)?
+                    .remove(key.to_string_lossy().as_ref()))
+            }
+        }
+    }
+
+    /// Load all states in the store, with their keys.
+    pub fn load_all(&self) -> Result<Vec<(OsString, NameState)>> {
+        let mut states = vec![];
+        match &self.backend {
+            Backend::Files(dir) => {
+                for entry in dir
+                    .read_dir()
+                    .with_context(|| format!("{:?} not found", dir))?
+                {
+                    let entry = entry?;
+                    if !entry.file_type()?.is_file() {
+                        continue;
+                    }
+                    let key = entry.file_name();
+                    // Hidden files, e.g. the lock of runs, are not states.
+                    if key.as_encoded_bytes().starts_with(b".") {
+                        continue;
+                    }
+                    match self.load(&key) {
+                        Ok(Some(state)) => states.push((key, state)),
+                        Ok(None) => {}
+                        Err(e) => tracing::warn!("skip invalid state file {:?}: {:?}", key, e),
+                    }
+                }
+            }
+            Backend::Sqlite(conn) => {
+                let rows = {
+                    let conn = lock(conn);
+                    let mut stmt = conn.prepare("SELECT key, content FROM states")?;
+                    let rows = stmt
+                        .query_map([], |row| {
+                            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+                        })?
+                        .collect::<rusqlite::Result<Vec<_>>>()?;
+                    rows
+                };
+                for (key, content) in rows {
+                    match self.decode(content, &key) {
+                        Ok(state) => states.push((OsString::from(key), state)),
+                        Err(e) => tracing::warn!("skip invalid state {:?}: {:?}", key, e),
+                    }
+                }
+            }
+            Backend::Single(path, lock) => {
+                let _guard = lock.lock().expect("state file is poisoned");
+                states.extend(
+                    self.read_document(path)?
+                        .into_iter()
+                        .map(|(key, state)| (OsString::from(key), state)),
+                );
+            }
+        }
+        states.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(states)
+    }
+
+    pub fn save(&self, key: &OsStr, state: &NameState) -> Result<()> {
+        let content = self.encode(state)?;
+        match &self.backend {
+            Backend::Files(dir) => Ok(fs::write(dir.join(key), content)?),
+            Backend::Single(path, lock) => {
+                let _guard = lock.lock().expect("state file is poisoned");
+                let mut states = self.read_document(path)?;
+                states.insert(key.to_string_lossy().to_string(), state.clone());
+                self.write_document(path, &states)
+            }
+            Backend::Sqlite(conn) => {
+                lock(conn).execute(
+                    "INSERT INTO states (key, content) VALUES (?1, ?2) \
+                     ON CONFLICT (key) DO UPDATE SET content = excluded.content",
+                    (key.to_string_lossy(), content),
+                )?;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &OsStr) -> Result<()> {
+        match &self.backend {
+            Backend::Files(dir) => Ok(fs::remove_file(dir.join(key))?),
+            Backend::Single(path, lock) => {
+                let _guard = lock.lock().expect("state file is poisoned");
+                let mut states = self.read_document(path)?;
+                states.remove(key.to_string_lossy().as_ref());
+                self.write_document(path, &states)
+            }
+            Backend::Sqlite(conn) => {
+                lock(conn).execute("DELETE FROM states WHERE key = ?1", [key.to_string_lossy()])?;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "json")
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufReader, Write},
+    net::IpAddr,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, state::StateStore, Tenant};
+
+/// The status of a name, which is shown to operators.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct NameStatus {
+    pub tenant: Option<String>,
+    /// The key of its state, it is the file stem of the name config.
+    pub key: String,
+    pub name: String,
+    pub last_renew: Option<u64>,
+    pub next: u64,
+    pub ips: Vec<IpAddr>,
+    pub last_error: Option<String>,
+    /// The number of consecutive failed renews.
+    #[serde(default)]
+    pub failures: u32,
+    /// When records of families are created for the first time, keyed by the family.
+    #[serde(default)]
+    pub created: BTreeMap<String, u64>,
+}
+
+/// Build statuses from state files of a tenant.
+pub fn from_states(config: &Config, tenant: Option<&str>) -> Result<Vec<NameStatus>> {
+    Ok(StateStore::new(config)?
+        .load_all()?
+        .into_iter()
+        .map(|(key, state)| NameStatus {
+            tenant: tenant.map(ToString::to_string),
+            key: key.to_string_lossy().to_string(),
+            name: state.name().clone(),
+            last_renew: state.last_renew(),
+            next: state.next(),
+            ips: state.ips().clone(),
+            last_error: state.last_error().clone(),
+            failures: state.failures(),
+            created: state
+                .families()
+                .iter()
+                .filter_map(|(family, s)| Some((family.clone(), s.created_at()?)))
+                .collect(),
+        })
+        .collect())
+}
+
+/// A summary of the last renew of names, e.g. for `systemctl status`.
+pub fn summary(statuses: &[NameStatus]) -> String {
+    let mut failed = statuses.iter().filter(|s| s.last_error.is_some());
+    let failed_count = failed.clone().count();
+    let mut summary = format!("{} names, {} failed", statuses.len(), failed_count);
+    if let Some(first) = failed.next() {
+        summary.push_str(&format!(
+            ", {}: {}",
+            first.name,
+            first.last_error.as_deref().unwrap_or_default()
+        ));
+    }
+    summary
+}
+
+/// Print statuses of names, a line of tab-separated fields for each, or a json array.
+pub fn print(config: &Config, json: bool) -> Result<()> {
+    let statuses = load(config)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&statuses)?);
+        return Ok(());
+    }
+    for s in &statuses {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            match &s.tenant {
+                Some(tenant) => format!("{}/{}", tenant, s.name),
+                None => s.name.clone(),
+            },
+            s.last_renew
+                .map(format_time)
+                .unwrap_or_else(|| "-".to_string()),
+            format_time(s.next),
+            s.ips
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            s.last_error.as_deref().unwrap_or_default(),
+            s.created
+                .iter()
+                .map(|(family, at)| format!("{} created at {}", family, format_time(*at)))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+    }
+    Ok(())
+}
+
+pub fn format_time(secs: u64) -> String {
+    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(secs)).to_string()
+}
+
+/// Statuses shared between the daemon and its control socket.
+pub type SharedStatuses = Arc<Mutex<Vec<NameStatus>>>;
+
+/// Serve statuses of the daemon on a unix socket, each connection gets a json array of
+/// statuses, and is closed.
+pub fn serve(path: &Path, statuses: SharedStatuses) -> Result<()> {
+    if path.exists() {
+        // A socket left by a previous daemon.
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("failed to bind control socket: {:?}", path))?;
+    let path = PathBuf::from(path);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.map_err(From::from).and_then(|mut stream| {
+                let body = serde_json::to_vec(&*statuses.lock().expect("statuses are poisoned"))?;
+                stream.write_all(&body)?;
+                Ok::<_, anyhow::Error>(())
+            });
+            if let Err(e) = result {
+                tracing::warn!("failed to serve control socket {:?}: {:?}", path, e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Fetch statuses from the control socket of a daemon.
+pub fn fetch(path: &Path) -> Result<Vec<NameStatus>> {
+    let stream = UnixStream::connect(path)
+        .with_context(|| format!("failed to connect to control socket: {:?}", path))?;
+    Ok(serde_json::from_reader(BufReader::new(stream))?)
+}
+
+/// Fetch statuses from the daemon if its control socket is available, or else from state files
+/// of the main config and all tenants.
+pub fn load(config: &Config) -> Result<Vec<NameStatus>> {
+    if let Some(path) = config.control_socket() {
+        match fetch(path) {
+            Ok(statuses) => return Ok(statuses),
+            Err(e) => tracing::debug!("fallback to state files: {:?}", e),
+        }
+    }
+    let mut statuses = from_states(config, None)?;
+    for (name, path) in config.tenants() {
+        let tenant_config = Tenant::load_config(path)?;
+        statuses.extend(from_states(&tenant_config, Some(name))?);
+    }
+    Ok(statuses)
+}
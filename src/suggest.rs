@@ -0,0 +1,153 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use figment::{
+    providers::{Format, Toml},
+    Figment,
+};
+use futures_util::future::join_all;
+
+use crate::{
+    config::{Config, IpProviderType, QueryProviderType},
+    ip, query,
+};
+
+const ATTEMPTS: usize = 3;
+
+/// A name which has both A and AAAA records, it is used to probe resolvers.
+const PROBE_NAME: &str = "one.one.one.one";
+
+const IP_PROVIDERS: &[&str] = &[
+    "type = \"IfconfigIo\"\nurl = \"https://ifconfig.io/ip\"",
+    "type = \"IfconfigIo\"\nurl = \"https://api64.ipify.org\"",
+    "type = \"IfconfigIo\"\nurl = \"https://icanhazip.com\"",
+    "type = \"IfconfigIo\"\nurl = \"https://ifconfig.me/ip\"",
+    "type = \"SslipIo\"\nname_server_host = \"ns.sslip.io\"\nname = \"ip.sslip.io\"",
+];
+
+const QUERY_PROVIDERS: &[&str] = &[
+    "type = \"Dns\"\nname_server_host = \"1.1.1.1\"",
+    "type = \"Dns\"\nname_server_host = \"8.8.8.8\"",
+    "type = \"Dns\"\nname_server_host = \"223.5.5.5\"",
+    "type = \"Dot\"\nname_server_host = \"one.one.one.one\"",
+    "type = \"Dot\"\nname_server_host = \"dns.google\"",
+    "type = \"Dot\"\nname_server_host = \"dns.alidns.com\"",
+    "type = \"DohIetf\"\nurl = \"https://cloudflare-dns.com/dns-query\"",
+    "type = \"DohIetf\"\nurl = \"https://dns.alidns.com/dns-query\"",
+    "type = \"DohGoogle\"\nurl = \"https://dns.google/resolve\"\nname_key = \"name\"",
+];
+
+struct Probe {
+    snippet: &'static str,
+    successes: usize,
+    /// The median latency of successful attempts.
+    latency: Option<Duration>,
+    last_result: String,
+}
+
+impl Probe {
+    async fn run<F, Fut>(snippet: &'static str, attempt: F) -> Self
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<String>>,
+    {
+        let mut latencies = vec![];
+        let mut last_result = String::new();
+        for _ in 0..ATTEMPTS {
+            let started = Instant::now();
+            match attempt().await {
+                Ok(result) => {
+                    latencies.push(started.elapsed());
+                    last_result = result;
+                }
+                Err(e) => last_result = format!("{:#}", e),
+            }
+        }
+        latencies.sort();
+        Self {
+            snippet,
+            successes: latencies.len(),
+            latency: latencies.get(latencies.len() / 2).copied(),
+            last_result,
+        }
+    }
+
+    fn print(&self) {
+        println!(
+            "  {}/{}\t{:>6}\t{}\t{}",
+            self.successes,
+            ATTEMPTS,
+            self.latency
+                .map(|l| format!("{}ms", l.as_millis()))
+                .unwrap_or_else(|| "-".to_string()),
+            self.snippet.replace('\n', ", "),
+            self.last_result
+        );
+    }
+}
+
+/// Probe all candidates at the same time, the most reliable and fastest one comes first.
+async fn probe_all<F, Fut>(candidates: &[&'static str], attempt: F) -> Vec<Probe>
+where
+    F: Fn(&'static str) -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    let attempt = &attempt;
+    let mut probes = join_all(
+        candidates
+            .iter()
+            .map(|&snippet| Probe::run(snippet, move || attempt(snippet))),
+    )
+    .await;
+    probes.sort_by_key(|p| (usize::MAX - p.successes, p.latency.unwrap_or(Duration::MAX)));
+    probes
+}
+
+fn parse<T: serde::de::DeserializeOwned>(snippet: &str) -> Result<T> {
+    Ok(Figment::new().merge(Toml::string(snippet)).extract()?)
+}
+
+/// Probe public ip services and resolvers from this host, and print the recommended config.
+pub async fn run(config: &Config, is_v6: bool) -> Result<()> {
+    let family = if is_v6 { "v6" } else { "v4" };
+
+    println!("probing ip providers ({}):", family);
+    let ip_probes = probe_all(IP_PROVIDERS, |snippet| async move {
+        let provider = ip::init_ip_provider(&parse::<IpProviderType>(snippet)?, config)?;
+        Ok(provider.query(is_v6).await?.to_string())
+    })
+    .await;
+    ip_probes.iter().for_each(Probe::print);
+
+    println!("probing query providers ({}):", family);
+    let query_probes = probe_all(QUERY_PROVIDERS, |snippet| async move {
+        let provider = query::init_query_provider(&parse::<QueryProviderType>(snippet)?, config)?;
+        let ips = provider.query(PROBE_NAME, is_v6).await?;
+        if ips.is_empty() {
+            bail!("no record of {} is resolved", PROBE_NAME);
+        }
+        Ok(format!("{:?}", ips))
+    })
+    .await;
+    query_probes.iter().for_each(Probe::print);
+
+    println!();
+    let best = |probes: &[Probe]| {
+        probes
+            .first()
+            .filter(|p| p.successes > 0)
+            .map(|p| p.snippet)
+    };
+    match (best(&query_probes), best(&ip_probes)) {
+        (Some(query_snippet), Some(ip_snippet)) => {
+            println!("# recommended providers of a name config");
+            println!("[{}.query_provider_type]\n{}\n", family, query_snippet);
+            println!("[{}.ip_provider_type]\n{}", family, ip_snippet);
+        }
+        _ => println!("no working provider is found, check the network of this host"),
+    }
+    Ok(())
+}
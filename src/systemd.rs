@@ -0,0 +1,205 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    config::{StateBackend, UpdateProviderType},
+    name_conf_paths, name_providers_conf, read_name_conf, shortest_renew_interval, state, Args,
+    Tenant,
+};
+
+const UNIT_NAME: &str = "dns-renew";
+
+const DEFAULT_RENEW_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Paths written by dns-renew, they are read-only with `ProtectSystem=strict`. Files are written
+/// atomically or rotated, so dirs of them are returned. Names whose providers run a
+/// `reload_command` are returned too, the command is unlikely to work in the sandbox.
+fn writable_paths(tenants: &[Tenant]) -> Result<(Vec<PathBuf>, Vec<String>)> {
+    let mut dirs = vec![];
+    let mut files = vec![];
+    let main_config = &tenants[0].config;
+    files.extend(main_config.log_file().as_ref().map(|l| l.path().clone()));
+    files.extend(main_config.metrics().textfile().clone());
+    let mut reloading_names = vec![];
+    for tenant in tenants {
+        let config = &tenant.config;
+        dirs.push(config.name_state_dir().clone());
+        // Journal files of sqlite, or the temporary file of the single state file, are created
+        // next to it.
+        match config.state_backend() {
+            StateBackend::Files => {}
+            StateBackend::Sqlite => files.push(state::db_path(config)),
+            StateBackend::Single => files.push(state::file_path(config)),
+        }
+        files.extend(config.audit_file().clone());
+        for path in name_conf_paths(config)? {
+            let name_conf = read_name_conf(&path)?;
+            for is_v6 in [false, true] {
+                let Some(providers_conf) = name_providers_conf(&name_conf, is_v6) else {
+                    continue;
+                };
+                for provider in providers_conf.update_provider_type().as_slice() {
+                    let (UpdateProviderType::LocalZone {
+                        file,
+                        reload_command,
+                        ..
+                    }
+                    | UpdateProviderType::ZoneFile {
+                        file,
+                        reload_command,
+                        ..
+                    }) = provider
+                    else {
+                        continue;
+                    };
+                    files.push(file.clone());
+                    if !reload_command.is_empty() && !reloading_names.contains(name_conf.name()) {
+                        reloading_names.push(name_conf.name().clone());
+                    }
+                }
+            }
+        }
+    }
+    dirs.extend(
+        files
+            .iter()
+            .filter_map(|f| f.parent())
+            .filter(|d| !d.as_os_str().is_empty())
+            .map(Path::to_path_buf),
+    );
+    let mut paths = vec![];
+    for dir in dirs {
+        if !paths.contains(&dir) {
+            paths.push(dir);
+        }
+    }
+    Ok((paths, reloading_names))
+}
+
+fn service(args: &Args, tenants: &[Tenant]) -> Result<String> {
+    let exe = std::env::current_exe()?;
+    let config_path = args
+        .config
+        .canonicalize()
+        .with_context(|| format!("{:?} not found", args.config))?;
+    let main_config = &tenants[0].config;
+
+    let mut lines = vec![
+        "[Unit]".to_string(),
+        "Description=Renew dns records of names".to_string(),
+        "Wants=network-online.target".to_string(),
+        "After=network-online.target".to_string(),
+        String::new(),
+        "[Service]".to_string(),
+        "Type=oneshot".to_string(),
+        format!(
+            "ExecStart={} --config {} run",
+            exe.display(),
+            config_path.display()
+        ),
+    ];
+
+    // Secrets are passed by credentials of systemd, and the paths in config are overridden by
+    // envs.
+    for (credential, path, env) in [
+        (
+            "credentials.toml",
+            main_config.update_credentials_file(),
+            "DNS_RENEW_UPDATE_CREDENTIALS_FILE",
+        ),
+        (
+            "state.key",
+            main_config.state_key_file(),
+            "DNS_RENEW_STATE_KEY_FILE",
+        ),
+    ] {
+        if let Some(path) = path {
+            lines.push(format!("LoadCredential={}:{}", credential, path.display()));
+            lines.push(format!("Environment={}=%d/{}", env, credential));
+        }
+    }
+
+    lines.extend(
+        [
+            "NoNewPrivileges=yes",
+            "ProtectSystem=strict",
+            "ProtectHome=yes",
+            "PrivateTmp=yes",
+            "PrivateDevices=yes",
+            "ProtectKernelTunables=yes",
+            "ProtectKernelModules=yes",
+            "ProtectControlGroups=yes",
+            "RestrictAddressFamilies=AF_INET AF_INET6 AF_UNIX AF_NETLINK",
+            "RestrictNamespaces=yes",
+            "RestrictSUIDSGID=yes",
+            "LockPersonality=yes",
+            "MemoryDenyWriteExecute=yes",
+            "CapabilityBoundingSet=",
+        ]
+        .map(ToString::to_string),
+    );
+    let (paths, reloading_names) = writable_paths(tenants)?;
+    for path in paths {
+        lines.push(format!("ReadWritePaths={}", path.display()));
+    }
+    if !reloading_names.is_empty() {
+        eprintln!(
+            "warning: reload_command of {} may fail in the service, which has no capabilities \
+             and can't gain privileges",
+            reloading_names.join(", ")
+        );
+    }
+    lines.push(String::new());
+    Ok(lines.join("\n"))
+}
+
+fn timer(tenants: &[Tenant]) -> Result<String> {
+    // Names which are not due are skipped in a run, so the timer only needs to follow the
+    // shortest interval.
+    let interval = shortest_renew_interval(tenants)?.unwrap_or(DEFAULT_RENEW_INTERVAL);
+    Ok([
+        "[Unit]".to_string(),
+        "Description=Renew dns records of names periodically".to_string(),
+        String::new(),
+        "[Timer]".to_string(),
+        "OnBootSec=1min".to_string(),
+        format!("OnUnitActiveSec={}s", interval.as_secs().max(1)),
+        String::new(),
+        "[Install]".to_string(),
+        "WantedBy=timers.target".to_string(),
+        String::new(),
+    ]
+    .join("\n"))
+}
+
+/// Generate `dns-renew.service` and `dns-renew.timer`, print them if no output dir is given.
+pub fn generate(args: &Args, tenants: &[Tenant], output_dir: Option<&Path>) -> Result<()> {
+    let units = [
+        (format!("{}.service", UNIT_NAME), service(args, tenants)?),
+        (format!("{}.timer", UNIT_NAME), timer(tenants)?),
+    ];
+    match output_dir {
+        Some(dir) => {
+            for (file_name, content) in units {
+                let path = dir.join(file_name);
+                fs::write(&path, content).with_context(|| format!("failed to write {:?}", path))?;
+                println!("{} is written", path.display());
+            }
+            println!(
+                "run `systemctl daemon-reload && systemctl enable --now {}.timer` to enable it",
+                UNIT_NAME
+            );
+        }
+        None => {
+            for (file_name, content) in units {
+                println!("# {}\n{}", file_name, content);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Cell, Row, Table},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    config::Config,
+    status::{self, format_time, NameStatus},
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+pub fn run(config: &Config) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = draw_loop(config, &mut terminal);
+    ratatui::restore();
+    result
+}
+
+fn draw_loop(config: &Config, terminal: &mut DefaultTerminal) -> Result<()> {
+    loop {
+        let statuses = status::load(config);
+        terminal.draw(|frame| draw(frame, &statuses))?;
+
+        if event::poll(REFRESH_INTERVAL)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press
+                    && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, statuses: &Result<Vec<NameStatus>>) {
+    let [table_area, footer_area] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+
+    let header = Row::new(["Name", "Last Renew", "Next Renew", "IPs", "Error"]).bold();
+    let rows = statuses.iter().flatten().map(|s| {
+        Row::new([
+            Cell::from(match &s.tenant {
+                Some(tenant) => format!("{}/{}", tenant, s.name),
+                None => s.name.clone(),
+            }),
+            Cell::from(s.last_renew.map(format_time).unwrap_or_default()),
+            Cell::from(format_time(s.next)),
+            Cell::from(
+                s.ips
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            Cell::from(match &s.last_error {
+                Some(e) if s.failures > 1 => format!("({} failures) {}", s.failures, e),
+                Some(e) => e.clone(),
+                None => String::new(),
+            })
+            .style(Style::default().fg(Color::Red)),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Percentage(20),
+            Constraint::Length(20),
+            Constraint::Length(20),
+            Constraint::Percentage(25),
+            Constraint::Fill(1),
+        ],
+    )
+    .header(header)
+    .block(Block::bordered().title("dns-renew"));
+    frame.render_widget(table, table_area);
+
+    let footer = match statuses {
+        Ok(_) => Line::from("q: quit"),
+        Err(e) => Line::from(format!("failed to load statuses: {:#}", e)).red(),
+    };
+    frame.render_widget(footer, footer_area);
+}
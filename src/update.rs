@@ -1,61 +1,102 @@
-use std::net::IpAddr;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    fs,
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+};
 
-use crate::config::{Config, UpdateCredential, UpdateProviderType};
-use anyhow::{bail, Result};
-use reqwest::Method;
+use crate::{
+    config::{
+        Config, NameConf, RestRequestConf, TemplateEngine, UpdateCredential, UpdateProviderType,
+    },
+    http,
+    plugin::PluginProvider,
+    registry,
+    script::Script,
+    DEFAULT_TIMEOUT,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use data_encoding::BASE64;
+use hickory_proto::rr::{dnssec::tsig::TSigner, Name};
+use minijinja::UndefinedBehavior;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+use reqwest::{Method, RequestBuilder};
+use serde::Serialize;
+use strfmt::Format;
+use tokio::process::Command;
 
 mod httpget {
-    use std::{collections::HashMap, net::IpAddr};
+    use std::net::IpAddr;
 
     use anyhow::Result;
-    use reqwest::blocking::Client;
-    use strfmt::Format;
+    use async_trait::async_trait;
+    use reqwest::{Client, Method};
 
-    use crate::config::UpdateCredential;
+    use crate::{
+        config::UpdateCredential,
+        http,
+        script::{HttpRequest, Script},
+    };
 
-    use super::UpdateProvider;
+    use super::{url_encoded, RenewOutcome, ResponseValidator, TemplateContext, UpdateProvider};
 
     pub(super) struct HttpGetUpdateProvider {
         pub(crate) credential: Option<UpdateCredential>,
         pub(crate) url_template: String,
+        pub(crate) validator: ResponseValidator,
+        pub(crate) script: Option<Script>,
+        pub(crate) context: TemplateContext,
+        pub(crate) client: Client,
     }
 
+    #[async_trait]
     impl UpdateProvider for HttpGetUpdateProvider {
         #[tracing::instrument(skip(self), err)]
-        fn update(&self, name: &str, ip: IpAddr) -> Result<bool> {
-            let mut vars = HashMap::new();
-            let ip = ip.to_string();
-            vars.insert("name".to_string(), name);
-            vars.insert("ip".to_string(), &ip);
-            let url = self.url_template.format(&vars)?;
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            let vars = self.context.vars(name, ip)?;
+            let url = self
+                .context
+                .render(&self.url_template, &url_encoded(&vars))?;
             tracing::debug!("url after rendered: {}", url);
 
-            let mut req_builder = Client::new().get(url);
+            let mut request = HttpRequest::new(Method::GET, url);
+            if let Some(script) = &self.script {
+                request = script.shape_request(request, &vars)?;
+            }
+            let mut req_builder = request.builder(&self.client);
 
-            req_builder = match &self.credential {
-                Some(UpdateCredential::HttpBasicAuth(credential)) => {
-                    req_builder.basic_auth(credential.username(), credential.password().as_ref())
-                }
-                Some(UpdateCredential::HttpBearerToken { token }) => req_builder.bearer_auth(token),
-                None => req_builder,
-            };
+            req_builder = super::with_http_credential(req_builder, self.credential.as_ref())?;
 
-            req_builder.send()?.error_for_status()?;
-            Ok(true)
+            let response = http::send(req_builder).await?.error_for_status()?;
+            let (status, headers) = (response.status(), response.headers().clone());
+            let body = response.text().await?;
+            if let Some(script) = &self.script {
+                script.check_response(status.as_u16(), &headers, &body)?;
+            }
+            self.validator.validate(&body)?;
+            Ok(RenewOutcome::applied())
         }
     }
 }
 
 mod httpplainbody {
-    use std::{collections::HashMap, net::IpAddr};
+    use std::net::IpAddr;
 
     use anyhow::Result;
-    use reqwest::{blocking::Client, header::CONTENT_TYPE, Method};
-    use strfmt::Format;
+    use async_trait::async_trait;
+    use reqwest::{Client, Method};
 
-    use crate::config::UpdateCredential;
+    use crate::{
+        config::UpdateCredential,
+        http,
+        script::{HttpRequest, Script},
+    };
 
-    use super::UpdateProvider;
+    use super::{RenewOutcome, ResponseValidator, TemplateContext, UpdateProvider};
 
     pub(super) struct HttpPlainBodyUpdateProvider {
         pub(crate) credential: Option<UpdateCredential>,
@@ -63,33 +104,354 @@ mod httpplainbody {
         pub(crate) method: Method,
         pub(crate) content_type: String,
         pub(crate) body_template: String,
+        pub(crate) validator: ResponseValidator,
+        pub(crate) script: Option<Script>,
+        pub(crate) context: TemplateContext,
+        pub(crate) client: Client,
     }
 
+    #[async_trait]
     impl UpdateProvider for HttpPlainBodyUpdateProvider {
         #[tracing::instrument(skip(self), err)]
-        fn update(&self, name: &str, ip: IpAddr) -> Result<bool> {
-            let mut vars = HashMap::new();
-            let ip = ip.to_string();
-            vars.insert("name".to_string(), name);
-            vars.insert("ip".to_string(), &ip);
-            let body = self.body_template.format(&vars)?;
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            let vars = self.context.vars(name, ip)?;
+            let body = self.context.render(&self.body_template, &vars)?;
             tracing::debug!("body after rendered: {}", body);
 
-            let mut req_builder = Client::new()
-                .request(self.method.clone(), &self.url)
-                .header(CONTENT_TYPE, &self.content_type)
-                .body(body);
+            let mut request = HttpRequest::new(self.method.clone(), self.url.clone())
+                .with_body(&self.content_type, body);
+            if let Some(script) = &self.script {
+                request = script.shape_request(request, &vars)?;
+            }
+            let mut req_builder = request.builder(&self.client);
+
+            req_builder = super::with_http_credential(req_builder, self.credential.as_ref())?;
+
+            let response = http::send(req_builder).await?.error_for_status()?;
+            let (status, headers) = (response.status(), response.headers().clone());
+            let body = response.text().await?;
+            if let Some(script) = &self.script {
+                script.check_response(status.as_u16(), &headers, &body)?;
+            }
+            self.validator.validate(&body)?;
+            Ok(RenewOutcome::applied())
+        }
+    }
+}
+
+mod httpjsonbody {
+    use std::net::IpAddr;
+
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
+    use reqwest::{Client, Method};
+    use serde_json::Value;
+
+    use crate::{
+        config::UpdateCredential,
+        http,
+        script::{HttpRequest, Script},
+    };
+
+    use super::{RenewOutcome, TemplateContext, UpdateProvider};
+
+    pub(super) struct HttpJsonBodyUpdateProvider {
+        pub(crate) credential: Option<UpdateCredential>,
+        pub(crate) url: String,
+        pub(crate) method: Method,
+        pub(crate) body: Value,
+        pub(crate) success_pointer: Option<String>,
+        pub(crate) success_value: Option<Value>,
+        pub(crate) script: Option<Script>,
+        pub(crate) context: TemplateContext,
+        pub(crate) client: Client,
+    }
+
+    impl HttpJsonBodyUpdateProvider {
+        /// A response with status 200 may still be a failure, it is checked by the value at
+        /// `success_pointer`.
+        fn validate(&self, response_body: &[u8]) -> Result<()> {
+            let Some(pointer) = &self.success_pointer else {
+                return Ok(());
+            };
+            let response: Value = serde_json::from_slice(response_body)?;
+            match (response.pointer(pointer), &self.success_value) {
+                (Some(actual), Some(expected)) if actual == expected => Ok(()),
+                (Some(_), None) => Ok(()),
+                (actual, _) => bail!("unexpected value at {} in response: {:?}", pointer, actual),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl UpdateProvider for HttpJsonBodyUpdateProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            let vars = self.context.vars(name, ip)?;
+            let body = self.context.render_json(&self.body, &vars)?;
+            tracing::debug!("body after rendered: {}", body);
+
+            let mut request = HttpRequest::new(self.method.clone(), self.url.clone())
+                .with_body("application/json", serde_json::to_string(&body)?);
+            if let Some(script) = &self.script {
+                request = script.shape_request(request, &vars)?;
+            }
+            let mut req_builder = request.builder(&self.client);
+
+            req_builder = super::with_http_credential(req_builder, self.credential.as_ref())?;
+
+            let response = http::send(req_builder).await?.error_for_status()?;
+            let (status, headers) = (response.status(), response.headers().clone());
+            let response_body = response.bytes().await?;
+            tracing::debug!("update through http json body, result: {:?}", response_body);
+            if let Some(script) = &self.script {
+                script.check_response(
+                    status.as_u16(),
+                    &headers,
+                    &String::from_utf8_lossy(&response_body),
+                )?;
+            }
+            self.validate(&response_body)?;
+            Ok(RenewOutcome::applied())
+        }
+    }
+}
+
+mod genericrest {
+    use std::{collections::HashMap, net::IpAddr};
+
+    use anyhow::{bail, Context, Result};
+    use async_trait::async_trait;
+    use reqwest::{Client, Method};
+    use serde_json::Value;
+
+    use crate::{
+        config::UpdateCredential,
+        http,
+        script::{HttpRequest, Script},
+    };
+
+    use super::{
+        url_encoded, RecordDiff, RecordSnapshot, RenewAction, RenewOutcome, TemplateContext,
+        UpdateProvider,
+    };
+
+    pub(super) struct RestRequest {
+        pub(crate) url: String,
+        pub(crate) method: Method,
+        pub(crate) body: Option<Value>,
+    }
+
+    pub(super) struct GenericRestUpdateProvider {
+        pub(crate) credential: Option<UpdateCredential>,
+        pub(crate) query: RestRequest,
+        pub(crate) create: RestRequest,
+        pub(crate) update: RestRequest,
+        pub(crate) records_pointer: String,
+        pub(crate) id_pointer: String,
+        pub(crate) content_pointer: String,
+        pub(crate) script: Option<Script>,
+        pub(crate) context: TemplateContext,
+        pub(crate) client: Client,
+    }
+
+    impl GenericRestUpdateProvider {
+        async fn send(
+            &self,
+            request: &RestRequest,
+            vars: &HashMap<String, String>,
+        ) -> Result<Value> {
+            let url = self.context.render(&request.url, &url_encoded(vars))?;
+            let mut http_request = HttpRequest::new(request.method.clone(), url);
+            if let Some(body) = &request.body {
+                let body = self.context.render_json(body, vars)?;
+                tracing::debug!("body after rendered: {}", body);
+                http_request =
+                    http_request.with_body("application/json", serde_json::to_string(&body)?);
+            }
+            if let Some(script) = &self.script {
+                http_request = script.shape_request(http_request, vars)?;
+            }
+            let url = http_request.url.clone();
+            let mut req_builder = http_request.builder(&self.client);
+            req_builder = super::with_http_credential(req_builder, self.credential.as_ref())?;
+
+            let response = http::send(req_builder).await?;
+            let err = response.error_for_status_ref().err();
+            let (status, headers) = (response.status(), response.headers().clone());
+            let response_body = response.bytes().await?;
+            tracing::debug!(
+                "call {} {}, result: {:?}",
+                request.method,
+                url,
+                response_body
+            );
+            if let Some(err) = err {
+                bail!("call {} with error: {}, {:?}", url, err, response_body);
+            }
+            if let Some(script) = &self.script {
+                script.check_response(
+                    status.as_u16(),
+                    &headers,
+                    &String::from_utf8_lossy(&response_body),
+                )?;
+            }
+            if response_body.is_empty() {
+                return Ok(Value::Null);
+            }
+            Ok(serde_json::from_slice(&response_body)?)
+        }
+
+        /// Find the id and the content of the record with the same family as `ip`.
+        fn find_record(&self, response: &Value, ip: &IpAddr) -> Result<Option<(String, String)>> {
+            let records = match response.pointer(&self.records_pointer) {
+                Some(Value::Array(records)) => records.iter().collect(),
+                Some(Value::Null) | None => vec![],
+                Some(record) => vec![record],
+            };
+            for record in records {
+                let Some(content) = record
+                    .pointer(&self.content_pointer)
+                    .and_then(Value::as_str)
+                else {
+                    continue;
+                };
+                match content.parse::<IpAddr>() {
+                    Ok(current) if current.is_ipv6() == ip.is_ipv6() => {}
+                    _ => continue,
+                }
+                let id = match record.pointer(&self.id_pointer) {
+                    Some(Value::String(id)) => id.clone(),
+                    Some(Value::Number(id)) => id.to_string(),
+                    id => bail!("unexpected id at {}: {:?}", self.id_pointer, id),
+                };
+                return Ok(Some((id, content.to_string())));
+            }
+            Ok(None)
+        }
+    }
+
+    #[async_trait]
+    impl UpdateProvider for GenericRestUpdateProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            let mut vars = self.context.vars(name, ip)?;
+            let ip_str = ip.to_string();
 
-            req_builder = match &self.credential {
-                Some(UpdateCredential::HttpBasicAuth(credential)) => {
-                    req_builder.basic_auth(credential.username(), credential.password().as_ref())
+            let response = self.send(&self.query, &vars).await.context("query step")?;
+            let snapshot = |content: &str| RecordSnapshot {
+                content: Some(content.to_string()),
+                ..Default::default()
+            };
+            match self.find_record(&response, &ip)? {
+                Some((_, content)) if content == ip_str => Ok(RenewOutcome::unchanged()),
+                Some((id, content)) => {
+                    vars.insert("id".to_string(), id);
+                    self.send(&self.update, &vars)
+                        .await
+                        .context("update step")?;
+                    Ok(RenewOutcome::new(
+                        RenewAction::Update,
+                        Some(RecordDiff {
+                            before: Some(snapshot(&content)),
+                            after: snapshot(&ip_str),
+                        }),
+                    ))
                 }
-                Some(UpdateCredential::HttpBearerToken { token }) => req_builder.bearer_auth(token),
-                None => req_builder,
+                None => {
+                    self.send(&self.create, &vars)
+                        .await
+                        .context("create step")?;
+                    Ok(RenewOutcome::new(
+                        RenewAction::Create,
+                        Some(RecordDiff {
+                            before: None,
+                            after: snapshot(&ip_str),
+                        }),
+                    ))
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use serde_json::json;
+
+        use super::*;
+        use crate::config::TemplateEngine;
+
+        fn provider(records_pointer: &str) -> GenericRestUpdateProvider {
+            let request = || RestRequest {
+                url: String::new(),
+                method: Method::GET,
+                body: None,
             };
+            GenericRestUpdateProvider {
+                credential: None,
+                query: request(),
+                create: request(),
+                update: request(),
+                records_pointer: records_pointer.to_string(),
+                id_pointer: "/id".to_string(),
+                content_pointer: "/content".to_string(),
+                script: None,
+                context: TemplateContext {
+                    engine: TemplateEngine::Strfmt,
+                    ttl: 300,
+                    zone: None,
+                },
+                client: Client::new(),
+            }
+        }
+
+        fn found(id: &str, content: &str) -> Option<(String, String)> {
+            Some((id.to_string(), content.to_string()))
+        }
+
+        #[test]
+        fn find_record_of_family() {
+            let response = json!({
+                "records": [
+                    { "id": "a", "content": "not an ip" },
+                    { "id": "b", "content": "2001:db8::1" },
+                    { "id": 3, "content": "192.0.2.1" },
+                ]
+            });
+            let provider = provider("/records");
+            let v4 = "198.51.100.1".parse().unwrap();
+            let v6 = "2001:db8::2".parse().unwrap();
+            assert_eq!(
+                provider.find_record(&response, &v4).unwrap(),
+                found("3", "192.0.2.1")
+            );
+            assert_eq!(
+                provider.find_record(&response, &v6).unwrap(),
+                found("b", "2001:db8::1")
+            );
+        }
 
-            req_builder.send()?.error_for_status()?;
-            Ok(true)
+        #[test]
+        fn find_record_in_other_shapes() {
+            let v4 = "198.51.100.1".parse().unwrap();
+            // A single record, instead of an array.
+            let response = json!({ "record": { "id": "a", "content": "192.0.2.1" } });
+            assert_eq!(
+                provider("/record").find_record(&response, &v4).unwrap(),
+                found("a", "192.0.2.1")
+            );
+            for response in [
+                json!({}),
+                json!({ "records": null }),
+                json!({ "records": [] }),
+            ] {
+                assert_eq!(
+                    provider("/records").find_record(&response, &v4).unwrap(),
+                    None
+                );
+            }
+            let response = json!({ "records": [{ "content": "192.0.2.1" }] });
+            assert!(provider("/records").find_record(&response, &v4).is_err());
         }
     }
 }
@@ -97,15 +459,19 @@ mod httpplainbody {
 mod cloudflare {
     use std::{collections::HashMap, net::IpAddr};
 
-    use anyhow::{bail, Result};
-    use reqwest::{
-        blocking::{Client, RequestBuilder},
-        header::CONTENT_TYPE,
-    };
+    use anyhow::{bail, Context, Result};
+    use async_trait::async_trait;
+    use reqwest::{header::CONTENT_TYPE, Client, RequestBuilder};
     use serde::{de::DeserializeOwned, Deserialize, Serialize};
+    use serde_json::{json, Value};
     use strfmt::Format;
 
-    use super::UpdateProvider;
+    use crate::http;
+
+    use super::{
+        record_type, CompanionRecord, RecordDiff, RecordSnapshot, RenewAction, RenewOutcome,
+        UpdateProvider,
+    };
 
     #[derive(Deserialize, Serialize)]
     struct DnsRecord {
@@ -119,6 +485,17 @@ mod cloudflare {
         id: Option<String>,
     }
 
+    impl From<&DnsRecord> for RecordSnapshot {
+        fn from(record: &DnsRecord) -> Self {
+            Self {
+                content: Some(record.content.clone()),
+                ttl: Some(record.ttl),
+                proxied: Some(record.proxied),
+                comment: record.comment.clone(),
+            }
+        }
+    }
+
     #[allow(dead_code)]
     #[derive(Deserialize)]
     struct DnsResponse<T, P> {
@@ -152,12 +529,44 @@ mod cloudflare {
         total_count: usize,
     }
 
+    pub(crate) enum CloudflareAuth {
+        Token(String),
+        /// The legacy global api key.
+        GlobalKey {
+            email: String,
+            key: String,
+        },
+    }
+
+    trait ApplyAuth {
+        fn apply(self, auth: &CloudflareAuth) -> Self;
+    }
+
+    impl ApplyAuth for RequestBuilder {
+        fn apply(self, auth: &CloudflareAuth) -> Self {
+            match auth {
+                CloudflareAuth::Token(token) => self.bearer_auth(token),
+                CloudflareAuth::GlobalKey { email, key } => {
+                    self.header("X-Auth-Email", email).header("X-Auth-Key", key)
+                }
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct Zone {
+        id: String,
+    }
+
     pub(super) struct CloudflareUpdateProvider {
-        pub(crate) token: String,
+        pub(crate) auth: CloudflareAuth,
         pub(crate) zone_id: String,
         pub(crate) proxied: bool,
         pub(crate) ttl: Option<u32>,
         pub(crate) comment: Option<String>,
+        /// Only the record with `comment` is managed, if there are multiple records of a name.
+        pub(crate) managed: bool,
+        pub(crate) client: Client,
     }
 
     impl CloudflareUpdateProvider {
@@ -165,24 +574,35 @@ mod cloudflare {
             "https://api.cloudflare.com/client/v4/zones/{zone_id}/dns_records";
         const OTHER_URL_TEMPLATE: &str =
             "https://api.cloudflare.com/client/v4/zones/{zone_id}/dns_records/{dns_record_id}";
+        const ZONES_URL: &str = "https://api.cloudflare.com/client/v4/zones";
 
-        fn record_type(is_v6: bool) -> &'static str {
-            if is_v6 {
-                "AAAA"
-            } else {
-                "A"
+        /// Find the id of a zone by its name, e.g. `example.com`.
+        #[tracing::instrument(skip(auth), err)]
+        pub(crate) async fn resolve_zone_id(
+            client: &Client,
+            auth: &CloudflareAuth,
+            zone: &str,
+        ) -> Result<String> {
+            let req_builder = client
+                .get(Self::ZONES_URL)
+                .apply(auth)
+                .query(&[("name", zone)]);
+            let mut response: DnsResponse<Vec<Zone>, PageInfo> = Self::call(req_builder).await?;
+            match response.result.pop() {
+                Some(zone) => Ok(zone.id),
+                None => bail!("zone {} is not found in cloudflare", zone),
             }
         }
 
-        fn call<T, P>(&self, req_builder: RequestBuilder) -> Result<DnsResponse<T, P>>
+        async fn call<T, P>(req_builder: RequestBuilder) -> Result<DnsResponse<T, P>>
         where
             T: DeserializeOwned,
             P: DeserializeOwned,
         {
-            let response = req_builder.send()?;
+            let response = http::send(req_builder).await?;
 
             let err = response.error_for_status_ref().err();
-            let response_body = response.bytes()?;
+            let response_body = response.bytes().await?;
             tracing::debug!("call cf, result: {:? }", response_body);
             if let Some(err) = err {
                 return Err(From::from(err));
@@ -196,24 +616,44 @@ mod cloudflare {
         }
 
         #[tracing::instrument(skip(self), err)]
-        fn query(&self, name: &str, is_v6: bool) -> Result<Option<DnsRecord>> {
+        async fn query(&self, name: &str, record_type: &str) -> Result<Option<DnsRecord>> {
             let mut vars = HashMap::new();
             vars.insert("zone_id".to_string(), self.zone_id.as_str());
             let url = Self::GET_OR_POST_URL_TEMPLATE.format(&vars)?;
             tracing::debug!("url after rendered: {}", url);
 
-            let req_builder = Client::new()
+            let req_builder = self
+                .client
                 .get(url)
-                .bearer_auth(&self.token)
-                .query(&[("name", name), ("type", Self::record_type(is_v6))]);
+                .apply(&self.auth)
+                .query(&[("name", name), ("type", record_type)]);
 
-            let mut response: DnsResponse<Vec<DnsRecord>, PageInfo> = self.call(req_builder)?;
-            // It should be contain zero or one record.
+            let mut response: DnsResponse<Vec<DnsRecord>, PageInfo> =
+                Self::call(req_builder).await?;
+            if self.managed {
+                // Other records of a round-robin set are left untouched.
+                return Ok(response
+                    .result
+                    .into_iter()
+                    .find(|r| r.comment == self.comment));
+            }
+            if response.result.len() > 1 {
+                bail!(
+                    "{} records of {} are found, set managed_comment to mark the managed one",
+                    response.result.len(),
+                    name
+                );
+            }
             Ok(response.result.pop())
         }
 
         #[tracing::instrument(skip(self), err)]
-        fn create(&self, name: &str, ip: IpAddr) -> Result<()> {
+        async fn create(
+            &self,
+            name: &str,
+            record_type: &str,
+            content: String,
+        ) -> Result<RecordDiff> {
             let mut vars = HashMap::new();
             vars.insert("zone_id".to_string(), self.zone_id.as_str());
             let url = Self::GET_OR_POST_URL_TEMPLATE.format(&vars)?;
@@ -224,23 +664,28 @@ mod cloudflare {
                 name: name.to_string(),
                 proxied: self.proxied,
                 ttl: self.ttl.unwrap_or(300),
-                content: ip.to_string(),
-                record_type: Self::record_type(ip.is_ipv6()).to_string(),
+                content,
+                record_type: record_type.to_string(),
                 id: None,
             };
 
-            let req_builder = Client::new()
+            let req_builder = self
+                .client
                 .post(url)
-                .bearer_auth(&self.token)
+                .apply(&self.auth)
                 .header(CONTENT_TYPE, "application/json")
                 .body(serde_json::to_string(&request)?);
 
-            let _response: DnsResponse<DnsRecord, Option<()>> = self.call(req_builder)?;
-            Ok(())
+            let _response: DnsResponse<DnsRecord, Option<()>> = Self::call(req_builder).await?;
+            Ok(RecordDiff {
+                before: None,
+                after: RecordSnapshot::from(&request),
+            })
         }
 
         #[tracing::instrument(skip(self, old), err)]
-        fn update(&self, mut old: DnsRecord, ip: IpAddr) -> Result<()> {
+        async fn update(&self, mut old: DnsRecord, content: String) -> Result<RecordDiff> {
+            let before = RecordSnapshot::from(&old);
             let id = if let Some(id) = old.id.take() {
                 id
             } else {
@@ -253,7 +698,7 @@ mod cloudflare {
             tracing::debug!("url after rendered: {}", url);
 
             old.proxied = self.proxied;
-            old.content = ip.to_string();
+            old.content = content;
             if !old.proxied {
                 if let Some(ttl) = &self.ttl {
                     old.ttl = *ttl;
@@ -261,24 +706,51 @@ mod cloudflare {
             }
             old.comment = self.comment.clone();
 
-            let req_builder = Client::new()
+            let req_builder = self
+                .client
                 .put(url)
-                .bearer_auth(&self.token)
+                .apply(&self.auth)
                 .header(CONTENT_TYPE, "application/json")
                 .body(serde_json::to_string(&old)?);
 
-            let _response: DnsResponse<DnsRecord, Option<()>> = self.call(req_builder)?;
+            let _response: DnsResponse<DnsRecord, Option<()>> = Self::call(req_builder).await?;
+
+            Ok(RecordDiff {
+                before: Some(before),
+                after: RecordSnapshot::from(&old),
+            })
+        }
+
+        /// Delete the record of a name in the type, if it exists.
+        async fn delete_record(&self, name: &str, record_type: &str) -> Result<()> {
+            let Some(record) = self.query(name, record_type).await? else {
+                return Ok(());
+            };
+            let Some(id) = record.id else {
+                bail!("no id in dns record");
+            };
+            let mut vars = HashMap::new();
+            vars.insert("zone_id".to_string(), self.zone_id.as_str());
+            vars.insert("dns_record_id".to_string(), id.as_str());
+            let url = Self::OTHER_URL_TEMPLATE.format(&vars)?;
 
+            let req_builder = self.client.delete(url).apply(&self.auth);
+            let _response: DnsResponse<Value, Option<()>> = Self::call(req_builder).await?;
             Ok(())
         }
-    }
 
-    impl UpdateProvider for CloudflareUpdateProvider {
-        #[tracing::instrument(skip(self), err)]
-        fn update(&self, name: &str, ip: IpAddr) -> Result<bool> {
-            match self.query(name, ip.is_ipv6())? {
+        /// Create or update a record of a name. Before creating, records in `conflicts` are deleted,
+        /// since a CNAME can't coexist with other records.
+        async fn renew(
+            &self,
+            name: &str,
+            record_type: &str,
+            content: String,
+            conflicts: &[&str],
+        ) -> Result<RenewOutcome> {
+            match self.query(name, record_type).await? {
                 Some(old) => {
-                    if old.content != ip.to_string()
+                    if old.content != content
                         || old.proxied != self.proxied
                         || self
                             .ttl
@@ -287,96 +759,2385 @@ mod cloudflare {
                             .unwrap_or(false)
                         || self.comment != old.comment
                     {
-                        self.update(old, ip)?
+                        let diff = self.update(old, content).await?;
+                        Ok(RenewOutcome::new(RenewAction::Update, Some(diff)))
                     } else {
-                        return Ok(false);
+                        Ok(RenewOutcome::unchanged())
+                    }
+                }
+                None => {
+                    tracing::debug!("no {} record of {} in zone, create it", record_type, name);
+                    for conflict in conflicts {
+                        self.delete_record(name, conflict).await?;
                     }
+                    let diff = self.create(name, record_type, content).await?;
+                    Ok(RenewOutcome::new(RenewAction::Create, Some(diff)))
                 }
-                None => self.create(name, ip)?,
             }
-            Ok(true)
         }
     }
-}
 
-fn find_optional_update_credential(
-    config: &Config,
-    credential: &Option<String>,
-) -> Result<Option<UpdateCredential>> {
-    if let Some(credential) = credential {
-        Ok(Some(find_update_credential(config, credential)?))
-    } else {
-        Ok(None)
+    #[async_trait]
+    impl UpdateProvider for CloudflareUpdateProvider {
+        #[tracing::instrument(skip(self, record), fields(name = record.name, record_type = record.record_type), err)]
+        async fn create_record(&self, record: &CompanionRecord) -> Result<()> {
+            let mut vars = HashMap::new();
+            vars.insert("zone_id".to_string(), self.zone_id.as_str());
+            let url = Self::GET_OR_POST_URL_TEMPLATE.format(&vars)?;
+
+            let mut request = json!({
+                "name": record.name,
+                "type": record.record_type,
+                "content": record.content,
+                "ttl": record.ttl.unwrap_or(300),
+            });
+            // Records like CAA or HTTPS are created by structured data in cloudflare.
+            if let Some(data) = &record.data {
+                request["data"] = data.clone();
+            }
+
+            let req_builder = self
+                .client
+                .post(url)
+                .apply(&self.auth)
+                .header(CONTENT_TYPE, "application/json")
+                .body(serde_json::to_string(&request)?);
+
+            let _response: DnsResponse<Value, Option<()>> = Self::call(req_builder).await?;
+            Ok(())
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            let record_type = record_type(ip.is_ipv6());
+            self.renew(name, record_type, ip.to_string(), &["CNAME"])
+                .await
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn query_ips(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
+            self.query(name, record_type(is_v6))
+                .await?
+                .map(|r| {
+                    r.content
+                        .parse()
+                        .with_context(|| format!("invalid ip in cloudflare: {}", r.content))
+                })
+                .into_iter()
+                .collect()
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn update_cname(&self, name: &str, target: &str) -> Result<RenewOutcome> {
+            self.renew(name, "CNAME", target.to_string(), &["A", "AAAA"])
+                .await
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn delete(&self, name: &str, is_v6: bool) -> Result<()> {
+            self.delete_record(name, record_type(is_v6)).await
+        }
     }
 }
 
-fn find_update_credential(config: &Config, credential: &String) -> Result<UpdateCredential> {
-    if let Some(credential) = config.update_credentials().get(credential) {
-        Ok(credential.clone())
-    } else {
-        bail!("Credential not found: {}", credential)
+mod inwx {
+    use std::{
+        net::IpAddr,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use anyhow::{anyhow, bail, Context, Result};
+    use async_trait::async_trait;
+    use data_encoding::BASE32_NOPAD;
+    use hmac::{Hmac, Mac};
+    use reqwest::{
+        header::{CONTENT_TYPE, COOKIE, SET_COOKIE},
+        Client,
+    };
+    use serde::{de::DeserializeOwned, Deserialize};
+    use serde_json::{json, Value};
+    use sha1::Sha1;
+
+    use crate::http;
+
+    use super::{
+        record_type, CompanionRecord, RecordDiff, RecordSnapshot, RenewAction, RenewOutcome,
+        UpdateProvider,
+    };
+
+    #[derive(Deserialize)]
+    struct RpcResponse {
+        code: u32,
+        msg: String,
+        #[serde(rename = "resData")]
+        res_data: Option<Value>,
     }
-}
 
-pub fn init_update_provider(
-    update_provider_type: &UpdateProviderType,
-    config: &Config,
-) -> Result<Box<dyn UpdateProvider>> {
-    match update_provider_type {
-        UpdateProviderType::HttpGet {
-            credential,
-            url_template,
-        } => Ok(Box::new(httpget::HttpGetUpdateProvider {
-            credential: find_optional_update_credential(config, credential)?,
-            url_template: url_template.clone(),
-        })),
-        UpdateProviderType::HttpPlainBody {
-            credential,
-            url,
-            method,
-            content_type,
-            body_template,
-        } => {
-            let method = match method.to_uppercase().as_str() {
-                "POST" => Method::POST,
-                "PUT" => Method::PUT,
-                "PATCH" => Method::PATCH,
-                _ => {
-                    bail!("Unsupport method in HttpPlainBody: {}", method);
-                }
-            };
-            Ok(Box::new(httpplainbody::HttpPlainBodyUpdateProvider {
-                credential: find_optional_update_credential(config, credential)?,
-                url: url.clone(),
-                method,
-                content_type: content_type.clone(),
-                body_template: body_template.clone(),
-            }))
+    #[derive(Deserialize)]
+    struct LoginResult {
+        tfa: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct InfoResult {
+        record: Option<Vec<Record>>,
+    }
+
+    #[derive(Deserialize)]
+    struct Record {
+        id: u64,
+        content: String,
+        ttl: u32,
+    }
+
+    pub(super) struct InwxUpdateProvider {
+        pub(crate) api_url: String,
+        pub(crate) username: String,
+        pub(crate) password: String,
+        pub(crate) totp_secret: Option<String>,
+        pub(crate) domain: String,
+        pub(crate) ttl: Option<u32>,
+        pub(crate) client: Client,
+    }
+
+    /// A logged in session, the session id is kept in a cookie.
+    struct Session<'a> {
+        provider: &'a InwxUpdateProvider,
+        client: Client,
+        cookie: Option<String>,
+    }
+
+    impl Session<'_> {
+        #[tracing::instrument(skip(self, params), err)]
+        async fn call<T: DeserializeOwned>(&mut self, method: &str, params: Value) -> Result<T> {
+            let mut req_builder = self
+                .client
+                .post(&self.provider.api_url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(serde_json::to_string(
+                    &json!({ "method": method, "params": params }),
+                )?);
+            if let Some(cookie) = &self.cookie {
+                req_builder = req_builder.header(COOKIE, cookie);
+            }
+            let response = http::send(req_builder).await?.error_for_status()?;
+            if let Some(cookie) = response
+                .headers()
+                .get(SET_COOKIE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(';').next())
+            {
+                self.cookie = Some(cookie.to_string());
+            }
+            let response_body = response.bytes().await?;
+            tracing::debug!("call inwx, result: {:?}", response_body);
+
+            let response: RpcResponse = serde_json::from_slice(&response_body)?;
+            // 1xxx means success.
+            if !(1000..2000).contains(&response.code) {
+                bail!(
+                    "call inwx {} with error: {} {}",
+                    method,
+                    response.code,
+                    response.msg
+                );
+            }
+            Ok(serde_json::from_value(
+                response.res_data.unwrap_or(Value::Null),
+            )?)
         }
-        UpdateProviderType::Cloudflare {
-            credential,
-            zone_id,
-            proxied,
-            ttl,
-            comment,
-        } => {
-            let token = match find_update_credential(config, credential)? {
-                UpdateCredential::HttpBasicAuth(_) => {
-                    bail!("Only HttpBearerToken credential is supported when cloudflare is used.");
-                }
-                UpdateCredential::HttpBearerToken { token } => token.clone(),
-            };
-            Ok(Box::new(cloudflare::CloudflareUpdateProvider {
-                token,
-                zone_id: zone_id.clone(),
-                proxied: proxied.unwrap_or(false),
-                ttl: *ttl,
-                comment: comment.clone(),
-            }))
+
+        async fn logout(&mut self) {
+            if let Err(e) = self.call::<Value>("account.logout", json!({})).await {
+                tracing::warn!("failed to logout from inwx: {:?}", e);
+            }
         }
     }
-}
 
-pub trait UpdateProvider {
-    fn update(&self, name: &str, ip: IpAddr) -> Result<bool>;
+    impl InwxUpdateProvider {
+        pub(crate) const DEFAULT_API_URL: &str = "https://api.domrobot.com/jsonrpc/";
+
+        /// Generate the current TOTP code of RFC 6238 with the default parameters of
+        /// authenticator apps: SHA1, 30 seconds and 6 digits.
+        fn totp(secret: &str) -> Result<String> {
+            Self::totp_at(
+                secret,
+                SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            )
+        }
+
+        fn totp_at(secret: &str, timestamp: u64) -> Result<String> {
+            let key = BASE32_NOPAD
+                .decode(secret.trim_end_matches('=').to_uppercase().as_bytes())
+                .with_context(|| "invalid totp secret of inwx")?;
+            let counter = timestamp / 30;
+            let mut mac = Hmac::<Sha1>::new_from_slice(&key)?;
+            mac.update(&counter.to_be_bytes());
+            let hash = mac.finalize().into_bytes();
+            let offset = (hash[hash.len() - 1] & 0xf) as usize;
+            let code = u32::from_be_bytes([
+                hash[offset] & 0x7f,
+                hash[offset + 1],
+                hash[offset + 2],
+                hash[offset + 3],
+            ]) % 1_000_000;
+            Ok(format!("{:06}", code))
+        }
+
+        async fn login(&self) -> Result<Session<'_>> {
+            let mut session = Session {
+                provider: self,
+                client: self.client.clone(),
+                cookie: None,
+            };
+            let login: LoginResult = session
+                .call(
+                    "account.login",
+                    json!({ "user": self.username, "pass": self.password }),
+                )
+                .await?;
+            match login.tfa.as_deref() {
+                None | Some("0") => {}
+                Some(tfa) => {
+                    let secret = self.totp_secret.as_ref().ok_or_else(|| {
+                        anyhow!("2fa[{}] is enabled in inwx, but no totp_secret is set", tfa)
+                    })?;
+                    session
+                        .call::<Value>("account.unlock", json!({ "tan": Self::totp(secret)? }))
+                        .await?;
+                }
+            }
+            Ok(session)
+        }
+
+        async fn do_update(
+            &self,
+            session: &mut Session<'_>,
+            name: &str,
+            ip: IpAddr,
+        ) -> Result<RenewOutcome> {
+            let record_type = record_type(ip.is_ipv6());
+            let info: InfoResult = session
+                .call(
+                    "nameserver.info",
+                    json!({ "domain": self.domain, "name": name, "type": record_type }),
+                )
+                .await?;
+            // It should be contain zero or one record.
+            match info.record.and_then(|mut r| r.pop()) {
+                Some(old) => {
+                    if old.content == ip.to_string()
+                        && self.ttl.map(|t| t == old.ttl).unwrap_or(true)
+                    {
+                        return Ok(RenewOutcome::unchanged());
+                    }
+                    let ttl = self.ttl.unwrap_or(old.ttl);
+                    session
+                        .call::<Value>(
+                            "nameserver.updateRecord",
+                            json!({
+                                "id": old.id,
+                                "content": ip.to_string(),
+                                "ttl": ttl,
+                            }),
+                        )
+                        .await?;
+                    Ok(RenewOutcome::new(
+                        RenewAction::Update,
+                        Some(RecordDiff {
+                            before: Some(RecordSnapshot {
+                                content: Some(old.content),
+                                ttl: Some(old.ttl),
+                                ..Default::default()
+                            }),
+                            after: RecordSnapshot {
+                                content: Some(ip.to_string()),
+                                ttl: Some(ttl),
+                                ..Default::default()
+                            },
+                        }),
+                    ))
+                }
+                None => {
+                    tracing::debug!("no {} record of {} in zone, create it", record_type, name);
+                    let ttl = self.ttl.unwrap_or(300);
+                    session
+                        .call::<Value>(
+                            "nameserver.createRecord",
+                            json!({
+                                "domain": self.domain,
+                                "type": record_type,
+                                "name": name,
+                                "content": ip.to_string(),
+                                "ttl": ttl,
+                            }),
+                        )
+                        .await?;
+                    Ok(RenewOutcome::new(
+                        RenewAction::Create,
+                        Some(RecordDiff {
+                            before: None,
+                            after: RecordSnapshot {
+                                content: Some(ip.to_string()),
+                                ttl: Some(ttl),
+                                ..Default::default()
+                            },
+                        }),
+                    ))
+                }
+            }
+        }
+    }
+
+    #[async_trait]
+    impl UpdateProvider for InwxUpdateProvider {
+        #[tracing::instrument(skip(self, record), fields(name = record.name, record_type = record.record_type), err)]
+        async fn create_record(&self, record: &CompanionRecord) -> Result<()> {
+            let mut session = self.login().await?;
+            let result = session
+                .call::<Value>(
+                    "nameserver.createRecord",
+                    json!({
+                        "domain": self.domain,
+                        "type": record.record_type,
+                        "name": record.name,
+                        "content": record.content,
+                        "ttl": record.ttl.or(self.ttl).unwrap_or(300),
+                    }),
+                )
+                .await;
+            session.logout().await;
+            result.map(|_| ())
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            let mut session = self.login().await?;
+            let result = self.do_update(&mut session, name, ip).await;
+            session.logout().await;
+            result
+        }
+    }
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// The secret of test vectors of RFC 6238, `12345678901234567890` in base32.
+        const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+        #[test]
+        fn totp_of_rfc6238() {
+            for (timestamp, code) in [
+                (59, "287082"),
+                (1111111109, "081804"),
+                (1111111111, "050471"),
+                (1234567890, "005924"),
+                (2000000000, "279037"),
+            ] {
+                assert_eq!(
+                    InwxUpdateProvider::totp_at(SECRET, timestamp).unwrap(),
+                    code
+                );
+            }
+            // Secrets are often shown in lowercase, or with paddings.
+            let secret = format!("{}====", SECRET.to_lowercase());
+            assert_eq!(InwxUpdateProvider::totp_at(&secret, 59).unwrap(), "287082");
+            assert!(InwxUpdateProvider::totp_at("not base32!", 59).is_err());
+        }
+    }
+}
+
+mod hurricaneelectric {
+    use std::net::IpAddr;
+
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
+    use reqwest::Client;
+
+    use crate::http;
+
+    use super::{RenewOutcome, UpdateProvider};
+
+    pub(super) struct HurricaneElectricUpdateProvider {
+        pub(crate) url: String,
+        pub(crate) key: String,
+        pub(crate) client: Client,
+    }
+
+    impl HurricaneElectricUpdateProvider {
+        pub(crate) const DEFAULT_URL: &str = "https://dyn.dns.he.net/nic/update";
+    }
+
+    #[async_trait]
+    impl UpdateProvider for HurricaneElectricUpdateProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            // The hostname is the username, and the key of the record is the password.
+            let req_builder = self
+                .client
+                .get(&self.url)
+                .basic_auth(name, Some(&self.key))
+                .query(&[("hostname", name), ("myip", &ip.to_string())]);
+
+            let text = http::send(req_builder)
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            tracing::debug!("call he, result: {}", text);
+            let text = text.trim();
+            match text.split_whitespace().next() {
+                Some("good") => Ok(RenewOutcome::applied()),
+                Some("nochg") => Ok(RenewOutcome::unchanged()),
+                _ => bail!("call he with error: {}", text),
+            }
+        }
+    }
+}
+
+mod transip {
+    use std::net::IpAddr;
+
+    use anyhow::{anyhow, bail, Result};
+    use async_trait::async_trait;
+    use data_encoding::{BASE64, HEXLOWER};
+    use openssl::{hash::MessageDigest, pkey::PKey, rand::rand_bytes, sign::Signer};
+    use reqwest::{header::CONTENT_TYPE, Client, RequestBuilder};
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use crate::http;
+
+    use super::{
+        record_type, RecordDiff, RecordSnapshot, RenewAction, RenewOutcome, UpdateProvider,
+    };
+
+    #[derive(Deserialize)]
+    struct AuthResponse {
+        token: String,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct DnsEntriesResponse {
+        dns_entries: Vec<DnsEntry>,
+    }
+
+    #[derive(Clone, Deserialize, Serialize)]
+    struct DnsEntry {
+        name: String,
+        expire: u32,
+        #[serde(rename = "type")]
+        record_type: String,
+        content: String,
+    }
+
+    pub(super) struct TransIpUpdateProvider {
+        pub(crate) api_url: String,
+        pub(crate) login: String,
+        /// The private key in pem, it is used to sign the request of an access token.
+        pub(crate) private_key: Vec<u8>,
+        pub(crate) domain: String,
+        pub(crate) ttl: Option<u32>,
+        pub(crate) client: Client,
+    }
+
+    impl TransIpUpdateProvider {
+        pub(crate) const DEFAULT_API_URL: &str = "https://api.transip.nl/v6";
+
+        /// The name relative to the domain, `@` is the domain itself.
+        fn relative_name<'a>(&self, name: &'a str) -> Result<&'a str> {
+            if name == self.domain {
+                return Ok("@");
+            }
+            name.strip_suffix(&self.domain)
+                .and_then(|n| n.strip_suffix('.'))
+                .ok_or_else(|| anyhow!("{} is not in domain {}", name, self.domain))
+        }
+
+        async fn send(&self, req_builder: RequestBuilder) -> Result<Vec<u8>> {
+            let response = http::send(req_builder).await?;
+            let err = response.error_for_status_ref().err();
+            let response_body = response.bytes().await?;
+            tracing::debug!("call transip, result: {:?}", response_body);
+            if let Some(err) = err {
+                bail!("call transip with error: {}, {:?}", err, response_body);
+            }
+            Ok(response_body.to_vec())
+        }
+
+        /// Request an access token, the body is signed by the private key.
+        #[tracing::instrument(skip(self), err)]
+        async fn auth(&self) -> Result<String> {
+            let mut nonce = [0; 16];
+            rand_bytes(&mut nonce)?;
+            let body = serde_json::to_vec(&json!({
+                "login": self.login,
+                "nonce": HEXLOWER.encode(&nonce),
+                "read_only": false,
+                "expiration_time": "30 minutes",
+                "label": format!("dns-renew-{}", HEXLOWER.encode(&nonce)),
+                "global_key": true,
+            }))?;
+            let private_key = PKey::private_key_from_pem(&self.private_key)?;
+            let mut signer = Signer::new(MessageDigest::sha512(), &private_key)?;
+            signer.update(&body)?;
+            let signature = BASE64.encode(&signer.sign_to_vec()?);
+
+            let req_builder = self
+                .client
+                .post(format!("{}/auth", self.api_url))
+                .header(CONTENT_TYPE, "application/json")
+                .header("Signature", signature)
+                .body(body);
+            let response: AuthResponse = serde_json::from_slice(&self.send(req_builder).await?)?;
+            Ok(response.token)
+        }
+    }
+
+    #[async_trait]
+    impl UpdateProvider for TransIpUpdateProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            let relative_name = self.relative_name(name)?;
+            let record_type = record_type(ip.is_ipv6());
+            let token = self.auth().await?;
+            let url = format!("{}/domains/{}/dns", self.api_url, self.domain);
+
+            let response: DnsEntriesResponse = serde_json::from_slice(
+                &self.send(self.client.get(&url).bearer_auth(&token)).await?,
+            )?;
+            let old = response
+                .dns_entries
+                .into_iter()
+                .find(|e| e.name == relative_name && e.record_type == record_type);
+
+            let new = DnsEntry {
+                name: relative_name.to_string(),
+                expire: self
+                    .ttl
+                    .or_else(|| old.as_ref().map(|e| e.expire))
+                    .unwrap_or(300),
+                record_type: record_type.to_string(),
+                content: ip.to_string(),
+            };
+            let snapshot = |e: &DnsEntry| RecordSnapshot {
+                content: Some(e.content.clone()),
+                ttl: Some(e.expire),
+                ..Default::default()
+            };
+            let diff = RecordDiff {
+                before: old.as_ref().map(snapshot),
+                after: snapshot(&new),
+            };
+
+            let (req_builder, action) = match &old {
+                Some(old) if old.content == new.content && old.expire == new.expire => {
+                    return Ok(RenewOutcome::unchanged());
+                }
+                // An entry is matched by name, type and expire in patching, so only the content
+                // can be patched.
+                Some(old) if old.expire == new.expire => {
+                    (self.client.patch(&url), RenewAction::Update)
+                }
+                // The ttl is changed by deleting the old entry and adding the new one.
+                Some(old) => {
+                    self.send(
+                        self.client
+                            .delete(&url)
+                            .bearer_auth(&token)
+                            .header(CONTENT_TYPE, "application/json")
+                            .body(serde_json::to_string(&json!({ "dnsEntry": old }))?),
+                    )
+                    .await?;
+                    (self.client.post(&url), RenewAction::Update)
+                }
+                None => {
+                    tracing::debug!("no {} record of {} in zone, create it", record_type, name);
+                    (self.client.post(&url), RenewAction::Create)
+                }
+            };
+            self.send(
+                req_builder
+                    .bearer_auth(&token)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_string(&json!({ "dnsEntry": new }))?),
+            )
+            .await?;
+            Ok(RenewOutcome::new(action, Some(diff)))
+        }
+    }
+}
+
+mod rfc2136 {
+    use std::{
+        net::IpAddr,
+        str::FromStr,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
+    use hickory_proto::{
+        op::{Message, MessageType, OpCode, Query, ResponseCode, UpdateMessage},
+        rr::{dnssec::tsig::TSigner, rdata, DNSClass, Name, RData, Record, RecordType},
+    };
+
+    use crate::dns::DnsClient;
+
+    use super::{RenewOutcome, UpdateProvider};
+
+    pub(super) struct Rfc2136UpdateProvider {
+        pub(crate) name_server_host: String,
+        pub(crate) name_server_port: Option<u16>,
+        pub(crate) timeout: Duration,
+        pub(crate) use_tcp: bool,
+        pub(crate) zone: String,
+        pub(crate) ttl: u32,
+        pub(crate) signer: Option<TSigner>,
+    }
+
+    impl Rfc2136UpdateProvider {
+        /// Build an update message which deletes rrsets of the name in the types, and adds the new
+        /// record if it is set.
+        fn update_message(
+            &self,
+            name: &str,
+            record_types: &[RecordType],
+            rdata: Option<RData>,
+        ) -> Result<Message> {
+            let name = Name::from_str(name)?;
+
+            let mut zone = Query::query(Name::from_str(&self.zone)?, RecordType::SOA);
+            zone.set_query_class(DNSClass::IN);
+
+            let mut message = Message::new();
+            message
+                .set_id(rand_id())
+                .set_message_type(MessageType::Query)
+                .set_op_code(OpCode::Update)
+                .set_recursion_desired(false);
+            message.add_zone(zone);
+
+            // Delete an rrset: class ANY, ttl 0 and no rdata, RFC 2136 2.5.2.
+            for record_type in record_types {
+                let mut delete = Record::with(name.clone(), *record_type, 0);
+                delete.set_dns_class(DNSClass::ANY);
+                message.add_update(delete);
+            }
+
+            if let Some(rdata) = rdata {
+                let mut add = Record::from_rdata(name, self.ttl, rdata);
+                add.set_dns_class(DNSClass::IN);
+                message.add_update(add);
+            }
+
+            if let Some(signer) = &self.signer {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                message.finalize(signer, now as u32)?;
+            }
+            Ok(message)
+        }
+    }
+
+    fn rand_id() -> u16 {
+        let mut id = [0; 2];
+        // An id is not a secret, a fixed one is fine if it fails.
+        let _ = openssl::rand::rand_bytes(&mut id);
+        u16::from_be_bytes(id)
+    }
+
+    impl Rfc2136UpdateProvider {
+        async fn send(&self, name: &str, message: Message) -> Result<()> {
+            let client = DnsClient::new(
+                &self.name_server_host,
+                self.name_server_port,
+                self.timeout,
+                !self.use_tcp,
+                false,
+            )?;
+            let response = client.send(message, None).await?;
+            tracing::debug!("update through rfc2136 returns: {:?}", response);
+            if response.response_code() != ResponseCode::NoError {
+                bail!(
+                    "update {} through rfc2136 with error: {}",
+                    name,
+                    response.response_code()
+                );
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl UpdateProvider for Rfc2136UpdateProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            let (record_type, rdata) = match ip {
+                IpAddr::V4(ip) => (RecordType::A, RData::A(rdata::A(ip))),
+                IpAddr::V6(ip) => (RecordType::AAAA, RData::AAAA(rdata::AAAA(ip))),
+            };
+            // A server ignores an address added next to a CNAME, RFC 2136 3.4.2.2, so a CNAME of a
+            // name flipped from `record = "CNAME"` is deleted in the same message.
+            let record_types = [record_type, RecordType::CNAME];
+            self.send(name, self.update_message(name, &record_types, Some(rdata))?)
+                .await?;
+            Ok(RenewOutcome::applied())
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn update_cname(&self, name: &str, target: &str) -> Result<RenewOutcome> {
+            let rdata = RData::CNAME(rdata::CNAME(Name::from_str(target)?));
+            let record_types = [RecordType::A, RecordType::AAAA, RecordType::CNAME];
+            self.send(name, self.update_message(name, &record_types, Some(rdata))?)
+                .await?;
+            Ok(RenewOutcome::applied())
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn delete(&self, name: &str, is_v6: bool) -> Result<()> {
+            let record_type = if is_v6 {
+                RecordType::AAAA
+            } else {
+                RecordType::A
+            };
+            self.send(name, self.update_message(name, &[record_type], None)?)
+                .await
+        }
+    }
+}
+
+mod powerdns {
+    use std::net::IpAddr;
+
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
+    use reqwest::{header::CONTENT_TYPE, Client};
+    use serde_json::{json, Value};
+
+    use crate::http;
+
+    use super::{record_type, RenewOutcome, UpdateProvider};
+
+    pub(super) struct PowerDnsUpdateProvider {
+        pub(crate) api_url: String,
+        pub(crate) server_id: String,
+        pub(crate) api_key: String,
+        pub(crate) zone: String,
+        pub(crate) ttl: u32,
+        pub(crate) client: Client,
+    }
+
+    impl PowerDnsUpdateProvider {
+        pub(crate) const DEFAULT_SERVER_ID: &str = "localhost";
+    }
+
+    /// PowerDNS only accepts canonical names, which end with a dot.
+    fn canonical(name: &str) -> String {
+        if name.ends_with('.') {
+            name.to_string()
+        } else {
+            format!("{}.", name)
+        }
+    }
+
+    impl PowerDnsUpdateProvider {
+        async fn patch(&self, rrsets: Vec<Value>) -> Result<()> {
+            let url = format!(
+                "{}/api/v1/servers/{}/zones/{}",
+                self.api_url.trim_end_matches('/'),
+                self.server_id,
+                canonical(&self.zone)
+            );
+            let body = serde_json::to_vec(&json!({ "rrsets": rrsets }))?;
+            let req_builder = self
+                .client
+                .patch(url)
+                .header(CONTENT_TYPE, "application/json")
+                .header("X-API-Key", &self.api_key)
+                .body(body);
+
+            let response = http::send(req_builder).await?;
+            let err = response.error_for_status_ref().err();
+            let response_body = response.bytes().await?;
+            tracing::debug!("call powerdns, result: {:?}", response_body);
+            if let Some(err) = err {
+                bail!("call powerdns with error: {}, {:?}", err, response_body);
+            }
+            Ok(())
+        }
+    }
+
+    fn delete_rrset(name: &str, record_type: &str) -> Value {
+        json!({
+            "name": canonical(name),
+            "type": record_type,
+            "changetype": "DELETE",
+        })
+    }
+
+    #[async_trait]
+    impl UpdateProvider for PowerDnsUpdateProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            // PowerDNS rejects a patch leaving a CNAME rrset next to an address one, the CNAME of a
+            // flip from `record = "CNAME"` is deleted in the same patch, which is applied at once.
+            self.patch(vec![
+                delete_rrset(name, "CNAME"),
+                json!({
+                    "name": canonical(name),
+                    "type": record_type(ip.is_ipv6()),
+                    "ttl": self.ttl,
+                    "changetype": "REPLACE",
+                    "records": [{ "content": ip.to_string(), "disabled": false }],
+                }),
+            ])
+            .await?;
+            Ok(RenewOutcome::applied())
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn update_cname(&self, name: &str, target: &str) -> Result<RenewOutcome> {
+            self.patch(vec![
+                delete_rrset(name, "A"),
+                delete_rrset(name, "AAAA"),
+                json!({
+                    "name": canonical(name),
+                    "type": "CNAME",
+                    "ttl": self.ttl,
+                    "changetype": "REPLACE",
+                    "records": [{ "content": canonical(target), "disabled": false }],
+                }),
+            ])
+            .await?;
+            Ok(RenewOutcome::applied())
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn delete(&self, name: &str, is_v6: bool) -> Result<()> {
+            self.patch(vec![delete_rrset(name, record_type(is_v6))])
+                .await
+        }
+    }
+}
+
+mod localzone {
+    use std::{fs, io::ErrorKind, net::IpAddr, path::PathBuf};
+
+    use anyhow::{Context, Result};
+    use async_trait::async_trait;
+
+    use crate::config::LocalResolver;
+
+    use super::{
+        record_type, run_reload_command, write_atomically, RecordDiff, RecordSnapshot, RenewAction,
+        RenewOutcome, UpdateProvider,
+    };
+
+    /// A line of a record of the name, and its ip.
+    type RecordLine = (String, IpAddr);
+
+    pub(super) struct LocalZoneUpdateProvider {
+        pub(crate) resolver: LocalResolver,
+        pub(crate) file: PathBuf,
+        pub(crate) ttl: u32,
+        pub(crate) reload_command: Vec<String>,
+    }
+
+    impl LocalZoneUpdateProvider {
+        fn line(&self, name: &str, ip: IpAddr) -> String {
+            match self.resolver {
+                LocalResolver::Dnsmasq => format!("address=/{}/{}", name, ip),
+                LocalResolver::Unbound => format!(
+                    "local-data: \"{}. {} IN {} {}\"",
+                    name.trim_end_matches('.'),
+                    self.ttl,
+                    record_type(ip.is_ipv6()),
+                    ip
+                ),
+            }
+        }
+
+        /// Return the ip of a line, if it is a record of the name in the same family.
+        fn parse(&self, line: &str, name: &str, is_v6: bool) -> Option<IpAddr> {
+            let line = line.trim();
+            let (line_name, ip) = match self.resolver {
+                LocalResolver::Dnsmasq => {
+                    let mut parts = line.strip_prefix("address=/")?.split('/');
+                    (parts.next()?, parts.next()?)
+                }
+                LocalResolver::Unbound => {
+                    let data = line.strip_prefix("local-data:")?.trim().trim_matches('"');
+                    let tokens = data.split_whitespace().collect::<Vec<_>>();
+                    let record_type = record_type(is_v6);
+                    if !tokens.iter().any(|t| t.eq_ignore_ascii_case(record_type)) {
+                        return None;
+                    }
+                    (*tokens.first()?, *tokens.last()?)
+                }
+            };
+            if !line_name
+                .trim_end_matches('.')
+                .eq_ignore_ascii_case(name.trim_end_matches('.'))
+            {
+                return None;
+            }
+            ip.parse::<IpAddr>().ok().filter(|ip| ip.is_ipv6() == is_v6)
+        }
+
+        /// Read lines of the file, except records of the name in the family, which are returned
+        /// with their ips.
+        fn read_except(&self, name: &str, is_v6: bool) -> Result<(Vec<String>, Vec<RecordLine>)> {
+            let content = match fs::read_to_string(&self.file) {
+                Ok(content) => content,
+                Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e).with_context(|| format!("failed to read {:?}", self.file)),
+            };
+
+            let mut old = vec![];
+            let mut lines = vec![];
+            for line in content.lines() {
+                match self.parse(line, name, is_v6) {
+                    Some(old_ip) => old.push((line.trim().to_string(), old_ip)),
+                    None => lines.push(line.to_string()),
+                }
+            }
+            Ok((lines, old))
+        }
+    }
+
+    #[async_trait]
+    impl UpdateProvider for LocalZoneUpdateProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            let (mut lines, old_lines) = self.read_except(name, ip.is_ipv6())?;
+            // The whole line is compared, a line of unbound has the ttl too.
+            let line = self.line(name, ip);
+            if matches!(old_lines.as_slice(), [(old_line, _)] if *old_line == line) {
+                return Ok(RenewOutcome::unchanged());
+            }
+            let old = old_lines.last().map(|(_, ip)| *ip);
+            lines.push(line);
+            let mut new_content = lines.join("\n");
+            new_content.push('\n');
+            write_atomically(&self.file, &new_content)?;
+            run_reload_command(&self.reload_command).await?;
+
+            let snapshot = |ip: IpAddr| RecordSnapshot {
+                content: Some(ip.to_string()),
+                ..Default::default()
+            };
+            let action = if old.is_some() {
+                RenewAction::Update
+            } else {
+                RenewAction::Create
+            };
+            Ok(RenewOutcome::new(
+                action,
+                Some(RecordDiff {
+                    before: old.map(snapshot),
+                    after: snapshot(ip),
+                }),
+            ))
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn delete(&self, name: &str, is_v6: bool) -> Result<()> {
+            let (lines, old_lines) = self.read_except(name, is_v6)?;
+            if old_lines.is_empty() {
+                return Ok(());
+            }
+            let mut new_content = lines.join("\n");
+            new_content.push('\n');
+            write_atomically(&self.file, &new_content)?;
+            run_reload_command(&self.reload_command).await
+        }
+    }
+}
+
+mod zonefile {
+    use std::{fs, net::IpAddr, path::PathBuf, time::SystemTime};
+
+    use anyhow::{anyhow, bail, Context, Result};
+    use async_trait::async_trait;
+
+    use super::{
+        record_type, run_reload_command, write_atomically, RecordDiff, RecordSnapshot, RenewAction,
+        RenewOutcome, UpdateProvider,
+    };
+
+    pub(super) struct ZoneFileUpdateProvider {
+        pub(crate) file: PathBuf,
+        pub(crate) zone: String,
+        pub(crate) ttl: u32,
+        pub(crate) reload_command: Vec<String>,
+    }
+
+    /// A record in a line of the zone file, its owner is absolute, in lowercase and without the
+    /// trailing dot.
+    struct Entry {
+        owner: String,
+        record_type: String,
+        rdata: Vec<String>,
+    }
+
+    fn absolute(name: &str, origin: &str) -> String {
+        if name == "@" {
+            origin.to_string()
+        } else if let Some(name) = name.strip_suffix('.') {
+            name.to_ascii_lowercase()
+        } else {
+            format!("{}.{}", name, origin).to_ascii_lowercase()
+        }
+    }
+
+    /// Parse records line by line. A record spanning lines in parentheses is only parsed by its
+    /// first line, which is enough for A, AAAA and SOA.
+    fn parse(content: &str, zone: &str) -> Vec<Option<Entry>> {
+        let mut origin = zone.trim_end_matches('.').to_ascii_lowercase();
+        let mut owner = origin.clone();
+        let mut depth = 0;
+        content
+            .lines()
+            .map(|line| {
+                let data = line.split(';').next().unwrap_or_default();
+                let in_parentheses = depth > 0;
+                depth += data.matches('(').count() as i32 - data.matches(')').count() as i32;
+                if in_parentheses {
+                    return None;
+                }
+                let mut tokens = data.split_whitespace();
+                if data.starts_with('$') {
+                    if tokens.next()?.eq_ignore_ascii_case("$ORIGIN") {
+                        origin = absolute(tokens.next()?, &origin);
+                    }
+                    return None;
+                }
+                // A line starting with a blank has the owner of the previous record.
+                if !data.starts_with(char::is_whitespace) {
+                    owner = absolute(tokens.next()?, &origin);
+                }
+                // Skip the ttl and the class.
+                let mut tokens = tokens.skip_while(|t| {
+                    t.starts_with(|c: char| c.is_ascii_digit()) || t.eq_ignore_ascii_case("IN")
+                });
+                Some(Entry {
+                    owner: owner.clone(),
+                    record_type: tokens.next()?.to_ascii_uppercase(),
+                    rdata: tokens.map(ToString::to_string).collect(),
+                })
+            })
+            .collect()
+    }
+
+    /// A serial in the format of `YYYYMMDDnn` is moved to today, others are increased by one.
+    fn next_serial(serial: u32) -> u32 {
+        let today = humantime::format_rfc3339_seconds(SystemTime::now()).to_string()[..10]
+            .replace('-', "")
+            .parse::<u32>()
+            .ok()
+            .and_then(|d| d.checked_mul(100));
+        match today {
+            Some(today) if (1_970_010_100..today).contains(&serial) => today,
+            _ => serial.wrapping_add(1),
+        }
+    }
+
+    /// Increase the serial of the SOA record starting at the line, the serial is the third token
+    /// of its rdata, which may be in the following lines.
+    fn bump_serial(lines: &mut [String], soa: usize) -> Result<()> {
+        let mut index = None;
+        for line in lines[soa..].iter_mut() {
+            let data = line.split(';').next().unwrap_or_default();
+            let tokens = data
+                .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                .filter(|t| !t.is_empty());
+            for token in tokens {
+                match index {
+                    None if token.eq_ignore_ascii_case("SOA") => index = Some(0),
+                    None => {}
+                    Some(2) => {
+                        let serial = token
+                            .parse::<u32>()
+                            .with_context(|| format!("invalid serial: {}", token))?;
+                        let start = token.as_ptr() as usize - line.as_ptr() as usize;
+                        line.replace_range(
+                            start..start + token.len(),
+                            &next_serial(serial).to_string(),
+                        );
+                        return Ok(());
+                    }
+                    Some(i) => index = Some(i + 1),
+                }
+            }
+        }
+        bail!("no serial is found in the SOA record")
+    }
+
+    impl ZoneFileUpdateProvider {
+        /// Replace records of the name in the family by the new line, or remove them if it is
+        /// `None`. The ips of the replaced records are returned, nothing is written if it is
+        /// unchanged.
+        async fn rewrite(
+            &self,
+            name: &str,
+            is_v6: bool,
+            new: Option<(IpAddr, String)>,
+        ) -> Result<Vec<IpAddr>> {
+            let content = fs::read_to_string(&self.file)
+                .with_context(|| format!("failed to read {:?}", self.file))?;
+            let zone = self.zone.trim_end_matches('.').to_ascii_lowercase();
+            let name = name.trim_end_matches('.').to_ascii_lowercase();
+            if name != zone && !name.ends_with(&format!(".{}", zone)) {
+                bail!("{} is not in zone {}", name, zone);
+            }
+            let record_type = record_type(is_v6);
+
+            let entries = parse(&content, &zone);
+            let matched = entries
+                .iter()
+                .enumerate()
+                .filter_map(|(i, e)| {
+                    e.as_ref()
+                        .filter(|e| e.owner == name && e.record_type == record_type)
+                        .map(|e| (i, e))
+                })
+                .collect::<Vec<_>>();
+            let old = matched
+                .iter()
+                .filter_map(|(_, e)| e.rdata.first()?.parse::<IpAddr>().ok())
+                .collect::<Vec<_>>();
+            match &new {
+                Some((ip, _)) if old == [*ip] => return Ok(old),
+                None if matched.is_empty() => return Ok(old),
+                _ => {}
+            }
+
+            let soa = entries
+                .iter()
+                .position(|e| e.as_ref().is_some_and(|e| e.record_type == "SOA"))
+                .ok_or_else(|| anyhow!("no SOA record in {:?}", self.file))?;
+            let mut lines = content.lines().map(ToString::to_string).collect::<Vec<_>>();
+            bump_serial(&mut lines, soa)?;
+            let at = matched.first().map(|(i, _)| *i).unwrap_or(lines.len());
+            for (i, _) in matched.iter().rev() {
+                let removed = lines.remove(*i);
+                if new.is_some() && *i == at || removed.starts_with(char::is_whitespace) {
+                    continue;
+                }
+                // The owner is moved to the next record, if it has the owner of the removed one.
+                let next = lines[*i..]
+                    .iter_mut()
+                    .find(|l| !l.split(';').next().unwrap_or_default().trim().is_empty());
+                if let Some(next) = next.filter(|l| l.starts_with(char::is_whitespace)) {
+                    next.insert_str(0, &format!("{}.", name));
+                }
+            }
+            if let Some((_, line)) = new {
+                // The owner is absolute, so lines following it keep their owner.
+                lines.insert(at, line);
+            }
+            let mut new_content = lines.join("\n");
+            new_content.push('\n');
+            write_atomically(&self.file, &new_content)?;
+            run_reload_command(&self.reload_command).await?;
+            Ok(old)
+        }
+    }
+
+    #[async_trait]
+    impl UpdateProvider for ZoneFileUpdateProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            let line = format!(
+                "{}.\t{}\tIN\t{}\t{}",
+                name.trim_end_matches('.'),
+                self.ttl,
+                record_type(ip.is_ipv6()),
+                ip
+            );
+            let old = self.rewrite(name, ip.is_ipv6(), Some((ip, line))).await?;
+            if old == [ip] {
+                return Ok(RenewOutcome::unchanged());
+            }
+
+            let snapshot = |ip: &IpAddr| RecordSnapshot {
+                content: Some(ip.to_string()),
+                ..Default::default()
+            };
+            let action = if old.is_empty() {
+                RenewAction::Create
+            } else {
+                RenewAction::Update
+            };
+            Ok(RenewOutcome::new(
+                action,
+                Some(RecordDiff {
+                    before: old.first().map(snapshot),
+                    after: snapshot(&ip),
+                }),
+            ))
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn delete(&self, name: &str, is_v6: bool) -> Result<()> {
+            self.rewrite(name, is_v6, None).await?;
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const ZONE: &str = "\
+$ORIGIN example.com.
+@\t3600\tIN\tSOA\tns1 admin (
+\t\t2020010105 ; serial
+\t\t3600 600 86400 300 )
+www\t300\tIN\tA\t192.0.2.1
+\t300\tIN\tAAAA\t2001:db8::1
+$ORIGIN sub.example.com.
+host\tA\t192.0.2.2
+abs.example.org.\t60\tA\t192.0.2.3
+";
+
+        fn records(content: &str) -> Vec<(String, String, String)> {
+            parse(content, "example.com")
+                .into_iter()
+                .flatten()
+                .map(|e| (e.owner, e.record_type, e.rdata.join(" ")))
+                .collect()
+        }
+
+        fn record(owner: &str, record_type: &str, rdata: &str) -> (String, String, String) {
+            (owner.into(), record_type.into(), rdata.into())
+        }
+
+        #[test]
+        fn parse_owners() {
+            assert_eq!(
+                records(ZONE),
+                [
+                    record("example.com", "SOA", "ns1 admin ("),
+                    record("www.example.com", "A", "192.0.2.1"),
+                    record("www.example.com", "AAAA", "2001:db8::1"),
+                    record("host.sub.example.com", "A", "192.0.2.2"),
+                    record("abs.example.org", "A", "192.0.2.3"),
+                ]
+            );
+        }
+
+        #[test]
+        fn next_serials() {
+            // A date-style serial is moved to today.
+            let serial = next_serial(2020010105);
+            assert!(serial > 2020010105);
+            assert_eq!(serial % 100, 0);
+            // One of today is increased.
+            assert_eq!(next_serial(serial), serial + 1);
+            assert_eq!(next_serial(42), 43);
+            assert_eq!(next_serial(u32::MAX), 0);
+        }
+
+        #[test]
+        fn bump_serial_of_multi_line_soa() {
+            let mut lines = ZONE.lines().map(ToString::to_string).collect::<Vec<_>>();
+            bump_serial(&mut lines, 1).unwrap();
+            assert_eq!(
+                lines[2],
+                format!("\t\t{} ; serial", next_serial(2020010105))
+            );
+
+            let mut lines = vec!["@ IN SOA ns1 admin 7 3600 600 86400 300".to_string()];
+            bump_serial(&mut lines, 0).unwrap();
+            assert_eq!(lines[0], "@ IN SOA ns1 admin 8 3600 600 86400 300");
+
+            let mut lines = vec!["@ IN SOA ns1 admin (".to_string()];
+            assert!(bump_serial(&mut lines, 0).is_err());
+        }
+
+        #[tokio::test]
+        async fn remove_first_record_of_owner() {
+            let file = std::env::temp_dir().join(format!("dns-renew-{}.zone", std::process::id()));
+            fs::write(&file, ZONE).unwrap();
+            let provider = ZoneFileUpdateProvider {
+                file: file.clone(),
+                zone: "example.com".to_string(),
+                ttl: 300,
+                reload_command: vec![],
+            };
+            let old = provider
+                .rewrite("www.example.com", false, None)
+                .await
+                .unwrap();
+            let content = fs::read_to_string(&file).unwrap();
+            fs::remove_file(&file).unwrap();
+
+            assert_eq!(old, ["192.0.2.1".parse::<IpAddr>().unwrap()]);
+            // The next record keeps its owner.
+            assert_eq!(
+                records(&content)[1..3],
+                [
+                    record("www.example.com", "AAAA", "2001:db8::1"),
+                    record("host.sub.example.com", "A", "192.0.2.2"),
+                ]
+            );
+            assert!(!content.contains("2020010105"));
+        }
+    }
+}
+
+mod exec {
+    use std::net::IpAddr;
+
+    use anyhow::{bail, Context, Result};
+    use async_trait::async_trait;
+    use tokio::process::Command;
+
+    use super::{RenewOutcome, TemplateContext, UpdateProvider};
+
+    pub(super) struct ExecUpdateProvider {
+        pub(crate) program: String,
+        pub(crate) args: Vec<String>,
+        pub(crate) unchanged_exit_code: i32,
+        pub(crate) context: TemplateContext,
+    }
+
+    impl ExecUpdateProvider {
+        pub(crate) const DEFAULT_UNCHANGED_EXIT_CODE: i32 = 100;
+    }
+
+    #[async_trait]
+    impl UpdateProvider for ExecUpdateProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            let vars = self.context.vars(name, ip)?;
+            let args = self
+                .args
+                .iter()
+                .map(|a| self.context.render(a, &vars))
+                .collect::<Result<Vec<_>>>()?;
+            tracing::debug!("args after rendered: {:?}", args);
+
+            let output = Command::new(&self.program)
+                .args(&args)
+                .envs(
+                    vars.iter()
+                        .map(|(k, v)| (format!("DNS_RENEW_{}", k.to_uppercase()), v)),
+                )
+                .output()
+                .await
+                .with_context(|| format!("failed to run {}", self.program))?;
+            tracing::debug!(
+                "{} exits with {}, stdout: {:?}, stderr: {:?}",
+                self.program,
+                output.status,
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            match output.status.code() {
+                Some(0) => Ok(RenewOutcome::applied()),
+                Some(code) if code == self.unchanged_exit_code => Ok(RenewOutcome::unchanged()),
+                _ => bail!(
+                    "{} exits with {}: {}",
+                    self.program,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            }
+        }
+    }
+}
+
+mod mikrotik {
+    use std::net::IpAddr;
+
+    use anyhow::{bail, Context, Result};
+    use async_trait::async_trait;
+    use reqwest::{header::CONTENT_TYPE, Client, RequestBuilder};
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use crate::{config::HttpBasicAuthCredential, http};
+
+    use super::{
+        record_type, RecordDiff, RecordSnapshot, RenewAction, RenewOutcome, UpdateProvider,
+    };
+
+    #[derive(Deserialize)]
+    struct StaticEntry {
+        #[serde(rename = ".id")]
+        id: String,
+        name: Option<String>,
+        address: Option<String>,
+        /// It is absent for an A record.
+        #[serde(rename = "type")]
+        record_type: Option<String>,
+    }
+
+    pub(super) struct MikrotikUpdateProvider {
+        /// e.g. `https://192.168.88.1`.
+        pub(crate) url: String,
+        pub(crate) credential: HttpBasicAuthCredential,
+        pub(crate) ttl: Option<String>,
+        pub(crate) client: Client,
+    }
+
+    impl MikrotikUpdateProvider {
+        async fn send(&self, req_builder: RequestBuilder) -> Result<Vec<u8>> {
+            let req_builder = req_builder.basic_auth(
+                self.credential.username(),
+                self.credential.password().as_ref(),
+            );
+            let response = http::send(req_builder).await?;
+            let err = response.error_for_status_ref().err();
+            let response_body = response.bytes().await?;
+            tracing::debug!("call mikrotik, result: {:?}", response_body);
+            if let Some(err) = err {
+                bail!("call mikrotik with error: {}, {:?}", err, response_body);
+            }
+            Ok(response_body.to_vec())
+        }
+
+        fn url(&self) -> String {
+            format!("{}/rest/ip/dns/static", self.url.trim_end_matches('/'))
+        }
+
+        async fn find(
+            &self,
+            client: &Client,
+            name: &str,
+            is_v6: bool,
+        ) -> Result<Option<StaticEntry>> {
+            let record_type = record_type(is_v6);
+            let entries: Vec<StaticEntry> = serde_json::from_slice(
+                &self
+                    .send(client.get(self.url()).query(&[("name", name)]))
+                    .await?,
+            )?;
+            Ok(entries.into_iter().find(|e| {
+                e.name.as_deref() == Some(name)
+                    && e.record_type.as_deref().unwrap_or("A") == record_type
+            }))
+        }
+    }
+
+    #[async_trait]
+    impl UpdateProvider for MikrotikUpdateProvider {
+        #[tracing::instrument(skip(self), err)]
+        async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome> {
+            let record_type = record_type(ip.is_ipv6());
+            let url = self.url();
+            let client = &self.client;
+            let old = self.find(client, name, ip.is_ipv6()).await?;
+
+            let snapshot = |address: &str| RecordSnapshot {
+                content: Some(address.to_string()),
+                ..Default::default()
+            };
+            let ip = ip.to_string();
+            let mut body = json!({ "address": ip });
+            if let Some(ttl) = &self.ttl {
+                body["ttl"] = json!(ttl);
+            }
+            match old {
+                Some(old) if old.address.as_deref() == Some(ip.as_str()) => {
+                    Ok(RenewOutcome::unchanged())
+                }
+                Some(old) => {
+                    self.send(
+                        client
+                            .patch(format!("{}/{}", url, old.id))
+                            .header(CONTENT_TYPE, "application/json")
+                            .body(serde_json::to_vec(&body)?),
+                    )
+                    .await?;
+                    Ok(RenewOutcome::new(
+                        RenewAction::Update,
+                        Some(RecordDiff {
+                            before: old.address.as_deref().map(snapshot),
+                            after: snapshot(&ip),
+                        }),
+                    ))
+                }
+                None => {
+                    body["name"] = json!(name);
+                    body["type"] = json!(record_type);
+                    self.send(
+                        client
+                            .put(&url)
+                            .header(CONTENT_TYPE, "application/json")
+                            .body(serde_json::to_vec(&body)?),
+                    )
+                    .await?;
+                    Ok(RenewOutcome::new(
+                        RenewAction::Create,
+                        Some(RecordDiff {
+                            before: None,
+                            after: snapshot(&ip),
+                        }),
+                    ))
+                }
+            }
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn delete(&self, name: &str, is_v6: bool) -> Result<()> {
+            let client = &self.client;
+            if let Some(old) = self.find(client, name, is_v6).await? {
+                self.send(client.delete(format!("{}/{}", self.url(), old.id)))
+                    .await?;
+            }
+            Ok(())
+        }
+
+        #[tracing::instrument(skip(self), err)]
+        async fn query_ips(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
+            self.find(&self.client, name, is_v6)
+                .await?
+                .and_then(|e| e.address)
+                .map(|address| {
+                    address
+                        .parse()
+                        .with_context(|| format!("invalid ip in mikrotik: {}", address))
+                })
+                .into_iter()
+                .collect()
+        }
+    }
+}
+
+/// Apply a credential to a request of the generic http providers.
+pub(crate) fn with_http_credential(
+    req_builder: RequestBuilder,
+    credential: Option<&UpdateCredential>,
+) -> Result<RequestBuilder> {
+    Ok(match credential {
+        Some(UpdateCredential::HttpBasicAuth(credential)) => {
+            req_builder.basic_auth(credential.username(), credential.password().as_ref())
+        }
+        Some(UpdateCredential::HttpBearerToken { token }) => req_builder.bearer_auth(token),
+        Some(_) => bail!("Only HttpBasicAuth or HttpBearerToken credential is supported."),
+        None => req_builder,
+    })
+}
+
+/// Check the body of a response with status 200, since some endpoints report errors in it.
+pub(crate) struct ResponseValidator {
+    success: Option<Regex>,
+    failure: Option<Regex>,
+}
+
+impl ResponseValidator {
+    fn new(success: &Option<String>, failure: &Option<String>) -> Result<Self> {
+        let compile = |regex: &Option<String>| {
+            regex
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .with_context(|| format!("invalid regex: {:?}", regex))
+        };
+        Ok(Self {
+            success: compile(success)?,
+            failure: compile(failure)?,
+        })
+    }
+
+    fn validate(&self, body: &str) -> Result<()> {
+        tracing::debug!("validate response: {:?}", body);
+        if let Some(failure) = &self.failure {
+            if failure.is_match(body) {
+                bail!("response matches failure_body_regex: {}", body.trim());
+            }
+        }
+        if let Some(success) = &self.success {
+            if !success.is_match(body) {
+                bail!("response doesn't match success_body_regex: {}", body.trim());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The method of a provider sending a body.
+fn body_method(method: &str, provider: &str) -> Result<Method> {
+    Ok(match method.to_uppercase().as_str() {
+        "POST" => Method::POST,
+        "PUT" => Method::PUT,
+        "PATCH" => Method::PATCH,
+        _ => {
+            bail!("Unsupport method in {}: {}", provider, method);
+        }
+    })
+}
+
+fn rest_request(conf: &RestRequestConf, default_method: &str) -> Result<genericrest::RestRequest> {
+    let method = conf.method().as_deref().unwrap_or(default_method);
+    Ok(genericrest::RestRequest {
+        url: conf.url().clone(),
+        method: Method::from_bytes(method.to_uppercase().as_bytes())
+            .map_err(|_| anyhow!("Unsupport method in GenericRest: {}", method))?,
+        body: conf.body().clone(),
+    })
+}
+
+pub(crate) fn find_optional_update_credential(
+    config: &Config,
+    credential: &Option<String>,
+) -> Result<Option<UpdateCredential>> {
+    if let Some(credential) = credential {
+        Ok(Some(find_update_credential(config, credential)?))
+    } else {
+        Ok(None)
+    }
+}
+
+pub(crate) fn find_update_credential(
+    config: &Config,
+    credential: &String,
+) -> Result<UpdateCredential> {
+    if let Some(credential) = config.update_credentials().get(credential) {
+        Ok(credential.clone())
+    } else {
+        bail!("Credential not found: {}", credential)
+    }
+}
+
+/// Check an update provider without contacting any service, its templates are rendered with a
+/// sample ip of the family.
+pub(crate) async fn check_update_provider(
+    update_provider_type: &UpdateProviderType,
+    config: &Config,
+    name_conf: &NameConf,
+    is_v6: bool,
+) -> Result<()> {
+    let mut zone_ids = BTreeMap::new();
+    // The zone id would be resolved by the api of cloudflare.
+    if let UpdateProviderType::Cloudflare {
+        zone: Some(zone), ..
+    } = update_provider_type
+    {
+        zone_ids.insert(zone.clone(), String::new());
+    }
+    init_update_provider(update_provider_type, config, name_conf, &mut zone_ids).await?;
+
+    let context = TemplateContext::new(name_conf);
+    let ip = if is_v6 {
+        IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1])
+    } else {
+        IpAddr::from([192, 0, 2, 1])
+    };
+    let mut vars = context.vars(name_conf.name(), ip)?;
+    vars.insert("id".to_string(), "id".to_string());
+    match update_provider_type {
+        UpdateProviderType::HttpGet { url_template, .. } => {
+            context.render(url_template, &url_encoded(&vars))?;
+        }
+        UpdateProviderType::HttpPlainBody { body_template, .. } => {
+            context.render(body_template, &vars)?;
+        }
+        UpdateProviderType::HttpJsonBody { body, .. } => {
+            context.render_json(body, &vars)?;
+        }
+        UpdateProviderType::GenericRest {
+            query,
+            create,
+            update,
+            ..
+        } => {
+            for request in [query, create, update] {
+                context.render(request.url(), &url_encoded(&vars))?;
+                if let Some(body) = request.body() {
+                    context.render_json(body, &vars)?;
+                }
+            }
+        }
+        UpdateProviderType::Exec { args, .. } => {
+            for arg in args {
+                context.render(arg, &vars)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// `zone_ids` is a cache of zone ids resolved from zone names, keyed by the zone name.
+/// The ttl of `name_conf` is used if the provider doesn't set one.
+pub async fn init_update_provider(
+    update_provider_type: &UpdateProviderType,
+    config: &Config,
+    name_conf: &NameConf,
+    zone_ids: &mut BTreeMap<String, String>,
+) -> Result<Box<dyn UpdateProvider>> {
+    let name_ttl = name_conf.ttl();
+    let context = TemplateContext::new(name_conf);
+    match update_provider_type {
+        UpdateProviderType::HttpGet {
+            credential,
+            url_template,
+            success_body_regex,
+            failure_body_regex,
+            script,
+            http_client,
+        } => Ok(Box::new(httpget::HttpGetUpdateProvider {
+            credential: find_optional_update_credential(config, credential)?,
+            url_template: url_template.clone(),
+            validator: ResponseValidator::new(success_body_regex, failure_body_regex)?,
+            script: script.as_deref().map(Script::load).transpose()?,
+            client: http::client_of(http_client)?,
+            context,
+        })),
+        UpdateProviderType::HttpPlainBody {
+            credential,
+            url,
+            method,
+            content_type,
+            body_template,
+            success_body_regex,
+            failure_body_regex,
+            script,
+            http_client,
+        } => Ok(Box::new(httpplainbody::HttpPlainBodyUpdateProvider {
+            credential: find_optional_update_credential(config, credential)?,
+            url: url.clone(),
+            method: body_method(method, "HttpPlainBody")?,
+            content_type: content_type.clone(),
+            body_template: body_template.clone(),
+            validator: ResponseValidator::new(success_body_regex, failure_body_regex)?,
+            script: script.as_deref().map(Script::load).transpose()?,
+            client: http::client_of(http_client)?,
+            context,
+        })),
+        UpdateProviderType::HttpJsonBody {
+            credential,
+            url,
+            method,
+            body,
+            success_pointer,
+            success_value,
+            script,
+            http_client,
+        } => Ok(Box::new(httpjsonbody::HttpJsonBodyUpdateProvider {
+            credential: find_optional_update_credential(config, credential)?,
+            url: url.clone(),
+            method: body_method(method.as_deref().unwrap_or("POST"), "HttpJsonBody")?,
+            body: body.clone(),
+            success_pointer: success_pointer.clone(),
+            success_value: success_value.clone(),
+            script: script.as_deref().map(Script::load).transpose()?,
+            client: http::client_of(http_client)?,
+            context,
+        })),
+        UpdateProviderType::GenericRest {
+            credential,
+            query,
+            create,
+            update,
+            records_pointer,
+            id_pointer,
+            content_pointer,
+            script,
+            http_client,
+        } => Ok(Box::new(genericrest::GenericRestUpdateProvider {
+            credential: find_optional_update_credential(config, credential)?,
+            query: rest_request(query, "GET")?,
+            create: rest_request(create, "POST")?,
+            update: rest_request(update, "PUT")?,
+            records_pointer: records_pointer.clone().unwrap_or_default(),
+            id_pointer: id_pointer.clone(),
+            content_pointer: content_pointer.clone(),
+            script: script.as_deref().map(Script::load).transpose()?,
+            client: http::client_of(http_client)?,
+            context,
+        })),
+        UpdateProviderType::Cloudflare {
+            credential,
+            zone_id,
+            zone,
+            proxied,
+            ttl,
+            comment,
+            managed_comment,
+            http_client,
+        } => {
+            let client = http::client_of(http_client)?;
+            let auth = match find_update_credential(config, credential)? {
+                UpdateCredential::HttpBearerToken { token } => {
+                    cloudflare::CloudflareAuth::Token(token)
+                }
+                UpdateCredential::CloudflareGlobalKey { email, key } => {
+                    cloudflare::CloudflareAuth::GlobalKey { email, key }
+                }
+                _ => {
+                    bail!("Only HttpBearerToken or CloudflareGlobalKey credential is supported when cloudflare is used.");
+                }
+            };
+            let zone_id = match (zone_id, zone) {
+                (Some(zone_id), _) => zone_id.clone(),
+                (None, Some(zone)) => match zone_ids.get(zone) {
+                    Some(zone_id) => zone_id.clone(),
+                    None => {
+                        let zone_id = cloudflare::CloudflareUpdateProvider::resolve_zone_id(
+                            &client, &auth, zone,
+                        )
+                        .await?;
+                        zone_ids.insert(zone.clone(), zone_id.clone());
+                        zone_id
+                    }
+                },
+                (None, None) => bail!("Either zone_id or zone should be set in cloudflare."),
+            };
+            Ok(Box::new(cloudflare::CloudflareUpdateProvider {
+                auth,
+                zone_id,
+                proxied: proxied.unwrap_or(false),
+                ttl: ttl.or(name_ttl),
+                comment: managed_comment.clone().or_else(|| comment.clone()),
+                managed: managed_comment.is_some(),
+                client,
+            }))
+        }
+        UpdateProviderType::Inwx {
+            credential,
+            domain,
+            ttl,
+            api_url,
+            http_client,
+        } => {
+            let credential = match find_update_credential(config, credential)? {
+                UpdateCredential::Inwx(credential) => credential,
+                _ => {
+                    bail!("Only Inwx credential is supported when inwx is used.");
+                }
+            };
+            Ok(Box::new(inwx::InwxUpdateProvider {
+                api_url: api_url
+                    .clone()
+                    .unwrap_or_else(|| inwx::InwxUpdateProvider::DEFAULT_API_URL.to_string()),
+                username: credential.username().clone(),
+                password: credential.password().clone(),
+                totp_secret: credential.totp_secret().clone(),
+                domain: domain.clone(),
+                ttl: ttl.or(name_ttl),
+                client: http::client_of(http_client)?,
+            }))
+        }
+        UpdateProviderType::HurricaneElectric {
+            credential,
+            url,
+            http_client,
+        } => {
+            let key = match find_update_credential(config, credential)? {
+                UpdateCredential::ApiKey { key } => key,
+                _ => {
+                    bail!("Only ApiKey credential is supported when hurricane electric is used.");
+                }
+            };
+            Ok(Box::new(
+                hurricaneelectric::HurricaneElectricUpdateProvider {
+                    url: url.clone().unwrap_or_else(|| {
+                        hurricaneelectric::HurricaneElectricUpdateProvider::DEFAULT_URL.to_string()
+                    }),
+                    key,
+                    client: http::client_of(http_client)?,
+                },
+            ))
+        }
+        UpdateProviderType::TransIp {
+            credential,
+            domain,
+            ttl,
+            api_url,
+            http_client,
+        } => {
+            let credential = match find_update_credential(config, credential)? {
+                UpdateCredential::TransIp(credential) => credential,
+                _ => {
+                    bail!("Only TransIp credential is supported when transip is used.");
+                }
+            };
+            let private_key = fs::read(credential.private_key_file()).with_context(|| {
+                format!(
+                    "failed to read private key of transip: {:?}",
+                    credential.private_key_file()
+                )
+            })?;
+            Ok(Box::new(transip::TransIpUpdateProvider {
+                api_url: api_url
+                    .clone()
+                    .unwrap_or_else(|| transip::TransIpUpdateProvider::DEFAULT_API_URL.to_string()),
+                login: credential.login().clone(),
+                private_key,
+                domain: domain.clone(),
+                ttl: ttl.or(name_ttl),
+                client: http::client_of(http_client)?,
+            }))
+        }
+        UpdateProviderType::PowerDns {
+            credential,
+            api_url,
+            server_id,
+            zone,
+            ttl,
+            http_client,
+        } => {
+            let key = match find_update_credential(config, credential)? {
+                UpdateCredential::ApiKey { key } => key,
+                _ => bail!("Only ApiKey credential is supported when powerdns is used."),
+            };
+            Ok(Box::new(powerdns::PowerDnsUpdateProvider {
+                api_url: api_url.clone(),
+                server_id: server_id.clone().unwrap_or_else(|| {
+                    powerdns::PowerDnsUpdateProvider::DEFAULT_SERVER_ID.to_string()
+                }),
+                api_key: key,
+                zone: zone.clone(),
+                ttl: ttl.or(name_ttl).unwrap_or(300),
+                client: http::client_of(http_client)?,
+            }))
+        }
+        UpdateProviderType::LocalZone {
+            resolver,
+            file,
+            ttl,
+            reload_command,
+        } => Ok(Box::new(localzone::LocalZoneUpdateProvider {
+            resolver: *resolver,
+            file: file.clone(),
+            ttl: ttl.or(name_ttl).unwrap_or(300),
+            reload_command: reload_command.clone(),
+        })),
+        UpdateProviderType::ZoneFile {
+            file,
+            zone,
+            ttl,
+            reload_command,
+        } => Ok(Box::new(zonefile::ZoneFileUpdateProvider {
+            file: file.clone(),
+            zone: zone.clone(),
+            ttl: ttl.or(name_ttl).unwrap_or(300),
+            reload_command: reload_command.clone(),
+        })),
+        UpdateProviderType::Exec {
+            program,
+            args,
+            unchanged_exit_code,
+        } => Ok(Box::new(exec::ExecUpdateProvider {
+            program: program.clone(),
+            args: args.clone(),
+            unchanged_exit_code: unchanged_exit_code
+                .unwrap_or(exec::ExecUpdateProvider::DEFAULT_UNCHANGED_EXIT_CODE),
+            context,
+        })),
+        UpdateProviderType::Mikrotik {
+            credential,
+            url,
+            ttl,
+            accept_invalid_certs,
+            http_client,
+        } => {
+            let credential = match find_update_credential(config, credential)? {
+                UpdateCredential::HttpBasicAuth(credential) => credential,
+                _ => bail!("Only HttpBasicAuth credential is supported when mikrotik is used."),
+            };
+            Ok(Box::new(mikrotik::MikrotikUpdateProvider {
+                url: url.clone(),
+                credential,
+                // In the format of RouterOS.
+                ttl: ttl.clone().or_else(|| name_ttl.map(|t| format!("{}s", t))),
+                client: http::builder_of(http_client)?
+                    // Routers usually have a self-signed certificate.
+                    .danger_accept_invalid_certs(accept_invalid_certs.unwrap_or(false))
+                    .build()?,
+            }))
+        }
+        UpdateProviderType::Rfc2136 {
+            credential,
+            name_server_host,
+            name_server_port,
+            timeout,
+            use_tcp,
+            zone,
+            ttl,
+        } => {
+            let signer = match find_optional_update_credential(config, credential)? {
+                Some(UpdateCredential::Tsig(credential)) => Some(
+                    TSigner::new(
+                        BASE64
+                            .decode(credential.secret().as_bytes())
+                            .with_context(|| "invalid secret of tsig")?,
+                        credential.algorithm()?,
+                        Name::from_str(credential.key_name())?,
+                        300,
+                    )
+                    .with_context(|| "failed to create tsig signer")?,
+                ),
+                Some(_) => bail!("Only Tsig credential is supported when rfc2136 is used."),
+                None => None,
+            };
+            Ok(Box::new(rfc2136::Rfc2136UpdateProvider {
+                name_server_host: name_server_host.clone(),
+                name_server_port: *name_server_port,
+                timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
+                use_tcp: use_tcp.unwrap_or(false),
+                zone: zone.clone(),
+                ttl: ttl.or(name_ttl).unwrap_or(300),
+                signer,
+            }))
+        }
+        UpdateProviderType::Custom { name, options } => registry::update_provider(name, options),
+        UpdateProviderType::Plugin {
+            plugin,
+            options,
+            timeout,
+        } => Ok(Box::new(PluginProvider::new(
+            config, plugin, options, *timeout,
+        )?)),
+    }
+}
+
+/// What a renew did, or would do in dry run, to the record of a name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenewAction {
+    /// The record is already up to date.
+    Unchanged,
+    /// The record doesn't exist yet, it is created for the first time.
+    Create,
+    /// The record exists, but its content is changed.
+    Update,
+    /// The record is deleted, since the name is disabled or removed.
+    Delete,
+}
+
+impl RenewAction {
+    pub fn is_changed(&self) -> bool {
+        !matches!(self, Self::Unchanged)
+    }
+}
+
+impl Display for RenewAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unchanged => write!(f, "unchanged"),
+            Self::Create => write!(f, "create"),
+            Self::Update => write!(f, "update"),
+            Self::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+/// The fields of a record which are managed by dns-renew, a field is `None` if it is unknown or
+/// not supported by the provider.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RecordSnapshot {
+    pub content: Option<String>,
+    pub ttl: Option<u32>,
+    pub proxied: Option<bool>,
+    pub comment: Option<String>,
+}
+
+/// A record before and after an update, `before` is `None` if the record is created.
+#[derive(Clone, Debug, Serialize)]
+pub struct RecordDiff {
+    pub before: Option<RecordSnapshot>,
+    pub after: RecordSnapshot,
+}
+
+impl Display for RecordDiff {
+    /// Only fields which are changed are displayed, e.g. `content: 1.1.1.1 -> 2.2.2.2`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn field<T: Display>(v: &Option<T>) -> String {
+            v.as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_else(|| "-".to_string())
+        }
+
+        let before = self.before.clone().unwrap_or_default();
+        let after = &self.after;
+        let mut changes = vec![];
+        if before.content != after.content {
+            changes.push(format!(
+                "content: {} -> {}",
+                field(&before.content),
+                field(&after.content)
+            ));
+        }
+        if before.ttl != after.ttl {
+            changes.push(format!(
+                "ttl: {} -> {}",
+                field(&before.ttl),
+                field(&after.ttl)
+            ));
+        }
+        if before.proxied != after.proxied {
+            changes.push(format!(
+                "proxied: {} -> {}",
+                field(&before.proxied),
+                field(&after.proxied)
+            ));
+        }
+        if before.comment != after.comment {
+            changes.push(format!(
+                "comment: {} -> {}",
+                field(&before.comment),
+                field(&after.comment)
+            ));
+        }
+        write!(f, "{}", changes.join(", "))
+    }
+}
+
+/// What an update provider did, with the diff of the record if it is changed.
+#[derive(Debug)]
+pub struct RenewOutcome {
+    pub action: RenewAction,
+    pub diff: Option<RecordDiff>,
+    /// Whether the provider knows if the record is created or updated, see `applied`.
+    pub known: bool,
+}
+
+impl RenewOutcome {
+    pub fn new(action: RenewAction, diff: Option<RecordDiff>) -> Self {
+        Self {
+            action,
+            diff,
+            known: true,
+        }
+    }
+
+    pub fn unchanged() -> Self {
+        Self::new(RenewAction::Unchanged, None)
+    }
+
+    /// The record is applied, but the provider can't tell whether it is created or updated, e.g.
+    /// by a ddns url, the action decided by querying records is used instead.
+    pub fn applied() -> Self {
+        Self {
+            known: false,
+            ..Self::new(RenewAction::Update, None)
+        }
+    }
+
+    /// Take `decided` as the action, if the provider doesn't know it.
+    pub(crate) fn or_decided(mut self, decided: RenewAction) -> Self {
+        if !self.known {
+            self.action = decided;
+            self.known = true;
+        }
+        self
+    }
+}
+
+/// A record which is created along with a new record, e.g. a CAA record.
+pub struct CompanionRecord {
+    pub name: String,
+    pub record_type: String,
+    pub content: String,
+    pub ttl: Option<u32>,
+    /// Structured data of the record, it is only used by some providers.
+    pub data: Option<serde_json::Value>,
+}
+
+/// Write to a temporary file first, so the reader never reads a partial file.
+fn write_atomically(path: &Path, content: &str) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, content).with_context(|| format!("failed to write {:?}", tmp))?;
+    fs::rename(&tmp, path).with_context(|| format!("failed to replace {:?}", path))?;
+    Ok(())
+}
+
+/// Run a command to reload a service after its file is rewritten, it does nothing if it is empty.
+async fn run_reload_command(reload_command: &[String]) -> Result<()> {
+    let Some((program, args)) = reload_command.split_first() else {
+        return Ok(());
+    };
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .await
+        .with_context(|| format!("failed to run {}", program))?;
+    if !status.success() {
+        bail!("reload command {:?} exits with {}", reload_command, status);
+    }
+    Ok(())
+}
+
+/// Values of a name used by templates of update providers.
+#[derive(Clone)]
+pub(crate) struct TemplateContext {
+    pub(crate) engine: TemplateEngine,
+    pub(crate) ttl: u32,
+    /// The zone of the name, it is guessed by the last two labels of the name if it is not set.
+    pub(crate) zone: Option<String>,
+}
+
+/// The type of address records of a family.
+pub(super) fn record_type(is_v6: bool) -> &'static str {
+    if is_v6 {
+        "AAAA"
+    } else {
+        "A"
+    }
+}
+
+impl TemplateContext {
+    pub(crate) fn new(name_conf: &NameConf) -> Self {
+        Self {
+            engine: name_conf.template_engine(),
+            ttl: name_conf.ttl().unwrap_or(300),
+            zone: name_conf.zone().clone(),
+        }
+    }
+
+    /// `{name}`, `{ip}`, `{ttl}`, `{record_type}`, `{is_v6}`, `{family}`, `{zone}` and
+    /// `{subdomain}`, which is `@` for the apex.
+    pub(crate) fn vars(&self, name: &str, ip: IpAddr) -> Result<HashMap<String, String>> {
+        let lower = name.trim_end_matches('.').to_ascii_lowercase();
+        let zone = match &self.zone {
+            Some(zone) => zone.trim_end_matches('.').to_ascii_lowercase(),
+            None => {
+                let mut labels = lower.rsplitn(3, '.').take(2).collect::<Vec<_>>();
+                labels.reverse();
+                labels.join(".")
+            }
+        };
+        let subdomain = if lower == zone {
+            "@".to_string()
+        } else {
+            match lower
+                .strip_suffix(zone.as_str())
+                .and_then(|s| s.strip_suffix('.'))
+            {
+                Some(subdomain) => subdomain.to_string(),
+                None => bail!("{} is not in zone {}", name, zone),
+            }
+        };
+        Ok(HashMap::from([
+            ("name".to_string(), name.to_string()),
+            ("ip".to_string(), ip.to_string()),
+            ("ttl".to_string(), self.ttl.to_string()),
+            (
+                "record_type".to_string(),
+                record_type(ip.is_ipv6()).to_string(),
+            ),
+            ("is_v6".to_string(), ip.is_ipv6().to_string()),
+            (
+                "family".to_string(),
+                if ip.is_ipv6() { "v6" } else { "v4" }.to_string(),
+            ),
+            ("zone".to_string(), zone),
+            ("subdomain".to_string(), subdomain),
+        ]))
+    }
+}
+
+impl TemplateContext {
+    /// Render a template by the engine of the name.
+    pub(crate) fn render(&self, template: &str, vars: &HashMap<String, String>) -> Result<String> {
+        match self.engine {
+            TemplateEngine::Strfmt => Ok(template.format(vars)?),
+            TemplateEngine::Jinja => {
+                let mut env = minijinja::Environment::new();
+                // A typo of a variable is an error, as it is in strfmt.
+                env.set_undefined_behavior(UndefinedBehavior::Strict);
+                let mut ctx = vars
+                    .iter()
+                    .map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+                    .collect::<serde_json::Map<_, _>>();
+                // Typed, so `{% if is_v6 %}` works as expected.
+                if let Some(is_v6) = vars.get("is_v6") {
+                    ctx.insert("is_v6".to_string(), (is_v6 == "true").into());
+                }
+                if let Some(ttl) = vars.get("ttl").and_then(|t| t.parse::<u32>().ok()) {
+                    ctx.insert("ttl".to_string(), ttl.into());
+                }
+                Ok(env.render_str(template, ctx)?)
+            }
+        }
+    }
+
+    /// Render all strings in a json value as templates.
+    pub(crate) fn render_json(
+        &self,
+        value: &serde_json::Value,
+        vars: &HashMap<String, String>,
+    ) -> Result<serde_json::Value> {
+        use serde_json::Value;
+
+        Ok(match value {
+            Value::String(s) => Value::String(self.render(s, vars)?),
+            Value::Array(values) => Value::Array(
+                values
+                    .iter()
+                    .map(|v| self.render_json(v, vars))
+                    .collect::<Result<_>>()?,
+            ),
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .map(|(k, v)| Ok((k.clone(), self.render_json(v, vars)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            v => v.clone(),
+        })
+    }
+}
+
+/// Unreserved characters of RFC 3986 are kept, others are percent-encoded.
+const URL_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Percent-encode values of variables, for templates of urls.
+pub(crate) fn url_encoded(vars: &HashMap<String, String>) -> HashMap<String, String> {
+    vars.iter()
+        .map(|(k, v)| (k.clone(), utf8_percent_encode(v, URL_COMPONENT).to_string()))
+        .collect()
+}
+
+#[async_trait]
+pub trait UpdateProvider: Send + Sync {
+    async fn update(&self, name: &str, ip: IpAddr) -> Result<RenewOutcome>;
+
+    /// Create a companion record, it is called after a record is created for the first time.
+    async fn create_record(&self, record: &CompanionRecord) -> Result<()> {
+        bail!(
+            "creating {} record of {} is not supported by this provider",
+            record.record_type,
+            record.name
+        )
+    }
+
+    /// The content of A or AAAA records of a name, it is used by the `Provider` query provider.
+    async fn query_ips(&self, name: &str, is_v6: bool) -> Result<Vec<IpAddr>> {
+        bail!(
+            "querying {} records of {} is not supported by this provider",
+            record_type(is_v6),
+            name
+        )
+    }
+
+    /// Point a name to the target by a CNAME, A and AAAA records of it are deleted.
+    async fn update_cname(&self, name: &str, target: &str) -> Result<RenewOutcome> {
+        bail!(
+            "updating CNAME of {} to {} is not supported by this provider",
+            name,
+            target
+        )
+    }
+
+    /// Delete the A or AAAA records of a name, it is called when the name is disabled or removed
+    /// with `delete_on_disable`.
+    async fn delete(&self, name: &str, is_v6: bool) -> Result<()> {
+        bail!(
+            "deleting {} records of {} is not supported by this provider",
+            record_type(is_v6),
+            name
+        )
+    }
 }
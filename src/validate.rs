@@ -0,0 +1,150 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    config::{Config, NameConf, NotifierConf, UpdateCredential},
+    parse_name_conf,
+    update::{self, find_optional_update_credential, find_update_credential},
+    Args, Tenant,
+};
+
+/// Check the main config, configs of tenants and all name configs without contacting any
+/// service, all problems are printed.
+pub async fn run(args: &Args, config: &Config) -> Result<()> {
+    let problems = check(&args.config, config).await;
+    if problems.is_empty() {
+        println!("config is valid");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    bail!("{} problem(s) found", problems.len())
+}
+
+/// Problems of the main config at `config_path`, configs of its tenants and all name configs.
+pub(crate) async fn check(config_path: &Path, config: &Config) -> Vec<String> {
+    let mut problems = vec![];
+    check_config(config_path, config, &mut problems).await;
+    for (name, path) in config.tenants() {
+        match Tenant::load_config(path) {
+            Ok(tenant_config) => check_config(path, &tenant_config, &mut problems).await,
+            Err(e) => problems.push(format!("tenant {}: {:#}", name, e)),
+        }
+    }
+    problems
+}
+
+async fn check_config(path: &Path, config: &Config, problems: &mut Vec<String>) {
+    let notification = config.notification();
+    for route in notification.routes() {
+        if let Some(notifier) = route.notifier() {
+            if !notification.notifiers().contains_key(notifier) {
+                problems.push(format!(
+                    "{}: notifier of route is not found: {}",
+                    path.display(),
+                    notifier
+                ));
+            }
+        }
+    }
+    for (name, notifier_conf) in notification.notifiers() {
+        if let Err(e) = check_notifier(config, notifier_conf) {
+            problems.push(format!("{}: notifier {}: {:#}", path.display(), name, e));
+        }
+    }
+
+    let mut paths = match config.name_conf_dir().read_dir() {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() && p.extension().filter(|&ext| ext == "toml").is_some())
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            problems.push(format!(
+                "{}: {:?} can't be read: {}",
+                path.display(),
+                config.name_conf_dir(),
+                e
+            ));
+            vec![]
+        }
+    };
+    paths.sort();
+    if let Some(discovery_conf) = config.discovery() {
+        paths.push(discovery_conf.template().clone());
+    }
+    for path in paths {
+        let name_conf = match fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|source| parse_name_conf(&source))
+        {
+            Ok(name_conf) => name_conf,
+            Err(e) => {
+                problems.push(format!("{}: {:#}", path.display(), e));
+                continue;
+            }
+        };
+        for e in check_name_conf(config, &name_conf).await {
+            problems.push(format!("{}: {:#}", path.display(), e));
+        }
+    }
+}
+
+fn check_notifier(config: &Config, notifier_conf: &NotifierConf) -> Result<()> {
+    match notifier_conf {
+        NotifierConf::Ntfy { credential, .. } => {
+            find_optional_update_credential(config, credential)?;
+        }
+        NotifierConf::Gotify { credential, .. } | NotifierConf::Telegram { credential, .. } => {
+            if !matches!(
+                find_update_credential(config, credential)?,
+                UpdateCredential::ApiKey { .. }
+            ) {
+                bail!("only ApiKey credential is supported");
+            }
+        }
+        NotifierConf::Slack { .. } | NotifierConf::Discord { .. } => {}
+    }
+    Ok(())
+}
+
+async fn check_name_conf(config: &Config, name_conf: &NameConf) -> Vec<anyhow::Error> {
+    let mut errors = vec![];
+    // Providers shared from the other family are only checked once.
+    for (name_providers_conf, is_v6) in [(name_conf.v4(), false), (name_conf.v6(), true)] {
+        let Some(name_providers_conf) = name_providers_conf else {
+            continue;
+        };
+        let family = if is_v6 { "v6" } else { "v4" };
+        for update_provider_type in name_providers_conf.update_provider_type().as_slice() {
+            if let Err(e) =
+                update::check_update_provider(update_provider_type, config, name_conf, is_v6).await
+            {
+                errors.push(e.context(format!("invalid update provider of {}", family)));
+            }
+        }
+    }
+
+    for (kind, hook_conf) in [
+        ("pre_update", name_conf.pre_update()),
+        ("post_update", name_conf.post_update()),
+    ] {
+        if hook_conf.as_ref().is_some_and(|h| h.command().is_empty()) {
+            errors.push(anyhow!("command of {} hook is empty", kind));
+        }
+    }
+
+    let vars = HashMap::from([
+        ("name".to_string(), name_conf.name().as_str()),
+        ("ip".to_string(), "192.0.2.1"),
+    ]);
+    for conf in name_conf.companion_records() {
+        for template in [conf.name().as_deref().unwrap_or("{name}"), conf.content()] {
+            if let Err(e) = strfmt::strfmt(template, &vars) {
+                errors.push(anyhow!(e).context("invalid template of companion record"));
+            }
+        }
+    }
+    errors
+}